@@ -0,0 +1,10 @@
+def main():
+    token = "public"
+    # <( BEGIN Agent_007 )>
+    token = "secret"
+    if token:
+        # <( BEGIN GEHEIM )>
+        print(token)
+        # <( END GEHEIM )>
+    # <( END Agent_007 )>
+    return token
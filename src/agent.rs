@@ -0,0 +1,547 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	a credential agent keeping passwords and derived keys in memory, so that
+//	separate invocations (like git filters) do not ask or derive again
+
+use std::collections::BTreeMap;
+use std::env;
+#[cfg(unix)]
+use std::fs::{self, DirBuilder};
+#[cfg(unix)]
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use std::process::{Command, Stdio};
+#[cfg(unix)]
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::{Duration, Instant};
+
+use consts;
+use edit;
+use error::{Error, Result};
+use etree::PBKDFOptions;
+use pbkdf::PBKDFCacheEntry;
+
+#[cfg(unix)]
+fn io_error(path: &Path, e: io::Error) -> Error {
+    Error::Io {
+        path: path.to_path_buf(),
+        source: e,
+    }
+}
+
+// The protocol is one request line per connection, answered by one line.
+// Words are separated by spaces, and secrets are hex encoded:
+//
+//   password WORD                             -> ok PASSWORD | none
+//   add-password WORD PASSWORD                -> ok
+//   key PASSWORD ALG KEYLEN MSEC PARAMS SALT  -> ok SALT PARAMS KEY | none
+//   add-key PASSWORD ALG MSEC PARAMS SALT KEY -> ok
+//   lock PASSPHRASE | unlock PASSPHRASE       -> ok
+//   forget [WORD...] | stop                   -> ok
+//   status                                    -> ok locked|unlocked KEYS WORD...
+//
+// with "-" for a missing MSEC, PARAMS or SALT. Any request may be answered
+// with "locked" or "error MESSAGE".
+
+// an agent to talk to, by its socket
+#[derive(Clone, Debug)]
+pub struct Agent {
+    pub socket: PathBuf,
+}
+
+fn optional(value: &Option<String>) -> &str {
+    value.as_ref().map_or("-", |value| value.as_str())
+}
+
+fn format_params(params: &BTreeMap<String, usize>) -> String {
+    params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn parse_params(params: &str) -> Option<BTreeMap<String, usize>> {
+    params
+        .split(',')
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let mut it = param.splitn(2, '=');
+            let name = it.next()?.to_string();
+            let value = it.next()?.parse().ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn parse_optional<T, F: Fn(&str) -> Option<T>>(value: &str, parse: F) -> Option<Option<T>> {
+    match value {
+        "-" => Some(None),
+        value => parse(value).map(Some),
+    }
+}
+
+fn decode_string(value: &str) -> Option<String> {
+    String::from_utf8(hex::decode(value).ok()?).ok()
+}
+
+impl Agent {
+    // the agent named by ENPROT_AGENT_SOCK, if it is set
+    pub fn from_env() -> Option<Agent> {
+        env::var_os(consts::AGENT_SOCKET_ENV)
+            .filter(|socket| !socket.is_empty())
+            .map(|socket| Agent {
+                socket: PathBuf::from(socket),
+            })
+    }
+
+    // the words of the answer, or None for "none"
+    #[cfg(unix)]
+    fn request(&self, request: &[&str]) -> Result<Option<Vec<String>>> {
+        let error = |e: io::Error| Error::Agent(format!("{}: {}", self.socket.display(), e));
+        let mut stream = UnixStream::connect(&self.socket).map_err(error)?;
+        stream
+            .write_all(format!("{}\n", request.join(" ")).as_bytes())
+            .map_err(error)?;
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).map_err(error)?;
+        let mut words = line
+            .trim_end_matches('\n')
+            .split(' ')
+            .map(|word| word.to_string());
+        match words.next().as_deref() {
+            Some("ok") => Ok(Some(words.collect())),
+            Some("none") => Ok(None),
+            Some("locked") => Err(Error::Agent("locked".to_string())),
+            Some("error") => Err(Error::Agent(words.collect::<Vec<String>>().join(" "))),
+            _ => Err(Error::Agent("invalid answer".to_string())),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn request(&self, _request: &[&str]) -> Result<Option<Vec<String>>> {
+        Err(Error::Agent("not supported on this platform".to_string()))
+    }
+
+    // While processing files the agent is an optional cache: when it
+    // fails, the password is asked for or the key derived as without it.
+
+    pub fn password(&self, name: &str) -> Option<String> {
+        let answer = self.request(&["password", name]).ok()??;
+        decode_string(answer.first()?)
+    }
+
+    pub fn add_password(&self, name: &str, password: &str) -> Result<()> {
+        if password.is_empty() {
+            return Err(Error::Agent("empty password".to_string()));
+        }
+        self.request(&["add-password", name, &hex::encode(password)])
+            .map(|_| ())
+    }
+
+    pub fn key(
+        &self,
+        password: &str,
+        key_len: usize,
+        opts: &PBKDFOptions,
+    ) -> Option<PBKDFCacheEntry> {
+        let answer = self
+            .request(&[
+                "key",
+                &hex::encode(password),
+                &opts.alg,
+                &key_len.to_string(),
+                optional(&opts.msec.map(|msec| msec.to_string())),
+                optional(&opts.params.as_ref().map(format_params)),
+                optional(&opts.salt.as_ref().map(hex::encode)),
+            ])
+            .ok()??;
+        if answer.len() != 3 {
+            return None;
+        }
+        Some(PBKDFCacheEntry {
+            password: password.to_string(),
+            alg: opts.alg.clone(),
+            msec: opts.msec.filter(|_| opts.params.is_none()).unwrap_or(0),
+            salt: hex::decode(&answer[0]).ok()?,
+            params: parse_params(&answer[1])?,
+            key: hex::decode(&answer[2]).ok()?,
+        })
+    }
+
+    pub fn add_key(&self, entry: &PBKDFCacheEntry) -> Result<()> {
+        self.request(&[
+            "add-key",
+            &hex::encode(&entry.password),
+            &entry.alg,
+            &entry.msec.to_string(),
+            &format_params(&entry.params),
+            &hex::encode(&entry.salt),
+            &hex::encode(&entry.key),
+        ])
+        .map(|_| ())
+    }
+
+    // the commands of "enprot agent"
+
+    pub fn lock(&self, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(Error::Agent("empty password".to_string()));
+        }
+        self.request(&["lock", &hex::encode(passphrase)])
+            .map(|_| ())
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<()> {
+        self.request(&["unlock", &hex::encode(passphrase)])
+            .map(|_| ())
+    }
+
+    // forget the passwords of words and the keys derived from them, or
+    // everything
+    pub fn forget(&self, words: &[&str]) -> Result<()> {
+        let mut request = vec!["forget"];
+        request.extend(words);
+        self.request(&request).map(|_| ())
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        self.request(&["stop"]).map(|_| ())
+    }
+
+    // whether it is locked, how many keys it has, and the words it has
+    // passwords for
+    pub fn status(&self) -> Result<(bool, usize, Vec<String>)> {
+        let answer = self.request(&["status"])?.unwrap_or_default();
+        match (
+            answer.first(),
+            answer.get(1).and_then(|keys| keys.parse().ok()),
+        ) {
+            (Some(state), Some(keys)) => Ok((state == "locked", keys, answer[2..].to_vec())),
+            _ => Err(Error::Agent("invalid answer".to_string())),
+        }
+    }
+}
+
+// a new socket in a private directory of its own, like ssh-agent's
+pub fn new_socket(rng: &Option<botan::RandomNumberGenerator>) -> Result<PathBuf> {
+    let random = rng
+        .as_ref()
+        .ok_or(Error::Crypto("Missing RNG"))?
+        .read(8)
+        .map_err(|_| Error::Crypto("RNG error"))?;
+    Ok(edit::temp_dir()
+        .join(format!("enprot-agent-{}", hex::encode(random)))
+        .join("agent.sock"))
+}
+
+#[cfg(unix)]
+fn socket_dir(socket: &Path) -> &Path {
+    match socket.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+// whether an agent can listen on socket: its directory, if it exists, must
+// not be accessible by anyone else, and no agent may be listening on it
+#[cfg(unix)]
+fn check_socket(socket: &Path) -> Result<()> {
+    let dir = socket_dir(socket);
+    if dir.exists() {
+        let mode = fs::metadata(dir)
+            .map_err(|e| io_error(dir, e))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(Error::Agent(format!(
+                "{} is accessible by other users",
+                dir.display()
+            )));
+        }
+    }
+    if UnixStream::connect(socket).is_ok() {
+        return Err(Error::Agent(format!(
+            "{}: already running",
+            socket.display()
+        )));
+    }
+    Ok(())
+}
+
+// run "exe agent serve" in the background and wait until it listens. Its
+// errors go nowhere, so the usual ones are checked here first.
+#[cfg(unix)]
+pub fn start(exe: &Path, socket: &Path, timeout: u64) -> Result<()> {
+    check_socket(socket)?;
+    let mut child = Command::new(exe)
+        .arg("agent")
+        .arg("serve")
+        .arg("--socket")
+        .arg(socket)
+        .arg("--timeout")
+        .arg(timeout.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| io_error(exe, e))?;
+    for _ in 0..200 {
+        if UnixStream::connect(socket).is_ok() {
+            return Ok(());
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(Error::Agent(format!("{}", status)));
+        }
+        thread::sleep(Duration::from_millis(25));
+    }
+    Err(Error::Agent(format!("{}: not listening", socket.display())))
+}
+
+#[cfg(not(unix))]
+pub fn start(_exe: &Path, _socket: &Path, _timeout: u64) -> Result<()> {
+    Err(Error::Agent("not supported on this platform".to_string()))
+}
+
+#[cfg(unix)]
+struct Expiring<T> {
+    value: T,
+    added: Instant,
+}
+
+#[cfg(unix)]
+impl<T> Expiring<T> {
+    fn new(value: T) -> Expiring<T> {
+        Expiring {
+            value,
+            added: Instant::now(),
+        }
+    }
+}
+
+// what the agent holds, in memory only
+#[cfg(unix)]
+struct State {
+    passwords: BTreeMap<String, Expiring<String>>,
+    keys: Vec<Expiring<PBKDFCacheEntry>>,
+    passphrase: Option<String>, // set while locked
+    timeout: Option<Duration>,
+}
+
+#[cfg(unix)]
+impl State {
+    fn expire(&mut self) {
+        if let Some(timeout) = self.timeout {
+            self.passwords
+                .retain(|_, pass| pass.added.elapsed() < timeout);
+            self.keys.retain(|entry| entry.added.elapsed() < timeout);
+        }
+    }
+
+    fn forget(&mut self, words: &[&str]) {
+        if words.is_empty() {
+            self.passwords.clear();
+            self.keys.clear();
+            return;
+        }
+        for word in words {
+            if let Some(pass) = self.passwords.remove(*word) {
+                self.keys.retain(|entry| entry.value.password != pass.value);
+            }
+        }
+    }
+
+    fn answer(&mut self, request: &[&str]) -> Option<String> {
+        self.expire();
+        let locked = self.passphrase.is_some();
+        Some(match *request {
+            ["password", _] | ["key", ..] | ["add-password", ..] | ["add-key", ..] if locked => {
+                "locked".to_string()
+            }
+            ["password", name] => match self.passwords.get(name) {
+                Some(pass) => format!("ok {}", hex::encode(&pass.value)),
+                None => "none".to_string(),
+            },
+            ["add-password", name, pass] => {
+                self.passwords
+                    .insert(name.to_string(), Expiring::new(decode_string(pass)?));
+                "ok".to_string()
+            }
+            ["key", pass, alg, key_len, msec, params, salt] => {
+                let password = decode_string(pass)?;
+                let key_len = key_len.parse().ok()?;
+                let opts = PBKDFOptions {
+                    alg: alg.to_string(),
+                    saltlen: 0,
+                    salt: parse_optional(salt, |salt| hex::decode(salt).ok())?,
+                    msec: parse_optional(msec, |msec| msec.parse().ok())?,
+                    params: parse_optional(params, parse_params)?,
+                };
+                match self
+                    .keys
+                    .iter()
+                    .find(|entry| entry.value.matches(&password, key_len, &opts))
+                {
+                    Some(entry) => format!(
+                        "ok {} {} {}",
+                        hex::encode(&entry.value.salt),
+                        format_params(&entry.value.params),
+                        hex::encode(&entry.value.key)
+                    ),
+                    None => "none".to_string(),
+                }
+            }
+            ["add-key", pass, alg, msec, params, salt, key] => {
+                self.keys.push(Expiring::new(PBKDFCacheEntry {
+                    password: decode_string(pass)?,
+                    alg: alg.to_string(),
+                    msec: msec.parse().ok()?,
+                    params: parse_params(params)?,
+                    salt: hex::decode(salt).ok()?,
+                    key: hex::decode(key).ok()?,
+                }));
+                "ok".to_string()
+            }
+            ["lock", passphrase] => {
+                if locked {
+                    return Some("error already locked".to_string());
+                }
+                self.passphrase = Some(decode_string(passphrase)?);
+                "ok".to_string()
+            }
+            ["unlock", passphrase] => {
+                if !locked {
+                    return Some("error not locked".to_string());
+                }
+                if self.passphrase != Some(decode_string(passphrase)?) {
+                    return Some("error wrong passphrase".to_string());
+                }
+                self.passphrase = None;
+                "ok".to_string()
+            }
+            ["forget", ref words @ ..] => {
+                self.forget(words);
+                "ok".to_string()
+            }
+            ["status"] => {
+                let state = if locked { "locked" } else { "unlocked" };
+                let mut answer = format!("ok {} {}", state, self.keys.len());
+                for name in self.passwords.keys() {
+                    answer += " ";
+                    answer += name;
+                }
+                answer
+            }
+            _ => return None,
+        })
+    }
+}
+
+// answer one request, returning whether to stop
+#[cfg(unix)]
+fn serve_client(stream: UnixStream, state: &Mutex<State>) -> io::Result<bool> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request = line
+        .trim_end_matches('\n')
+        .split(' ')
+        .collect::<Vec<&str>>();
+    let stop = request == ["stop"];
+    let answer = if stop {
+        "ok".to_string()
+    } else {
+        state
+            .lock()
+            .unwrap()
+            .answer(&request)
+            .unwrap_or_else(|| "error invalid request".to_string())
+    };
+    (&stream).write_all(format!("{}\n", answer).as_bytes())?;
+    Ok(stop)
+}
+
+// listen on socket until stopped, forgetting passwords and keys after
+// timeout seconds (0 for never). The socket is only accessible to us, in
+// a directory that no one else may access.
+#[cfg(unix)]
+pub fn serve(socket: &Path, timeout: u64) -> Result<()> {
+    let dir = socket_dir(socket);
+    let created = !dir.exists();
+    if created {
+        DirBuilder::new()
+            .mode(0o700)
+            .create(dir)
+            .map_err(|e| io_error(dir, e))?;
+    }
+    check_socket(socket)?;
+    // left over from an agent that did not stop
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket).map_err(|e| io_error(socket, e))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .map_err(|e| io_error(socket, e))?;
+
+    let state = Arc::new(Mutex::new(State {
+        passwords: BTreeMap::new(),
+        keys: Vec::new(),
+        passphrase: None,
+        timeout: Some(Duration::from_secs(timeout)).filter(|_| timeout != 0),
+    }));
+    if timeout != 0 {
+        let state = state.clone();
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            state.lock().unwrap().expire();
+        });
+    }
+    for stream in listener.incoming() {
+        // a client that fails only fails itself
+        match stream.map(|stream| serve_client(stream, &state)) {
+            Ok(Ok(true)) => break,
+            _ => continue,
+        }
+    }
+    let _ = fs::remove_file(socket);
+    if created {
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_socket: &Path, _timeout: u64) -> Result<()> {
+    Err(Error::Agent("not supported on this platform".to_string()))
+}
@@ -0,0 +1,119 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	replacing files atomically, with an optional backup
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// tells apart the temporary files of the workers of --jobs
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// path with suffix appended to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path
+        .file_name()
+        .map_or(OsString::new(), |name| name.to_owned());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// write data to path through a temporary file in the same directory, which
+// is synced and renamed over it, so that path always has either its old or
+// its new contents. The new file gets the permissions and, as far as we are
+// allowed to, the owner of the old one. With backup, the old file is kept as
+// path followed by that suffix.
+pub fn write(path: &Path, data: &[u8], backup: Option<&str>) -> io::Result<()> {
+    replace(path, data, backup, false)
+}
+
+// like write, but a file that did not exist is created readable only by us
+pub fn write_private(path: &Path, data: &[u8]) -> io::Result<()> {
+    replace(path, data, None, true)
+}
+
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn replace(path: &Path, data: &[u8], backup: Option<&str>, private: bool) -> io::Result<()> {
+    // the file a symbolic link points to is replaced, not the link
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let old = fs::metadata(&path).ok();
+    let name = path
+        .file_name()
+        .map_or("".into(), |name| name.to_string_lossy());
+    let temp = path.with_file_name(format!(
+        ".{}.enprot-{}-{}",
+        name,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let result = (|| {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        // only we can read it until it has the permissions of the old file
+        #[cfg(unix)]
+        {
+            if old.is_some() || private {
+                options.mode(0o600);
+            }
+        }
+        let mut file = options.open(&temp)?;
+        file.write_all(data)?;
+        if let Some(old) = old.as_ref() {
+            // only root may give files to others, which is no reason to fail
+            #[cfg(unix)]
+            let _ = std::os::unix::fs::chown(&temp, Some(old.uid()), Some(old.gid()));
+            file.set_permissions(old.permissions())?;
+        }
+        file.sync_all()?;
+
+        if let (Some(suffix), Some(_)) = (backup, old.as_ref()) {
+            let backup = with_suffix(&path, suffix);
+            if let Err(e) = fs::remove_file(&backup) {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e);
+                }
+            }
+            fs::hard_link(&path, &backup).or_else(|_| fs::copy(&path, &backup).map(|_| ()))?;
+        }
+        fs::rename(&temp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+        return result;
+    }
+
+    // and the rename itself survives a crash
+    #[cfg(unix)]
+    {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            let _ = File::open(dir).and_then(|dir| dir.sync_all());
+        }
+    }
+    Ok(())
+}
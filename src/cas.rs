@@ -24,95 +24,97 @@
 //	content addressed storage
 
 use etree::ParseOps;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
+use std::sync::Mutex;
 
+use atomic;
 use crypto;
+use error::{Error, Result};
 
-pub fn load(hexhash: &str, paops: &mut ParseOps) -> Result<Vec<u8>, &'static str> {
+// held while an object is read or written, so that the workers of --jobs
+// neither write the same object at once nor read one being written
+static LOCK: Mutex<()> = Mutex::new(());
+
+pub fn load(hexhash: &str, paops: &mut ParseOps) -> Result<Vec<u8>> {
     // check that it is valid
     if let Err(_) = hex::decode(hexhash) {
-        eprintln!("Not a valid hex token for CAS: {}", hexhash);
-        return Err("CAS hex token invalid");
+        return Err(Error::Format("Not a valid hex token for CAS"));
     };
 
     let mut path = paops.casdir.clone();
     path.push(&hexhash);
 
     // open input file
+    let lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
     let mut file_in = match File::open(&path) {
         Ok(file_in) => file_in,
-        Err(e) => {
-            eprintln!("Failed to open {} for reading: {}", path.display(), e);
-            return Err("CAS file error");
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::CasMissing {
+                hash: hexhash.to_string(),
+                path: paops.casdir.clone(),
+            });
         }
+        Err(e) => return Err(Error::Io { path, source: e }),
     };
 
     let mut blob = Vec::new();
     match file_in.read_to_end(&mut blob) {
         Ok(bytes) => {
             if paops.verbose {
-                eprintln!("cas::load(): {} bytes from {}", bytes, path.display());
+                let message = format!("cas::load(): {} bytes from {}", bytes, path.display());
+                paops.log.push(message);
             }
         }
-        Err(e) => {
-            eprintln!("Error reading {}: {}", path.display(), e);
-            return Err("CAS read error");
-        }
+        Err(e) => return Err(Error::Io { path, source: e }),
     }
+    drop(lock);
 
     // verify hash just because
     let verify = crypto::hexdigest("sha3-256", &blob, &paops.policy)?;
 
     if hexhash != verify {
-        eprintln!(
-            "CONTENT HASH MISMATCH!\ninput = {}\ncheck = {}",
-            hexhash, verify
-        );
-        return Err("CAS verification error");
+        return Err(Error::CasMismatch {
+            expected: hexhash.to_string(),
+            actual: verify,
+        });
     }
 
     Ok(blob)
 }
 
-pub fn save(blob: Vec<u8>, paops: &mut ParseOps) -> Result<String, &'static str> {
+pub fn save(blob: Vec<u8>, paops: &mut ParseOps) -> Result<String> {
     let hexhash = crypto::hexdigest("sha3-256", &blob, &paops.policy)?;
     let mut path = paops.casdir.clone();
     path.push(&hexhash);
 
     // check if it exists
+    let _lock = LOCK.lock().unwrap_or_else(|e| e.into_inner());
     if path.is_file() {
         if paops.verbose {
-            eprintln!("cas:save(): {} already exists. Exiting.", path.display());
+            let message = format!("cas:save(): {} already exists. Exiting.", path.display());
+            paops.log.push(message);
         }
         return Ok(hexhash);
     }
+    if paops.dry_run {
+        return Ok(hexhash);
+    }
 
-    // open output file
-    let mut file_out = match File::create(&path) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Failed to open {} for writing: {}", path.display(), e);
-            return Err("CAS create error");
-        }
-    };
-
-    // write it
-    match file_out.write(&blob) {
-        Ok(bytes) => {
-            if paops.verbose {
-                eprintln!("cas:save(): {} bytes to {}", bytes, path.display());
-            }
-        }
-        Err(e) => {
-            eprintln!(
-                "Error writing {} bytes to {}: {}",
-                blob.len(),
-                path.display(),
-                e
-            );
-            return Err("CAS write error");
-        }
+    // write it, so that no object is ever half written, creating the CAS
+    // directory of a fresh checkout
+    if let Err(e) = fs::create_dir_all(&paops.casdir) {
+        return Err(Error::Io {
+            path: paops.casdir.clone(),
+            source: e,
+        });
+    }
+    if let Err(e) = atomic::write(&path, &blob, None) {
+        return Err(Error::Io { path, source: e });
+    }
+    if paops.verbose {
+        let message = format!("cas:save(): {} bytes to {}", blob.len(), path.display());
+        paops.log.push(message);
     }
 
     Ok(hexhash)
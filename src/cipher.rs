@@ -34,6 +34,7 @@ use phf::phf_map;
 use std::marker::PhantomData;
 
 use crypto;
+use error::{Error, Result};
 
 pub static BOTAN_CIPHER_ALG_MAP: phf::Map<&'static str, &'static str> = phf_map! {
     "aes-256-siv" => "AES-256/SIV",
@@ -45,13 +46,7 @@ pub trait SymmetricCipher {
     fn nonce_len(&self) -> usize;
     fn key_len_min(&self) -> usize;
     fn key_len_max(&self) -> usize;
-    fn _process(
-        &self,
-        key: &[u8],
-        iv: &[u8],
-        ad: &[u8],
-        data: &[u8],
-    ) -> Result<Vec<u8>, &'static str>;
+    fn _process(&self, key: &[u8], iv: &[u8], ad: &[u8], data: &[u8]) -> Result<Vec<u8>>;
 
     fn process(
         &self,
@@ -60,16 +55,16 @@ pub trait SymmetricCipher {
         ad: &[u8],
         data: &[u8],
         policy: &Box<dyn crypto::CryptoPolicy>,
-    ) -> Result<Vec<u8>, &'static str> {
+    ) -> Result<Vec<u8>> {
         policy.check_cipher(self.alg(), key, iv, ad)?;
         self._process(key, iv, ad, data)
     }
 }
 
-fn to_botan_cipher(alg: &str) -> Result<&'static str, &'static str> {
+fn to_botan_cipher(alg: &str) -> Result<&'static str> {
     Ok(BOTAN_CIPHER_ALG_MAP
         .get::<str>(alg)
-        .ok_or("Unrecognized cipher")?)
+        .ok_or(Error::Crypto("Unrecognized cipher"))?)
 }
 
 struct BotanCipher {
@@ -77,21 +72,23 @@ struct BotanCipher {
     nonce_len: usize,
     key_len_min: usize,
     key_len_max: usize,
+    direction: CipherDirection,
     obj: botan::Cipher,
 }
 
 impl BotanCipher {
-    fn create(alg: &str, direction: CipherDirection) -> Result<Self, &'static str> {
+    fn create(alg: &str, direction: CipherDirection) -> Result<Self> {
         let obj = botan::Cipher::new(to_botan_cipher(alg)?, direction)
-            .map_err(|_| "Botan error creating cipher")?;
+            .map_err(|_| Error::Crypto("Botan error creating cipher"))?;
         let keyspec = obj
             .key_spec()
-            .map_err(|_| "Botan error retrieving key spec")?;
+            .map_err(|_| Error::Crypto("Botan error retrieving key spec"))?;
         Ok(BotanCipher {
             alg: alg.to_string(),
             nonce_len: obj.default_nonce_length(),
             key_len_min: keyspec.minimum_keylength(),
             key_len_max: keyspec.maximum_keylength(),
+            direction,
             obj,
         })
     }
@@ -114,22 +111,19 @@ impl SymmetricCipher for BotanCipher {
         self.key_len_max
     }
 
-    fn _process(
-        &self,
-        key: &[u8],
-        iv: &[u8],
-        ad: &[u8],
-        data: &[u8],
-    ) -> Result<Vec<u8>, &'static str> {
+    fn _process(&self, key: &[u8], iv: &[u8], ad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
         self.obj
             .set_key(key)
-            .map_err(|_| "Botan error setting cipher key")?;
+            .map_err(|_| Error::Crypto("Botan error setting cipher key"))?;
         self.obj
             .set_associated_data(ad)
-            .map_err(|_| "Botan error setting AD")?;
+            .map_err(|_| Error::Crypto("Botan error setting AD"))?;
         self.obj
             .process(iv, data)
-            .map_err(|_| "Botan error processing cipher data")
+            .map_err(|_| match self.direction {
+                CipherDirection::Encrypt => Error::Crypto("Botan error processing cipher data"),
+                CipherDirection::Decrypt => Error::Auth,
+            })
     }
 }
 
@@ -149,7 +143,7 @@ impl<C> AESGCMSIVCipher<C>
 where
     C: BlockCipher<BlockSize = U16, ParBlocks = U8>,
 {
-    fn create(direction: CipherDirection) -> Result<Self, &'static str> {
+    fn create(direction: CipherDirection) -> Result<Self> {
         Ok(AESGCMSIVCipher {
             alg: format!("aes-{}-gcm-siv", C::KeySize::to_usize() * 8),
             nonce_len: <AesGcmSiv<C> as Aead>::NonceSize::to_usize(),
@@ -181,13 +175,7 @@ where
         self.key_len_max
     }
 
-    fn _process(
-        &self,
-        key: &[u8],
-        iv: &[u8],
-        ad: &[u8],
-        data: &[u8],
-    ) -> Result<Vec<u8>, &'static str> {
+    fn _process(&self, key: &[u8], iv: &[u8], ad: &[u8], data: &[u8]) -> Result<Vec<u8>> {
         let obj = AesGcmSiv::<C>::new(GenericArray::clone_from_slice(key));
         match self.direction {
             CipherDirection::Encrypt => obj
@@ -195,32 +183,75 @@ where
                     GenericArray::from_slice(iv),
                     aead::Payload { msg: data, aad: ad },
                 )
-                .map_err(|_| "Failed to encrypt"),
+                .map_err(|_| Error::Crypto("Failed to encrypt")),
             CipherDirection::Decrypt => obj
                 .decrypt(
                     GenericArray::from_slice(iv),
                     aead::Payload { msg: data, aad: ad },
                 )
-                .map_err(|_| "Failed to decrypt"),
+                .map_err(|_| Error::Auth),
         }
     }
 }
 
-fn create(alg: &str, direction: CipherDirection) -> Result<Box<dyn SymmetricCipher>, &'static str> {
+fn create(alg: &str, direction: CipherDirection) -> Result<Box<dyn SymmetricCipher>> {
     match alg {
         "aes-256-gcm-siv" => Ok(Box::new(AESGCMSIVCipher::<Aes256>::create(direction)?)),
         _ => Ok(Box::new(BotanCipher::create(alg, direction)?)),
     }
 }
 
-pub fn encryption(alg: &str) -> Result<Box<dyn SymmetricCipher>, &'static str> {
+pub fn encryption(alg: &str) -> Result<Box<dyn SymmetricCipher>> {
     create(alg, CipherDirection::Encrypt)
 }
 
-pub fn decryption(alg: &str) -> Result<Box<dyn SymmetricCipher>, &'static str> {
+pub fn decryption(alg: &str) -> Result<Box<dyn SymmetricCipher>> {
     create(alg, CipherDirection::Decrypt)
 }
 
+// Key wrapping
+//
+// The nonce is random and stored in front of the wrapped key, since a key
+// derived from a password may be used to wrap several keys.
+
+const WRAP_CIPHER_ALG: &str = "aes-256-gcm";
+// associated data for wrapped keys, so they are never mistaken for segments
+const WRAP_AD: &[u8] = b"enprot-wrap-v1";
+
+pub fn wrap_key_len() -> Result<usize> {
+    Ok(encryption(WRAP_CIPHER_ALG)?.key_len_max())
+}
+
+pub fn wrap_key(
+    kek: &[u8],
+    key: &[u8],
+    rng: &Option<botan::RandomNumberGenerator>,
+    policy: &Box<dyn crypto::CryptoPolicy>,
+) -> Result<Vec<u8>> {
+    let enc = encryption(WRAP_CIPHER_ALG)?;
+    let mut wrapped = rng
+        .as_ref()
+        .ok_or(Error::Crypto("Missing RNG"))?
+        .read(enc.nonce_len())
+        .map_err(|_| Error::Crypto("RNG error"))?;
+    let ct = enc.process(kek, &wrapped, WRAP_AD, key, policy)?;
+    wrapped.extend(ct);
+    Ok(wrapped)
+}
+
+pub fn unwrap_key(
+    kek: &[u8],
+    wrapped: &[u8],
+    policy: &Box<dyn crypto::CryptoPolicy>,
+) -> Result<Vec<u8>> {
+    let dec = decryption(WRAP_CIPHER_ALG)?;
+    if wrapped.len() < dec.nonce_len() {
+        return Err(Error::Format("Wrapped key too short"));
+    }
+    let (iv, ct) = wrapped.split_at(dec.nonce_len());
+    dec.process(kek, iv, WRAP_AD, ct, policy)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,277 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	project configuration
+
+use glob::{MatchOptions, Pattern};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use consts;
+use error::{Error, Result};
+use filetype::FileTypes;
+use gitfilter;
+use keysource::KeySource;
+
+pub const CONFIG_FILE: &str = ".enprot.toml";
+
+// prefix referring to a keyword group in keyword arguments
+pub const GROUP_PREFIX: char = '@';
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub casdir: Option<PathBuf>, // relative to the configuration file
+    pub policy: Option<String>,
+    pub max_depth: Option<usize>,
+    pub pbkdf: Option<String>,
+    pub pbkdf_msec: Option<u32>,
+    pub pbkdf_salt_len: Option<usize>,
+    pub cipher: Option<String>,
+    pub bind_ad: Option<bool>,
+    pub keyring: Option<String>,
+    #[serde(default)]
+    pub filetypes: BTreeMap<String, Separators>, // separators by extension
+    #[serde(default)]
+    pub separators: Vec<GlobSeparators>, // separators by path, first match wins
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>, // named lists of keywords
+    #[serde(default)]
+    pub protect: Vec<String>, // keywords that must not appear in plaintext
+    #[serde(default)]
+    pub keys: BTreeMap<String, KeySource>, // where passwords are read from
+    #[serde(default)]
+    pub include: Vec<String>, // globs of the files -R takes, all if empty
+    #[serde(default)]
+    pub exclude: Vec<String>, // globs of the files and directories -R skips
+    #[serde(skip)]
+    pub path: PathBuf, // the file this was loaded from
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Separators {
+    pub left: String,
+    pub right: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlobSeparators {
+    pub glob: String,
+    pub left: String,
+    pub right: String,
+}
+
+// accept a value only if it is one of valid
+fn check_value(path: &Path, name: &str, value: &Option<String>, valid: &[&str]) -> Result<()> {
+    match value {
+        Some(value) if !valid.contains(&value.as_str()) => Err(Error::Config {
+            path: path.to_path_buf(),
+            message: format!("invalid {} '{}'", name, value),
+        }),
+        _ => Ok(()),
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config> {
+        let contents = fs::read_to_string(path).map_err(|e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        let mut config: Config = toml::from_str(&contents).map_err(|e| Error::Config {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })?;
+        config.path = path.to_path_buf();
+        config.validate()?;
+        Ok(config)
+    }
+
+    // the nearest configuration file in dir or one of its parents
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .find(|path| path.is_file())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let error = |message: String| Error::Config {
+            path: self.path.clone(),
+            message,
+        };
+        check_value(&self.path, "policy", &self.policy, consts::VALID_POLICIES)?;
+        check_value(&self.path, "pbkdf", &self.pbkdf, consts::VALID_PBKDF_ALGS)?;
+        check_value(
+            &self.path,
+            "cipher",
+            &self.cipher,
+            consts::VALID_CIPHER_ALGS,
+        )?;
+        check_value(&self.path, "keyring", &self.keyring, consts::VALID_KEYRINGS)?;
+        if self.pbkdf_msec == Some(0) || self.pbkdf_salt_len == Some(0) {
+            return Err(error("PBKDF settings must be greater than 0".to_string()));
+        }
+        // a missing casdir is created by the first store
+        if let Some(casdir) = self.casdir() {
+            if casdir.exists() && !casdir.is_dir() {
+                return Err(error(format!(
+                    "casdir '{}' is not a directory",
+                    casdir.display()
+                )));
+            }
+        }
+        // commands, file descriptors, files outside the project (also by
+        // symbolic links) and most environment variables are taken only
+        // from configurations the user trusts
+        if let Some(name) = self
+            .keys
+            .iter()
+            .find(|(_, source)| !source.is_confined(self.dir()))
+            .map(|(name, _)| name)
+        {
+            if !self.trusted() {
+                return Err(error(format!(
+                    "the password source of {} needs a trusted configuration \
+                     (git config enprot.trustConfig true)",
+                    name
+                )));
+            }
+        }
+        let globs = self.separators.iter().map(|seps| &seps.glob);
+        for glob in globs.chain(&self.include).chain(&self.exclude) {
+            Pattern::new(glob).map_err(|e| error(format!("invalid glob '{}': {}", glob, e)))?;
+        }
+        Ok(())
+    }
+
+    // a configuration of the user, in the home directory, or of a
+    // repository whose git configuration trusts it
+    fn trusted(&self) -> bool {
+        let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let dir = canonical(self.dir());
+        env::var_os("HOME").map(|home| canonical(Path::new(&home))) == Some(dir.clone())
+            || gitfilter::trusts_config(&dir)
+    }
+
+    // the directory holding the configuration file
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new(""))
+    }
+
+    pub fn casdir(&self) -> Option<PathBuf> {
+        self.casdir.as_ref().map(|casdir| self.dir().join(casdir))
+    }
+
+    // password sources by keyword, with files relative to the configuration
+    pub fn key_sources(&self) -> impl Iterator<Item = (&String, KeySource)> {
+        self.keys
+            .iter()
+            .map(move |(name, source)| (name, source.relative_to(self.dir())))
+    }
+
+    // the built-in file types with the configured ones added
+    pub fn filetypes(&self) -> FileTypes {
+        let mut filetypes = FileTypes::new();
+        for (ext, seps) in &self.filetypes {
+            filetypes.insert(ext, &seps.left, &seps.right);
+        }
+        filetypes
+    }
+
+    // separators for the first glob matching path, which is relative to
+    // the configuration file; globs without a '/' match the file name only
+    pub fn separators(&self, path: &Path) -> Option<(String, String)> {
+        let opts = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let name = Path::new(path.file_name()?);
+        self.separators
+            .iter()
+            .find(|seps| {
+                let pattern = Pattern::new(&seps.glob).unwrap();
+                if seps.glob.contains('/') {
+                    pattern.matches_path_with(path, opts)
+                } else {
+                    pattern.matches_path_with(name, opts)
+                }
+            })
+            .map(|seps| (seps.left.clone(), seps.right.clone()))
+    }
+}
+
+// keywords with group references like "@name" replaced by the keywords of
+// the group defined in config
+pub fn expand_groups(config: Option<&Config>, keywords: &[String]) -> Result<Vec<String>> {
+    let mut expanded = Vec::new();
+    for keyword in keywords {
+        if !keyword.starts_with(GROUP_PREFIX) {
+            expanded.push(keyword.clone());
+            continue;
+        }
+        let config = config.ok_or(Error::Operation("Keyword groups need a configuration file"))?;
+        let name = &keyword[GROUP_PREFIX.len_utf8()..];
+        let group = config.groups.get(name).ok_or_else(|| Error::Config {
+            path: config.path.clone(),
+            message: format!("unknown keyword group '{}'", name),
+        })?;
+        expanded.extend(group.iter().cloned());
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separators_by_glob() {
+        let config: Config = toml::from_str(
+            "[[separators]]\nglob = \"docs/*.txt\"\nleft = \"<<\"\nright = \">>\"\n\
+             [[separators]]\nglob = \"*.txt\"\nleft = \"[[\"\nright = \"]]\"\n",
+        )
+        .unwrap();
+        let seps = |path: &str| config.separators(Path::new(path));
+        assert_eq!(seps("docs/a.txt"), Some(("<<".into(), ">>".into())));
+        assert_eq!(seps("docs/sub/a.txt"), Some(("[[".into(), "]]".into())));
+        assert_eq!(seps("a.txt"), Some(("[[".into(), "]]".into())));
+        assert_eq!(seps("docs/a.md"), None);
+    }
+
+    #[test]
+    fn keyword_groups() {
+        let config: Config = toml::from_str("[groups]\nagents = [\"A\", \"B\"]\n").unwrap();
+        let keywords = vec!["C".to_string(), "@agents".to_string()];
+        assert_eq!(
+            expand_groups(Some(&config), &keywords).unwrap(),
+            vec!["C", "A", "B"]
+        );
+        assert!(expand_groups(Some(&config), &["@spies".to_string()]).is_err());
+        assert!(expand_groups(None, &keywords).is_err());
+        assert_eq!(expand_groups(None, &keywords[..1]).unwrap(), vec!["C"]);
+    }
+}
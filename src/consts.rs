@@ -25,6 +25,9 @@ pub const VALID_CIPHER_ALGS: &[&str] = &["aes-256-siv", "aes-256-gcm", "aes-256-
 
 pub const DEFAULT_MAX_DEPTH: usize = 100;
 
+// appended to the names of the backups of overwritten files by default
+pub const DEFAULT_BACKUP_SUFFIX: &str = "~";
+
 // parsing separators
 pub const DEFAULT_LEFT_SEP: &str = "// <(";
 pub const DEFAULT_RIGHT_SEP: &str = ")>";
@@ -38,6 +41,22 @@ pub const VALID_PBKDF_ALGS: &[&str] = &[
     "legacy",
 ];
 
+pub const VALID_PUBKEY_ALGS: &[&str] = &["x25519", "p256"];
+
+// environment variables with passwords, followed by the WORD
+pub const KEY_ENV_PREFIX: &str = "ENPROT_KEY_";
+
+// environment variable with the passphrase of the keystore
+pub const KEYSTORE_PASSWORD_ENV: &str = "ENPROT_KEYSTORE_PASSWORD";
+
+// where passwords are stored between sessions
+pub const VALID_KEYRINGS: &[&str] = &["auto", "secret-service", "file"];
+
+// environment variable with the socket of the agent
+pub const AGENT_SOCKET_ENV: &str = "ENPROT_AGENT_SOCK";
+// seconds the agent keeps passwords and keys by default
+pub const DEFAULT_AGENT_TIMEOUT: u64 = 900;
+
 // policies
-pub const VALID_POLICIES: &[&str] = &["default", "nist"];
+pub const VALID_POLICIES: &[&str] = &["default", "nist", "strict"];
 pub const DEFAULT_POLICY: &str = "default";
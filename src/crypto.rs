@@ -24,8 +24,10 @@
 use phf::phf_map;
 use std::collections::BTreeMap;
 
+use error::{Error, Result};
 pub use policy::default::CryptoPolicyDefault;
 pub use policy::nist::CryptoPolicyNIST;
+pub use policy::strict::CryptoPolicyStrict;
 pub use policy::CryptoPolicy;
 
 pub static BOTAN_HASH_ALG_MAP: phf::Map<&'static str, &'static str> = phf_map! {
@@ -35,33 +37,39 @@ pub static BOTAN_HASH_ALG_MAP: phf::Map<&'static str, &'static str> = phf_map! {
     "sha3-512" => "SHA-3(512)",
 };
 
-pub fn to_botan_hash(alg: &str) -> Result<&'static str, &'static str> {
+pub fn to_botan_hash(alg: &str) -> Result<&'static str> {
     Ok(BOTAN_HASH_ALG_MAP
         .get::<str>(alg)
-        .ok_or("Unrecognized hash algorithm")?)
+        .ok_or(Error::Crypto("Unrecognized hash algorithm"))?)
 }
 
-pub fn digest(
-    alg: &str,
-    data: &[u8],
-    policy: &Box<dyn CryptoPolicy>,
-) -> Result<Vec<u8>, &'static str> {
+pub fn digest(alg: &str, data: &[u8], policy: &Box<dyn CryptoPolicy>) -> Result<Vec<u8>> {
     policy.check_hash(alg)?;
-    let hash =
-        botan::HashFunction::new(to_botan_hash(alg)?).map_err(|_| "Botan error creating hash")?;
-    hash.update(data).map_err(|_| "Botan error updating hash")?;
-    hash.finish().map_err(|_| "Botan error finishing hash")
+    let hash = botan::HashFunction::new(to_botan_hash(alg)?)
+        .map_err(|_| Error::Crypto("Botan error creating hash"))?;
+    hash.update(data)
+        .map_err(|_| Error::Crypto("Botan error updating hash"))?;
+    hash.finish()
+        .map_err(|_| Error::Crypto("Botan error finishing hash"))
 }
 
-pub fn hexdigest(
-    alg: &str,
-    data: &[u8],
-    policy: &Box<dyn CryptoPolicy>,
-) -> Result<String, &'static str> {
+pub fn hexdigest(alg: &str, data: &[u8], policy: &Box<dyn CryptoPolicy>) -> Result<String> {
     Ok(hex::encode(digest(alg, data, policy)?))
 }
 
-fn to_botan_pbkdf(alg: &str) -> Result<String, &'static str> {
+pub fn mac(alg: &str, key: &[u8], data: &[u8], policy: &Box<dyn CryptoPolicy>) -> Result<Vec<u8>> {
+    policy.check_hash(alg)?;
+    let mac = botan::MsgAuthCode::new(&format!("HMAC({})", to_botan_hash(alg)?))
+        .map_err(|_| Error::Crypto("Botan error creating MAC"))?;
+    mac.set_key(key)
+        .map_err(|_| Error::Crypto("Botan error setting MAC key"))?;
+    mac.update(data)
+        .map_err(|_| Error::Crypto("Botan error updating MAC"))?;
+    mac.finish()
+        .map_err(|_| Error::Crypto("Botan error finishing MAC"))
+}
+
+fn to_botan_pbkdf(alg: &str) -> Result<String> {
     if alg.starts_with("pbkdf2-") {
         let hash = alg.splitn(2, "-").skip(1).collect::<String>();
         return Ok(format!("PBKDF2({})", to_botan_hash(&hash)?));
@@ -69,10 +77,7 @@ fn to_botan_pbkdf(alg: &str) -> Result<String, &'static str> {
     match alg {
         "argon2" => Ok("Argon2id".to_string()),
         "scrypt" => Ok("Scrypt".to_string()),
-        _ => {
-            eprintln!("Invalid KDF: '{}'", alg);
-            Err("Invalid KDF")
-        }
+        _ => Err(Error::Crypto("Invalid KDF")),
     }
 }
 
@@ -84,17 +89,19 @@ pub fn derive_key_from_password(
     salt: &[u8],
     mut params_map: BTreeMap<String, usize>,
     policy: &Box<dyn CryptoPolicy>,
-) -> Result<Vec<u8>, &'static str> {
+) -> Result<Vec<u8>> {
     policy.check_pbkdf(alg, key_len, password, salt, &params_map)?;
     let mut params: [usize; 3] = [0, 0, 0];
     for (i, param) in param_order[1].iter().enumerate() {
         if param.is_empty() {
             continue;
         }
-        params[i] = params_map.remove(*param).ok_or("Missing PBKDF parameter")?;
+        params[i] = params_map
+            .remove(*param)
+            .ok_or(Error::Format("Missing PBKDF parameter"))?;
     }
     if !params_map.is_empty() {
-        return Err("Extraneous PBKDF parameters");
+        return Err(Error::Format("Extraneous PBKDF parameters"));
     }
     let key = botan::derive_key_from_password(
         &to_botan_pbkdf(alg)?,
@@ -105,7 +112,7 @@ pub fn derive_key_from_password(
         params[1],
         params[2],
     )
-    .map_err(|_| "Botan error deriving key")?;
+    .map_err(|_| Error::Crypto("Botan error deriving key"))?;
     Ok(key)
 }
 
@@ -117,7 +124,7 @@ pub fn derive_key_from_password_timed(
     salt: &[u8],
     msec: u32,
     policy: &Box<dyn CryptoPolicy>,
-) -> Result<(Vec<u8>, BTreeMap<String, usize>), &'static str> {
+) -> Result<(Vec<u8>, BTreeMap<String, usize>)> {
     let (key, param1, param2, param3) = botan::derive_key_from_password_timed(
         &to_botan_pbkdf(alg)?,
         key_len,
@@ -125,7 +132,7 @@ pub fn derive_key_from_password_timed(
         &salt,
         msec,
     )
-    .map_err(|_| "Botan error deriving key (timed)")?;
+    .map_err(|_| Error::Crypto("Botan error deriving key (timed)"))?;
     let params = [param1, param2, param3];
     let mut params_map = BTreeMap::new();
     for (i, param) in param_order[0].iter().filter(|v| !v.is_empty()).enumerate() {
@@ -0,0 +1,261 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Library interface for embedding enprot.
+//!
+//! A [`Document`] is parsed with a set of [`Operations`], transformed and
+//! written back out, the same way the command line tool processes a file:
+//!
+//! ```no_run
+//! use enprot::{Credentials, Document, Operations};
+//!
+//! let mut ops = Operations::default()
+//!     .encrypt("Agent_007")
+//!     .credentials(Credentials::new().password("Agent_007", "password"));
+//! let doc = Document::read("sample/simple.ept", &mut ops).unwrap();
+//! let out = doc.transform(&mut ops).unwrap();
+//! out.write(&mut std::io::stdout(), &mut ops).unwrap();
+//! ```
+//!
+//! Unlike the command line tool, nothing is ever prompted for: a password
+//! that is needed but missing from the [`Credentials`] is reported as
+//! [`Error::MissingPassword`].
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use crypto::{CryptoPolicy, CryptoPolicyDefault};
+use error::{Error, Result};
+use etree;
+use etree::{ParseOps, TextNode};
+use pubkey;
+use pubkey::PrivateKey;
+
+/// Passwords and private keys used to encrypt and decrypt segments.
+#[derive(Default)]
+pub struct Credentials {
+    passwords: HashMap<String, String>,
+    new_passwords: HashMap<String, String>,
+    identities: Vec<PrivateKey>,
+}
+
+impl Credentials {
+    pub fn new() -> Credentials {
+        Credentials::default()
+    }
+
+    /// Sets the password for a keyword, or for `KEYWORD/LABEL` of a
+    /// password recipient.
+    pub fn password(mut self, keyw: &str, password: &str) -> Credentials {
+        self.passwords
+            .insert(keyw.to_string(), password.to_string());
+        self
+    }
+
+    /// Sets the password a keyword is re-encrypted with when rekeying.
+    pub fn new_password(mut self, keyw: &str, password: &str) -> Credentials {
+        self.new_passwords
+            .insert(keyw.to_string(), password.to_string());
+        self
+    }
+
+    /// Loads a private key for segments encrypted to public key recipients.
+    pub fn identity<P: AsRef<Path>>(mut self, path: P) -> Result<Credentials> {
+        self.identities
+            .push(pubkey::load_private_key(path.as_ref())?);
+        Ok(self)
+    }
+}
+
+/// The operations to apply to a document, along with the settings that
+/// affect parsing and writing.
+pub struct Operations {
+    paops: ParseOps,
+}
+
+impl Default for Operations {
+    fn default() -> Operations {
+        Operations::new(Box::new(CryptoPolicyDefault {}))
+    }
+}
+
+impl Operations {
+    /// Creates an empty set of operations using the given crypto policy
+    /// and its default algorithms.
+    pub fn new(policy: Box<dyn CryptoPolicy>) -> Operations {
+        let mut paops = ParseOps::new(policy);
+        paops.interactive = false;
+        Operations { paops }
+    }
+
+    /// Stores the segments of a keyword in the CAS.
+    pub fn store(mut self, keyw: &str) -> Operations {
+        self.paops.store.insert(keyw.to_string());
+        self
+    }
+
+    /// Fetches the stored segments of a keyword from the CAS.
+    pub fn fetch(mut self, keyw: &str) -> Operations {
+        self.paops.fetch.insert(keyw.to_string());
+        self
+    }
+
+    /// Encrypts the segments of a keyword.
+    pub fn encrypt(mut self, keyw: &str) -> Operations {
+        self.paops.encrypt.insert(keyw.to_string());
+        self
+    }
+
+    /// Decrypts the segments of a keyword.
+    pub fn decrypt(mut self, keyw: &str) -> Operations {
+        self.paops.decrypt.insert(keyw.to_string());
+        self
+    }
+
+    /// Re-encrypts the segments of a keyword with a new password.
+    pub fn rekey(mut self, keyw: &str) -> Operations {
+        self.paops.rekey.insert(keyw.to_string());
+        self
+    }
+
+    /// Re-encrypts the segments of a keyword with the current algorithms.
+    pub fn migrate(mut self, keyw: &str) -> Operations {
+        self.paops.migrate.insert(keyw.to_string());
+        self
+    }
+
+    /// Sets the directory CAS objects are read from and written to.
+    pub fn casdir<P: Into<PathBuf>>(mut self, dir: P) -> Operations {
+        self.paops.casdir = dir.into();
+        self
+    }
+
+    /// Sets the separators that enclose commands, `// <(` and `)>` by
+    /// default.
+    pub fn separators(mut self, left: &str, right: &str) -> Operations {
+        self.paops.left_sep = left.to_string();
+        self.paops.right_sep = right.to_string();
+        self
+    }
+
+    /// Limits how deeply segments may be nested, 0 for no limit.
+    pub fn max_depth(mut self, depth: usize) -> Operations {
+        self.paops.max_depth = depth;
+        self
+    }
+
+    /// Binds ciphertext to its keyword, and to `doc_id` if given, as
+    /// associated data.
+    pub fn bind_ad(mut self, doc_id: Option<&str>) -> Operations {
+        self.paops.cipheropts.ad = true;
+        self.paops.cipheropts.doc_id = doc_id.map(|id| id.to_string());
+        self
+    }
+
+    /// Adds passwords and private keys to the ones already given.
+    pub fn credentials(mut self, creds: Credentials) -> Operations {
+        self.paops.passwords.extend(creds.passwords);
+        self.paops.new_passwords.extend(creds.new_passwords);
+        self.paops.identities.extend(creds.identities);
+        self
+    }
+
+    /// Takes the warnings collected since the last call, such as those
+    /// about unrecognized extended fields.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.paops.log)
+    }
+}
+
+/// A parsed document.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Document {
+    /// The name used in error messages.
+    pub name: String,
+    /// The top level nodes of the document.
+    pub nodes: Vec<TextNode>,
+}
+
+impl Document {
+    /// Parses a document from a reader. `name` is used in error messages.
+    pub fn parse<R: BufRead>(name: &str, reader: R, ops: &mut Operations) -> Result<Document> {
+        ops.paops.fname = name.to_string();
+        Ok(Document {
+            name: name.to_string(),
+            nodes: etree::parse(reader, &mut ops.paops)?,
+        })
+    }
+
+    /// Reads and parses a document from a file.
+    pub fn read<P: AsRef<Path>>(path: P, ops: &mut Operations) -> Result<Document> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        Document::parse(&path.to_string_lossy(), BufReader::new(file), ops)
+    }
+
+    /// Returns the keywords of all segments, including nested ones.
+    pub fn keywords(&self) -> BTreeSet<String> {
+        let mut keywords = BTreeSet::new();
+        collect_keywords(&self.nodes, &mut keywords);
+        keywords
+    }
+
+    /// Applies the operations, returning the transformed document.
+    pub fn transform(&self, ops: &mut Operations) -> Result<Document> {
+        ops.paops.fname = self.name.clone();
+        Ok(Document {
+            name: self.name.clone(),
+            nodes: etree::transform(&self.nodes, &mut ops.paops)?,
+        })
+    }
+
+    /// Writes the document out in EPT format.
+    pub fn write<W: Write>(&self, writer: &mut W, ops: &mut Operations) -> Result<()> {
+        etree::tree_write(writer, &self.nodes, &mut ops.paops)
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Io {
+                path: PathBuf::from(&self.name),
+                source: e,
+            })
+    }
+}
+
+fn collect_keywords(nodes: &[TextNode], keywords: &mut BTreeSet<String>) {
+    for node in nodes {
+        match node {
+            TextNode::BeginEnd { keyw, txt, .. } | TextNode::Encrypted { keyw, txt, .. } => {
+                keywords.insert(keyw.to_string());
+                collect_keywords(txt, keywords);
+            }
+            TextNode::Stored { keyw, .. } => {
+                keywords.insert(keyw.to_string());
+            }
+            TextNode::Plain(_) | TextNode::Data { .. } => {}
+        }
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	editing in $EDITOR through a private temporary file
+
+use std::env;
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::{self, BufRead, Write};
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use error::{Error, Result};
+
+fn io_error(path: &Path, e: io::Error) -> Error {
+    Error::Io {
+        path: path.to_path_buf(),
+        source: e,
+    }
+}
+
+// a file in a directory of its own that only we can read, preferably in
+// memory, so that plaintext never reaches the disk. The editor may leave
+// swap or backup files next to it. All of them are overwritten and removed
+// when it is dropped.
+pub struct TempFile {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl TempFile {
+    // name is the file name, so that the editor recognizes the type
+    pub fn create(name: &str, rng: &Option<botan::RandomNumberGenerator>) -> Result<TempFile> {
+        let random = rng
+            .as_ref()
+            .ok_or(Error::Crypto("Missing RNG"))?
+            .read(8)
+            .map_err(|_| Error::Crypto("RNG error"))?;
+        let dir = temp_dir().join(format!("enprot-{}", hex::encode(random)));
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&dir).map_err(|e| io_error(&dir, e))?;
+        let temp = TempFile {
+            path: dir.join(name),
+            dir,
+        };
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options
+            .open(&temp.path)
+            .map_err(|e| io_error(&temp.path, e))?;
+        Ok(temp)
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<()> {
+        fs::write(&self.path, data).map_err(|e| io_error(&self.path, e))
+    }
+
+    pub fn read(&self) -> Result<Vec<u8>> {
+        fs::read(&self.path).map_err(|e| io_error(&self.path, e))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Ok(entries) = fs::read_dir(&self.dir) {
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let _ = wipe(&entry.path());
+            }
+        }
+        let _ = fs::remove_dir(&self.dir);
+    }
+}
+
+// XDG_RUNTIME_DIR and /dev/shm are usually tmpfs
+pub fn temp_dir() -> PathBuf {
+    let runtime = env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    let shm = Some(PathBuf::from("/dev/shm")).filter(|_| cfg!(target_os = "linux"));
+    runtime
+        .into_iter()
+        .chain(shm)
+        .find(|dir| dir.is_dir())
+        .unwrap_or_else(env::temp_dir)
+}
+
+// overwrite a file with zeros before removing it
+fn wipe(path: &Path) -> io::Result<()> {
+    let len = fs::metadata(path)?.len();
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len as usize])?;
+    file.sync_all()?;
+    fs::remove_file(path)
+}
+
+// run $VISUAL or $EDITOR, or vi, on path and wait for it. Like git, the
+// variable may contain arguments and is run by the shell.
+pub fn run_editor(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let status = if cfg!(windows) {
+        Command::new(&editor).arg(path).status()
+    } else {
+        Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg(&editor)
+            .arg(path)
+            .status()
+    };
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(Error::Io {
+            path: PathBuf::from(&editor),
+            source: io::Error::other(format!("editor {}", status)),
+        }),
+        Err(e) => Err(io_error(Path::new(&editor), e)),
+    }
+}
+
+// ask a yes or no question on the terminal, yes unless answered otherwise
+pub fn confirm(question: &str) -> bool {
+    eprint!("{} [Y/n] ", question);
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(0) | Err(_) => false,
+        Ok(_) => !answer.trim().to_lowercase().starts_with('n'),
+    }
+}
@@ -0,0 +1,152 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    // malformed EPT markup
+    Parse {
+        file: String,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // CAS object that does not exist
+    CasMissing {
+        hash: String,
+        path: PathBuf,
+    },
+    // CAS object whose contents do not match its name
+    CasMismatch {
+        expected: String,
+        actual: String,
+    },
+    // wrong password or key, or tampered ciphertext
+    Auth,
+    // password that is needed but was not given, when not prompting
+    MissingPassword(String),
+    // something the crypto policy does not permit
+    Policy(String),
+    // any other failure of the crypto primitives
+    Crypto(&'static str),
+    // malformed extended field, key or encoding
+    Format(&'static str),
+    // operation that cannot be applied to a segment
+    Operation(&'static str),
+    // invalid configuration file
+    Config {
+        path: PathBuf,
+        message: String,
+    },
+    // reading or writing a file
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    // git command that failed, with its message
+    Git(String),
+    // credential agent that failed or refused a request
+    Agent(String),
+    // keystore file that cannot be opened or saved
+    Keystore {
+        path: PathBuf,
+        source: Box<Error>,
+    },
+    // password source that could not be read
+    KeySource {
+        keyword: String,
+        message: String,
+    },
+    // any of the above, while processing the segments of a keyword
+    Segment {
+        action: &'static str,
+        keyword: String,
+        source: Box<Error>,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub(crate) fn segment(action: &'static str, keyword: &str, source: Error) -> Error {
+        Error::Segment {
+            action,
+            keyword: keyword.to_string(),
+            source: Box::new(source),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Parse {
+                file,
+                line,
+                column,
+                message,
+            } => write!(f, "{}:{}:{}: {}", file, line, column, message),
+            Error::CasMissing { hash, path } => {
+                write!(f, "CAS object {} not found in {}", hash, path.display())
+            }
+            Error::CasMismatch { expected, actual } => write!(
+                f,
+                "CAS content hash mismatch (expected {}, found {})",
+                expected, actual
+            ),
+            Error::Auth => write!(f, "Decryption failed, wrong key or corrupted data"),
+            Error::MissingPassword(name) => write!(f, "No password given for {}", name),
+            Error::Policy(msg) => write!(f, "{}", msg),
+            Error::Crypto(msg) | Error::Format(msg) | Error::Operation(msg) => {
+                write!(f, "{}", msg)
+            }
+            Error::Config { path, message } => write!(f, "{}: {}", path.display(), message),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Git(msg) => write!(f, "git: {}", msg),
+            Error::Agent(msg) => write!(f, "agent: {}", msg),
+            Error::Keystore { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::KeySource { keyword, message } => {
+                write!(f, "Cannot get the password for {}: {}", keyword, message)
+            }
+            Error::Segment {
+                action,
+                keyword,
+                source,
+            } => write!(f, "Error {} {}: {}", action, keyword, source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Keystore { source, .. } | Error::Segment { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
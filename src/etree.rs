@@ -25,16 +25,24 @@ use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::prelude::*;
-use std::io::{Cursor, Write};
+use std::io::{self, Cursor, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 
+use agent::Agent;
 use cas;
 use consts;
-use crypto::CryptoPolicy;
+use crypto::{CryptoPolicy, CryptoPolicyDefault};
+use error::{Error, Result};
+use keyring::Keyring;
+use keysource::KeySource;
 use pbkdf::PBKDFCache;
 use prot;
+use prot::Recipient;
+use pubkey::PrivateKey;
 use utils;
 
+#[derive(Clone)]
 pub struct PBKDFOptions {
     pub alg: String,                             // algorithm name
     pub saltlen: usize,                          // desired salt length
@@ -55,9 +63,13 @@ impl PBKDFOptions {
     }
 }
 
+#[derive(Clone)]
 pub struct CipherOptions {
     pub alg: String,
     pub iv: Option<Vec<u8>>,
+    pub ad: bool,               // bind keyword and extended fields as associated data
+    pub doc_id: Option<String>, // document identifier to bind as well
+    pub deterministic: bool,    // derive salt and IV from the plaintext
 }
 
 impl CipherOptions {
@@ -65,30 +77,136 @@ impl CipherOptions {
         CipherOptions {
             alg: policy.default_cipher_alg(),
             iv: None,
+            ad: policy.default_bind_ad(),
+            doc_id: None,
+            deterministic: false,
         }
     }
 }
 
+// what the workers of --jobs share: the passwords any of them found, and
+// the settings of the command line with its private keys and keystore, read
+// once on the main thread. The passwords are locked while one is looked for
+// or asked for, so that each is read from its source or asked for only once.
+pub struct Shared {
+    passwords: Mutex<Passwords>,
+    settings: Settings,
+    keyring: Option<Mutex<Keyring>>,
+}
+
+// what each worker copies into a ParseOps of its own. Its policy and botan's
+// objects stay in the thread that made them, so the private keys are kept
+// encoded, as the keystore keeps them.
+struct Settings {
+    verbose: bool,
+    left_sep: String,
+    right_sep: String,
+    passwords: HashMap<String, String>,
+    new_passwords: HashMap<String, String>,
+    recipients: HashMap<String, Vec<Recipient>>,
+    revoke: HashMap<String, Vec<String>>,
+    identities: Vec<String>,
+    agent: Option<Agent>,
+    pbkdf_cache: bool,
+    interactive: bool,
+    dry_run: bool,
+}
+
+#[derive(Default)]
+struct Passwords {
+    passwords: HashMap<String, String>,
+    new_passwords: HashMap<String, String>,
+}
+
+impl Shared {
+    // for workers taking over from paops, with the keyring of the keystore
+    pub fn new(paops: &ParseOps, keyring: Option<Keyring>) -> Result<Shared> {
+        Ok(Shared {
+            passwords: Mutex::default(),
+            settings: Settings {
+                verbose: paops.verbose,
+                left_sep: paops.left_sep.clone(),
+                right_sep: paops.right_sep.clone(),
+                passwords: paops.passwords.clone(),
+                new_passwords: paops.new_passwords.clone(),
+                recipients: paops.recipients.clone(),
+                revoke: paops.revoke.clone(),
+                identities: paops
+                    .identities
+                    .iter()
+                    .map(|identity| identity.encode())
+                    .collect::<Result<_>>()?,
+                agent: paops.agent.clone(),
+                pbkdf_cache: paops.pbkdf_cache.is_some(),
+                interactive: paops.interactive,
+                dry_run: paops.dry_run,
+            },
+            keyring: keyring.map(Mutex::new),
+        })
+    }
+
+    fn lock(shared: &Option<Arc<Shared>>) -> Option<MutexGuard<'_, Passwords>> {
+        shared
+            .as_ref()
+            .map(|shared| shared.passwords.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    // the keyring of the keystore, for workers that have none of their own
+    fn keyring(shared: &Option<Arc<Shared>>) -> Option<MutexGuard<'_, Keyring>> {
+        shared
+            .as_ref()?
+            .keyring
+            .as_ref()
+            .map(|keyring| keyring.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
 // parse operations
 
 pub struct ParseOps {
     pub max_depth: usize,
-    pub left_sep: String,                          // left separator
-    pub right_sep: String,                         // right separator
-    pub store: HashSet<String>,                    // keywords to store
-    pub fetch: HashSet<String>,                    // keywords to fetch
-    pub encrypt: HashSet<String>,                  // keywords to encrypt
-    pub decrypt: HashSet<String>,                  // keywords to decrypt
-    pub passwords: HashMap<String, String>,        // passwords
-    pub fname: String,                             // file name being parsed
-    pub casdir: PathBuf,                           // directory for cas objects
-    pub verbose: bool,                             // verbose output to stdout
-    pub rng: Option<botan::RandomNumberGenerator>, // RNG to use
-    pub policy: Box<dyn CryptoPolicy>,             // the crypto alg policy
-    pub pbkdfopts: PBKDFOptions,                   // the PBKDF options
-    pub pbkdf_cache: Option<PBKDFCache>,           // the PBKDF cache
-    pub cipheropts: CipherOptions,                 // cipher options
-    level: usize,                                  // current recursion level
+    pub left_sep: String,                                // left separator
+    pub right_sep: String,                               // right separator
+    pub store: HashSet<String>,                          // keywords to store
+    pub fetch: HashSet<String>,                          // keywords to fetch
+    pub encrypt: HashSet<String>,                        // keywords to encrypt
+    pub decrypt: HashSet<String>,                        // keywords to decrypt
+    pub rekey: HashSet<String>,                          // keywords to re-encrypt
+    pub migrate: HashSet<String>,                        // keywords to upgrade algorithms for
+    pub new_passwords: HashMap<String, String>,          // new passwords for rekeying
+    pub passwords: HashMap<String, String>,              // passwords
+    pub key_sources: HashMap<String, KeySource>,         // where to read passwords from
+    pub recipients: HashMap<String, Vec<Recipient>>,     // recipients to wrap the data key for
+    pub grant: HashSet<String>,                          // keywords to add recipients to
+    pub revoke: HashMap<String, Vec<String>>,            // recipients to remove
+    pub identities: Vec<PrivateKey>,                     // private keys for decryption
+    pub fname: String,                                   // file name being parsed
+    pub casdir: PathBuf,                                 // directory for cas objects
+    pub verbose: bool,                                   // verbose output to stdout
+    pub rng: Option<botan::RandomNumberGenerator>,       // RNG to use
+    pub policy: Box<dyn CryptoPolicy>,                   // the crypto alg policy
+    pub defaults: Box<dyn CryptoPolicy>,                 // where the settings come from
+    pub pbkdfopts: PBKDFOptions,                         // the PBKDF options
+    pub pbkdf_cache: Option<PBKDFCache>,                 // the PBKDF cache
+    pub agent: Option<Agent>,                            // agent holding passwords
+    pub keyring: Option<Keyring>,                        // stored passwords
+    pub cipheropts: CipherOptions,                       // cipher options
+    pub pubkey_alg: String,                              // algorithm for new key pairs
+    pub interactive: bool,                               // prompt for missing passwords
+    pub eol: String,                                     // line terminator for new lines
+    pub dry_run: bool,                                   // compute but don't write CAS objects
+    pub reuse: HashMap<String, Vec<PreviousEncryption>>, // earlier encryptions to keep
+    pub shared: Option<Arc<Shared>>,                     // shared with other workers
+    pub log: Vec<String>,                                // messages for standard error
+    level: usize,                                        // current recursion level
+    unconfirmed: HashSet<String>,                        // asked for, not known to be right
+}
+
+// an earlier encryption of a segment, kept if the plaintext is unchanged
+pub struct PreviousEncryption {
+    pub pt: Vec<u8>,
+    pub ct: Vec<u8>,
+    pub extfields: BTreeMap<String, String>,
 }
 
 impl ParseOps {
@@ -101,113 +219,217 @@ impl ParseOps {
             fetch: HashSet::new(),
             encrypt: HashSet::new(),
             decrypt: HashSet::new(),
+            rekey: HashSet::new(),
+            migrate: HashSet::new(),
+            new_passwords: HashMap::new(),
             passwords: HashMap::new(),
+            key_sources: HashMap::new(),
+            recipients: HashMap::new(),
+            grant: HashSet::new(),
+            revoke: HashMap::new(),
+            identities: Vec::new(),
             fname: "".to_string(),
             casdir: Path::new("").to_path_buf(),
             level: 0,
             verbose: false,
             rng: Some(botan::RandomNumberGenerator::new().unwrap()),
             pbkdfopts: PBKDFOptions::new(&policy),
-            pbkdf_cache: Some(Vec::new()),
+            pbkdf_cache: Some(PBKDFCache::default()),
+            agent: None,
+            keyring: None,
+            unconfirmed: HashSet::new(),
             cipheropts: CipherOptions::new(&policy),
+            pubkey_alg: policy.default_pubkey_alg(),
+            interactive: true,
+            eol: "\n".to_string(),
+            dry_run: false,
+            reuse: HashMap::new(),
+            shared: None,
+            log: Vec::new(),
+            defaults: Box::new(CryptoPolicyDefault {}),
             policy: policy,
         }
     }
+
+    // the ParseOps of a worker of --jobs, a copy of the one shared was made
+    // from
+    pub fn worker(shared: &Arc<Shared>, policy: Box<dyn CryptoPolicy>) -> ParseOps {
+        let settings = &shared.settings;
+        let mut paops = ParseOps::new(policy);
+        paops.verbose = settings.verbose;
+        paops.left_sep = settings.left_sep.clone();
+        paops.right_sep = settings.right_sep.clone();
+        paops.passwords = settings.passwords.clone();
+        paops.new_passwords = settings.new_passwords.clone();
+        paops.recipients = settings.recipients.clone();
+        paops.revoke = settings.revoke.clone();
+        // encoded from keys that were loaded, they load again
+        paops.identities = settings
+            .identities
+            .iter()
+            .map(|pem| PrivateKey::decode(pem).unwrap())
+            .collect();
+        paops.agent = settings.agent.clone();
+        match paops.pbkdf_cache.as_mut() {
+            Some(cache) if settings.pbkdf_cache => cache.agent = settings.agent.clone(),
+            _ => paops.pbkdf_cache = None,
+        }
+        paops.interactive = settings.interactive;
+        paops.dry_run = settings.dry_run;
+        paops.shared = Some(shared.clone());
+        paops
+    }
 }
 
 const DATA_BYTES_PER_LINE: usize = 48; // that's 64 characters
 
 // the actual tree
 
-type TextTree = Vec<TextNode>;
+pub type TextTree = Vec<TextNode>;
 
+// part of the library API: variants and fields may be added, so matches
+// outside the crate need a wildcard arm and `..`
 #[derive(Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
 pub enum TextNode {
-    Plain(String),
-    Data(Vec<u8>),
+    Plain(String), // lines of text, including line terminators
+    #[non_exhaustive]
+    Data {
+        data: Vec<u8>,
+        indent: String,     // whitespace before the left separator
+        lines: Vec<String>, // command lines as parsed, including terminators
+    },
+    #[non_exhaustive]
     Stored {
         keyw: String,
         cas: String,
+        indent: String,
+        lines: Vec<String>,
     },
+    #[non_exhaustive]
     Encrypted {
         keyw: String,
         txt: TextTree,
         extfields: BTreeMap<String, String>,
+        indent: String,
+        lines: Vec<String>,
     },
+    #[non_exhaustive]
     BeginEnd {
         keyw: String,
         txt: TextTree,
+        indent: String,
+        lines: Vec<String>,
     },
 }
 
 type Parser = fn(
     &[&str],
     &String,
-    i32,
+    usize,
+    &str,
     &mut ParseOps,
     &mut Vec<TextNode>,
     &mut Vec<TextNode>,
-) -> Result<(), &'static str>;
+) -> Result<()>;
+
+// "\r\n", "\n" or "" for the last line of a file without one
+fn line_terminator(line: &str) -> &str {
+    if line.ends_with("\r\n") {
+        &line[line.len() - 2..]
+    } else if line.ends_with('\n') {
+        &line[line.len() - 1..]
+    } else {
+        ""
+    }
+}
+
+// error at the first occurrence of token in the line
+fn parse_error(paops: &ParseOps, line: &str, lineno: usize, token: &str, message: String) -> Error {
+    Error::Parse {
+        file: paops.fname.clone(),
+        line: lineno,
+        column: line.find(token).map_or(1, |i| i + 1),
+        message,
+    }
+}
 
 fn parse_data(
     cmd: &[&str],
     line: &String,
-    lineno: i32,
+    lineno: usize,
+    indent: &str,
     paops: &mut ParseOps,
     _pstack: &mut Vec<TextNode>,
     text: &mut Vec<TextNode>,
-) -> Result<(), &'static str> {
+) -> Result<()> {
     for i in 0..cmd.len() {
         let mut data = match utils::base64_decode(cmd[i]) {
             Ok(data) => data,
             Err(e) => {
-                eprintln!(
-                    "Parse: Error decoding base64.\n\
-                     Parse: '{}': {}\n\
-                     {}:{}:{}",
-                    cmd[i], e, paops.fname, lineno, line
-                );
-                return Err("Parse error");
+                return Err(parse_error(
+                    paops,
+                    line,
+                    lineno,
+                    cmd[i],
+                    format!("Error decoding base64 '{}': {}", cmd[i], e),
+                ));
             }
         };
 
         // combine with previous
-        if let Some(TextNode::Data(last)) = text.last_mut() {
+        if let Some(TextNode::Data { data: last, .. }) = text.last_mut() {
             last.append(&mut data);
         } else {
-            text.push(TextNode::Data(data));
+            text.push(TextNode::Data {
+                data,
+                indent: indent.to_string(),
+                lines: Vec::new(),
+            });
         }
     }
+    if let Some(TextNode::Data { lines, .. }) = text.last_mut() {
+        lines.push(line.to_string());
+    }
     return Ok(());
 }
 
 fn parse_begin(
     cmd: &[&str],
     line: &String,
-    lineno: i32,
+    lineno: usize,
+    indent: &str,
     paops: &mut ParseOps,
     pstack: &mut Vec<TextNode>,
     text: &mut Vec<TextNode>,
-) -> Result<(), &'static str> {
+) -> Result<()> {
     if cmd.len() != 1 {
-        eprintln!(
-            "Parse: BEGIN needs a single keyword.\n\
-             {}:{}:{}",
-            paops.fname, lineno, line
-        );
-        return Err("Parse error");
+        return Err(parse_error(
+            paops,
+            line,
+            lineno,
+            "BEGIN",
+            "BEGIN needs a single keyword".to_string(),
+        ));
     }
     paops.level += 1;
     pstack.push(TextNode::BeginEnd {
         keyw: cmd[0].to_owned(),
         txt: text.to_vec(),
+        indent: indent.to_string(),
+        lines: vec![line.to_string()],
     });
     text.clear();
     Ok(())
 }
 
 // parse trailing extended fields, such as pbkdf:
-fn parse_encrypted_extfields(cmd: &[&str]) -> Result<BTreeMap<String, String>, &'static str> {
+fn parse_encrypted_extfields(
+    cmd: &[&str],
+    line: &String,
+    lineno: usize,
+    paops: &ParseOps,
+) -> Result<BTreeMap<String, String>> {
     let mut extfields: BTreeMap<String, String> = BTreeMap::new();
     for field in cmd.iter().rev() {
         if field.find(':') == None {
@@ -218,7 +440,13 @@ fn parse_encrypted_extfields(cmd: &[&str]) -> Result<BTreeMap<String, String>, &
         let key = fields[0];
         let value = fields[1];
         if extfields.contains_key(key) {
-            return Err("Duplicate extended field");
+            return Err(parse_error(
+                paops,
+                line,
+                lineno,
+                field,
+                format!("Duplicate extended field '{}'", key),
+            ));
         }
         extfields.insert(key.to_string(), value.to_string());
     }
@@ -228,25 +456,36 @@ fn parse_encrypted_extfields(cmd: &[&str]) -> Result<BTreeMap<String, String>, &
 fn parse_encrypted(
     cmd: &[&str],
     line: &String,
-    lineno: i32,
+    lineno: usize,
+    indent: &str,
     paops: &mut ParseOps,
     pstack: &mut Vec<TextNode>,
     text: &mut Vec<TextNode>,
-) -> Result<(), &'static str> {
-    let extfields = parse_encrypted_extfields(cmd)?;
+) -> Result<()> {
+    let extfields = parse_encrypted_extfields(cmd, line, lineno, paops)?;
     let param_count = cmd.len() - extfields.len();
     let extfield_keys: HashSet<String> = extfields.keys().map(|f| f.to_string()).collect();
-    let known_extfields: HashSet<String> = ["pbkdf".to_string(), "cipher".to_string()]
-        .iter()
-        .cloned()
-        .collect();
+    let known_extfields: HashSet<String> = [
+        "pbkdf".to_string(),
+        "cipher".to_string(),
+        prot::AD_EXTFIELD.to_string(),
+    ]
+    .iter()
+    .cloned()
+    .collect();
     if extfield_keys
         .difference(&known_extfields)
+        .filter(|key| {
+            !key.starts_with(prot::PUBKEY_EXTFIELD_PREFIX)
+                && !key.starts_with(prot::PASSWORD_EXTFIELD_PREFIX)
+        })
         .peekable()
         .peek()
         .is_some()
     {
-        eprintln!("Warning: Unrecognized extended field(s) present");
+        paops
+            .log
+            .push("Warning: Unrecognized extended field(s) present".to_string());
     }
     match param_count {
         1 => {
@@ -258,6 +497,8 @@ fn parse_encrypted(
                 keyw: cmd[0].to_owned(),
                 txt: text.to_vec(),
                 extfields,
+                indent: indent.to_string(),
+                lines: vec![line.to_string()],
             });
             text.clear();
             return Ok(());
@@ -267,25 +508,36 @@ fn parse_encrypted(
             // <( ENCRYPTED Agent_007 7a8da017c0fe671ba16f4bc55b884444e708849290d8366f19c552c90950b8c2 )>
             // <( ENCRYPTED Agent_007 7a8da017c0fe671ba16f4bc55b884444e708849290d8366f19c552c90950b8c2 pbkdf:... )>
             if cmd[1].len() != 64 {
-                return Err("Invalid CAS identifier");
+                return Err(parse_error(
+                    paops,
+                    line,
+                    lineno,
+                    cmd[1],
+                    "Invalid CAS identifier".to_string(),
+                ));
             }
             let node = vec![TextNode::Stored {
                 keyw: "ct".to_string(),
                 cas: cmd[1].to_string(),
+                indent: indent.to_string(),
+                lines: Vec::new(),
             }];
             text.push(TextNode::Encrypted {
                 keyw: cmd[0].to_string(),
                 txt: node,
                 extfields,
+                indent: indent.to_string(),
+                lines: vec![line.to_string()],
             });
         }
         _ => {
-            eprintln!(
-                "Parse: ENCRYPTED has wrong number of \
-                 parameters ({}).\n{}:{}:{}",
-                param_count, paops.fname, lineno, line
-            );
-            return Err("Parse error");
+            return Err(parse_error(
+                paops,
+                line,
+                lineno,
+                "ENCRYPTED",
+                format!("ENCRYPTED has wrong number of parameters ({})", param_count),
+            ));
         }
     }
     return Ok(());
@@ -294,33 +546,45 @@ fn parse_encrypted(
 fn parse_end(
     cmd: &[&str],
     line: &String,
-    lineno: i32,
+    lineno: usize,
+    _indent: &str,
     paops: &mut ParseOps,
     pstack: &mut Vec<TextNode>,
     text: &mut Vec<TextNode>,
-) -> Result<(), &'static str> {
+) -> Result<()> {
     if cmd.len() > 1 {
-        eprintln!(
-            "Parse: Unknown padding in END.\n{}:{}:{}",
-            paops.fname, lineno, line
-        );
-        return Err("Parse error");
+        return Err(parse_error(
+            paops,
+            line,
+            lineno,
+            cmd[1],
+            "Unknown padding in END".to_string(),
+        ));
     }
     match pstack.pop() {
-        Some(TextNode::BeginEnd { keyw, txt }) => {
+        Some(TextNode::BeginEnd {
+            keyw,
+            txt,
+            indent,
+            mut lines,
+        }) => {
             // keyword mismatch ?
             if cmd.len() >= 1 && keyw != cmd[0] {
-                eprintln!(
-                    "Parse: END mismatch (expected '{}').\n\
-                     {}:{}:{}",
-                    keyw, paops.fname, lineno, line
-                );
-                return Err("Parse error");
+                return Err(parse_error(
+                    paops,
+                    line,
+                    lineno,
+                    cmd[0],
+                    format!("END mismatch (expected '{}')", keyw),
+                ));
             }
 
+            lines.push(line.to_string());
             let node = TextNode::BeginEnd {
                 keyw: keyw,
                 txt: text.to_vec(),
+                indent,
+                lines,
             };
             *text = txt;
             text.push(node);
@@ -330,56 +594,66 @@ fn parse_end(
             keyw,
             txt,
             extfields,
+            indent,
+            mut lines,
         }) => {
             // keyword mismatch ?
-            if keyw != cmd[0] {
-                eprintln!(
-                    "Parse: END mismatch (expected '{}').\n\
-                     {}:{}:{}",
-                    keyw, paops.fname, lineno, line
-                );
-                return Err("Parse error");
+            if cmd.len() < 1 || keyw != cmd[0] {
+                return Err(parse_error(
+                    paops,
+                    line,
+                    lineno,
+                    "END",
+                    format!("END mismatch (expected '{}')", keyw),
+                ));
             }
             // check that the contents are right type
             if text.len() != 1 {
-                eprintln!(
-                    "Parse: {} elements in encrypted {} \
-                     (must be a single DATA or STORED).\n{}:{}:{}",
-                    text.len(),
-                    keyw,
-                    paops.fname,
+                return Err(parse_error(
+                    paops,
+                    line,
                     lineno,
-                    line
-                );
-                return Err("Parse error");
+                    "END",
+                    format!(
+                        "{} elements in encrypted {} (must be a single DATA or STORED)",
+                        text.len(),
+                        keyw
+                    ),
+                ));
             }
             match text[0] {
-                TextNode::Data(_) | TextNode::Stored { .. } => {
+                TextNode::Data { .. } | TextNode::Stored { .. } => {
+                    lines.push(line.to_string());
                     let node = TextNode::Encrypted {
                         keyw: keyw,
                         txt: text.to_vec(),
                         extfields,
+                        indent,
+                        lines,
                     };
                     *text = txt;
                     text.push(node);
                     paops.level -= 1;
                 }
                 _ => {
-                    eprintln!(
-                        "Parse: not DATA or STORED \
-                         element in encrypted {}.\n{}:{}:{}",
-                        keyw, paops.fname, lineno, line
-                    );
-                    return Err("Parse error");
+                    return Err(parse_error(
+                        paops,
+                        line,
+                        lineno,
+                        "END",
+                        format!("not DATA or STORED element in encrypted {}", keyw),
+                    ));
                 }
             }
         }
         _ => {
-            eprintln!(
-                "Parse: END without a start clause.\n{}:{}:{}",
-                paops.fname, lineno, line
-            );
-            return Err("Parse error");
+            return Err(parse_error(
+                paops,
+                line,
+                lineno,
+                "END",
+                "END without a start clause".to_string(),
+            ));
         }
     }
     Ok(())
@@ -388,69 +662,100 @@ fn parse_end(
 fn parse_stored(
     cmd: &[&str],
     line: &String,
-    lineno: i32,
+    lineno: usize,
+    indent: &str,
     paops: &mut ParseOps,
     _pstack: &mut Vec<TextNode>,
     text: &mut Vec<TextNode>,
-) -> Result<(), &'static str> {
+) -> Result<()> {
     if cmd.len() != 2 {
-        eprintln!(
-            "Parse: STORED needs two parameters.\n{}:{}:{}",
-            paops.fname, lineno, line
-        );
-        return Err("Parse error");
+        return Err(parse_error(
+            paops,
+            line,
+            lineno,
+            "STORED",
+            "STORED needs two parameters".to_string(),
+        ));
     }
     text.push(TextNode::Stored {
         keyw: cmd[0].to_owned(),
         cas: cmd[1].to_owned(),
+        indent: indent.to_string(),
+        lines: vec![line.to_string()],
     });
     Ok(())
 }
 
-pub fn parse<R>(buf_in: R, paops: &mut ParseOps) -> Result<TextTree, &'static str>
+pub fn parse<R>(mut buf_in: R, paops: &mut ParseOps) -> Result<TextTree>
 where
     R: BufRead,
 {
     if paops.max_depth != 0 && paops.level > paops.max_depth {
-        panic!("Maximum recursion depth!");
+        return Err(Error::Operation("Maximum recursion depth exceeded"));
     }
 
     let mut text = Vec::new(); // the vector of TextNodes
     let mut lineno = 0; // line number in source
     let mut pstack = Vec::new(); // stack
 
-    for line_in in buf_in.lines() {
-        let line = line_in.expect("read error");
+    // new lines end like the first line, or with a newline if it has no
+    // terminator, not like those of the file parsed before
+    paops.eol = "\n".to_string();
+
+    // lines are kept with their terminators, so they can be written back
+    // exactly as they were
+    loop {
+        let mut line = String::new();
+        let len = buf_in.read_line(&mut line).map_err(|e| Error::Io {
+            path: PathBuf::from(&paops.fname),
+            source: e,
+        })?;
+        if len == 0 {
+            break;
+        }
         lineno += 1;
+        if lineno == 1 && line.ends_with('\n') {
+            paops.eol = line_terminator(&line).to_string();
+        }
 
         if !line.trim_start().starts_with(&paops.left_sep) {
             // combine with previous
             if let Some(TextNode::Plain(last)) = text.last_mut() {
-                last.push('\n');
                 *last += &line;
                 continue;
             }
 
-            text.push(TextNode::Plain(line.clone()));
+            text.push(TextNode::Plain(line));
             continue;
         }
 
         // we have a command
+        let indent = &line[..line.len() - line.trim_start().len()];
         let mut trimmed = line.trim().replacen(&paops.left_sep, "", 1);
 
         // create a vector out of it
         if !trimmed.ends_with(&paops.right_sep) {
-            eprintln!(
-                "Parse: Right separator '{}' missing.\n\
-                 {}:{}:{}",
-                paops.right_sep, paops.fname, lineno, line
-            );
-            return Err("Parse error");
+            return Err(parse_error(
+                paops,
+                &line,
+                lineno,
+                &paops.left_sep,
+                format!("Right separator '{}' missing", paops.right_sep),
+            ));
         }
 
         let i = trimmed.len() - paops.right_sep.len();
         trimmed.truncate(i);
         let cmd: Vec<&str> = trimmed.split_whitespace().collect();
+        if cmd.is_empty() {
+            return Err(parse_error(
+                paops,
+                &line,
+                lineno,
+                &paops.left_sep,
+                "Empty command".to_string(),
+            ));
+        }
 
         let mut cmd_parsers: HashMap<&str, Parser> = HashMap::new();
         cmd_parsers.insert("DATA", parse_data);
@@ -460,62 +765,142 @@ where
         cmd_parsers.insert("STORED", parse_stored);
         match cmd_parsers.get(cmd[0]) {
             Some(parser) => {
-                parser(&cmd[1..], &line, lineno, paops, &mut pstack, &mut text)?;
+                parser(
+                    &cmd[1..],
+                    &line,
+                    lineno,
+                    indent,
+                    paops,
+                    &mut pstack,
+                    &mut text,
+                )?;
                 continue;
             }
             _ => {
-                eprintln!(
-                    "Parse: Unknown section '{}' at\n{}:{}:{}",
-                    cmd[0], paops.fname, lineno, line
-                );
-                return Err("Parse error");
+                return Err(parse_error(
+                    paops,
+                    &line,
+                    lineno,
+                    cmd[0],
+                    format!("Unknown section '{}'", cmd[0]),
+                ));
             }
         }
     }
 
-    if pstack.len() > 0 {
-        loop {
-            match pstack.pop() {
-                Some(TextNode::BeginEnd { keyw, txt: _ }) => {
-                    eprintln!("Parse: BEGIN {} without END.", keyw);
-                }
-                Some(TextNode::Encrypted {
-                    keyw,
-                    txt: _,
-                    extfields: _,
-                }) => {
-                    eprintln!("Parse: ENCRYPTED {} without END.", keyw);
-                }
-                _ => return Err("Unexpected end"),
-            }
-        }
+    // report the innermost unterminated section
+    let unterminated = match pstack.pop() {
+        Some(TextNode::BeginEnd { keyw, .. }) => Some(format!("BEGIN {} without END", keyw)),
+        Some(TextNode::Encrypted { keyw, .. }) => Some(format!("ENCRYPTED {} without END", keyw)),
+        _ => None,
+    };
+    if let Some(message) = unterminated {
+        return Err(Error::Parse {
+            file: paops.fname.clone(),
+            line: lineno,
+            column: 1,
+            message,
+        });
     }
 
     Ok(text)
 }
 
+// tokens of a command line between the separators
+fn command_tokens<'a>(line: &'a str, paops: &ParseOps) -> Vec<&'a str> {
+    let trimmed = line.trim();
+    if trimmed.len() < paops.left_sep.len() + paops.right_sep.len()
+        || !trimmed.starts_with(&paops.left_sep)
+        || !trimmed.ends_with(&paops.right_sep)
+    {
+        return Vec::new();
+    }
+    trimmed[paops.left_sep.len()..trimmed.len() - paops.right_sep.len()]
+        .split_whitespace()
+        .collect()
+}
+
+// write a command line, or the line it was parsed from if it has the same
+// tokens. Otherwise the line terminator of the original line is kept.
+fn write_command<W: Write>(
+    outw: &mut W,
+    indent: &str,
+    cmd: &str,
+    orig: Option<&String>,
+    paops: &ParseOps,
+) -> io::Result<()> {
+    let mut eol = paops.eol.as_str();
+    if let Some(orig) = orig {
+        if command_tokens(orig, paops) == cmd.split_whitespace().collect::<Vec<&str>>() {
+            return write!(outw, "{}", orig);
+        }
+        eol = line_terminator(orig);
+    }
+    write!(
+        outw,
+        "{}{} {} {}{}",
+        indent, paops.left_sep, cmd, paops.right_sep, eol
+    )
+}
+
+// the line a segment with contents was parsed from, unless it was the last
+// line of the file without a terminator, as STORED may have been
+fn opening_line(lines: &[String]) -> Option<&String> {
+    lines
+        .get(0)
+        .filter(|line| !line_terminator(line).is_empty())
+}
+
+// the data in DATA lines as parsed, if they are all valid
+fn decode_data_lines(lines: &[String], paops: &ParseOps) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    for line in lines {
+        for field in command_tokens(line, paops).iter().skip(1) {
+            data.append(&mut utils::base64_decode(field).ok()?);
+        }
+    }
+    Some(data)
+}
+
+pub fn encrypted_command(
+    keyw: &str,
+    cas: Option<&str>,
+    extfields: &BTreeMap<String, String>,
+) -> String {
+    let mut cmd = format!("ENCRYPTED {}", keyw);
+    if let Some(cas) = cas {
+        cmd += &format!(" {}", cas);
+    }
+    for (key, value) in extfields.iter() {
+        cmd += &format!(" {}:{}", key, value);
+    }
+    cmd
+}
+
 // recursive unparser
 
-pub fn tree_write<W: Write>(outw: &mut W, text: &TextTree, paops: &mut ParseOps) {
+pub fn tree_write<W: Write>(outw: &mut W, text: &TextTree, paops: &mut ParseOps) -> io::Result<()> {
     for elem in text {
         match elem {
             // Plain chunk of text
             TextNode::Plain(line) => {
-                writeln!(outw, "{}", line).unwrap();
+                write!(outw, "{}", line)?;
             }
 
             // BEGIN-END block
-            TextNode::BeginEnd { keyw, txt } => {
-                writeln!(
-                    outw,
-                    "{} BEGIN {} {}",
-                    paops.left_sep, keyw, paops.right_sep
-                )
-                .unwrap();
+            TextNode::BeginEnd {
+                keyw,
+                txt,
+                indent,
+                lines,
+            } => {
+                let begin = format!("BEGIN {}", keyw);
+                write_command(outw, indent, &begin, opening_line(lines), paops)?;
                 paops.level += 1;
-                tree_write(outw, txt, paops);
+                tree_write(outw, txt, paops)?;
                 paops.level -= 1;
-                writeln!(outw, "{} END {} {}", paops.left_sep, keyw, paops.right_sep).unwrap();
+                let end = format!("END {}", keyw);
+                write_command(outw, indent, &end, lines.get(1), paops)?;
             }
 
             // ENCRYPTED block
@@ -523,67 +908,76 @@ pub fn tree_write<W: Write>(outw: &mut W, text: &TextTree, paops: &mut ParseOps)
                 keyw,
                 txt,
                 ref extfields,
+                indent,
+                lines,
             } => {
-                write!(outw, "{} ENCRYPTED {}", paops.left_sep, keyw).unwrap();
-                if let TextNode::Stored { keyw: _, ref cas } = txt[0] {
+                if let TextNode::Stored { ref cas, .. } = txt[0] {
                     // Encrypted+Stored
-                    write!(outw, " {}", cas).unwrap();
-                    for (key, value) in extfields.iter() {
-                        write!(outw, " {}:{}", key, value).unwrap();
-                    }
-                    writeln!(outw, " {}", paops.right_sep).unwrap();
+                    let cmd = encrypted_command(keyw, Some(cas), extfields);
+                    write_command(outw, indent, &cmd, lines.get(0), paops)?;
                 } else {
                     // Encrypted
-                    for (key, value) in extfields.iter() {
-                        write!(outw, " {}:{}", key, value).unwrap();
-                    }
-                    writeln!(outw, " {}", paops.right_sep).unwrap();
+                    let cmd = encrypted_command(keyw, None, extfields);
+                    write_command(outw, indent, &cmd, opening_line(lines), paops)?;
                     paops.level += 1;
-                    tree_write(outw, txt, paops);
+                    tree_write(outw, txt, paops)?;
                     paops.level -= 1;
-                    writeln!(outw, "{} END {} {}", paops.left_sep, keyw, paops.right_sep).unwrap();
+                    let end = format!("END {}", keyw);
+                    write_command(outw, indent, &end, lines.get(1), paops)?;
                 }
             }
 
             // STORED
-            TextNode::Stored { keyw, cas } => {
-                writeln!(
-                    outw,
-                    "{} STORED {} {} {}",
-                    paops.left_sep, keyw, cas, paops.right_sep
-                )
-                .unwrap();
+            TextNode::Stored {
+                keyw,
+                cas,
+                indent,
+                lines,
+            } => {
+                let cmd = format!("STORED {} {}", keyw, cas);
+                write_command(outw, indent, &cmd, lines.get(0), paops)?;
             }
             // DATA
-            TextNode::Data(data) => {
+            TextNode::Data {
+                data,
+                indent,
+                lines,
+            } => {
+                // keep the original lines, unless the data has changed
+                if !lines.is_empty() && decode_data_lines(lines, paops).as_ref() == Some(data) {
+                    for line in lines {
+                        write!(outw, "{}", line)?;
+                    }
+                    continue;
+                }
                 for line in data.chunks(DATA_BYTES_PER_LINE) {
-                    writeln!(
-                        outw,
-                        "{} DATA {} {}",
-                        paops.left_sep,
-                        utils::base64_encode(line).unwrap(),
-                        paops.right_sep
-                    )
-                    .unwrap();
+                    let cmd = format!("DATA {}", utils::base64_encode(line).unwrap());
+                    write_command(outw, indent, &cmd, None, paops)?;
                 }
             }
         }
     }
+    Ok(())
 }
 
 // perform ops
 
-pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTree, &'static str> {
+pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTree> {
     let mut text_out = Vec::new();
 
     if paops.max_depth != 0 && paops.level > paops.max_depth {
-        panic!("Maximum recursion depth!");
+        return Err(Error::Operation("Maximum recursion depth exceeded"));
     }
 
     for elem in text_in {
         match elem {
             // BEGIN-END
-            TextNode::BeginEnd { ref keyw, ref txt } => {
+            TextNode::BeginEnd {
+                ref keyw,
+                ref txt,
+                ref indent,
+                ref lines,
+            } => {
                 // encrypt it ?
                 if paops.encrypt.contains(keyw) {
                     paops.level += 1;
@@ -592,27 +986,43 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
 
                     // get blob
                     let pt = tree_to_blob(&block, paops);
-                    // get password
-                    let (newpass, pass) = match paops.passwords.get(keyw) {
-                        Some(pass) => (false, pass.to_string()),
-                        None => (true, prot::get_password(&keyw, true)),
-                    };
-                    if newpass {
-                        paops
-                            .passwords
-                            .insert(keyw.clone().to_string(), pass.clone());
-                    }
+
+                    // keep an earlier encryption of the same plaintext
+                    let previous = paops.reuse.get_mut(keyw).and_then(|previous| {
+                        let index = previous.iter().position(|prev| prev.pt == pt)?;
+                        Some(previous.remove(index))
+                    });
 
                     // encrypt
-                    let (ct, extfields) = prot::encrypt(
-                        pt,
-                        &pass,
-                        &paops.rng,
-                        &paops.pbkdfopts,
-                        &paops.cipheropts,
-                        &mut paops.pbkdf_cache,
-                        &paops.policy,
-                    )?;
+                    let (ct, extfields) = if let Some(previous) = previous {
+                        (previous.ct, previous.extfields)
+                    } else if let Some(recipients) = paops.recipients.get(keyw).cloned() {
+                        let dek = prot::new_data_key(&paops.rng, &paops.cipheropts)?;
+                        let mut extfields = BTreeMap::new();
+                        wrap_data_key(keyw, &dek, &recipients, &mut extfields, paops)?;
+                        let ct = prot::encrypt_with_data_key(
+                            pt,
+                            keyw,
+                            &dek,
+                            &paops.rng,
+                            &paops.cipheropts,
+                            &paops.policy,
+                            &mut extfields,
+                        )?;
+                        (ct, extfields)
+                    } else {
+                        let pass = password(keyw, true, paops)?;
+                        prot::encrypt(
+                            pt,
+                            keyw,
+                            &pass,
+                            &paops.rng,
+                            &paops.pbkdfopts,
+                            &paops.cipheropts,
+                            &mut paops.pbkdf_cache,
+                            &paops.policy,
+                        )?
+                    };
 
                     // also store it (store at CAS) ?
                     let node = if paops.store.contains(keyw) {
@@ -620,14 +1030,22 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
                         vec![TextNode::Stored {
                             keyw: "ct".to_string(),
                             cas: hexhash,
+                            indent: indent.to_string(),
+                            lines: Vec::new(),
                         }]
                     } else {
-                        vec![TextNode::Data(ct)]
+                        vec![TextNode::Data {
+                            data: ct,
+                            indent: indent.to_string(),
+                            lines: Vec::new(),
+                        }]
                     };
                     text_out.push(TextNode::Encrypted {
                         keyw: keyw.to_string(),
                         txt: node,
                         extfields,
+                        indent: indent.to_string(),
+                        lines: lines.clone(),
                     });
                     continue;
                 }
@@ -643,6 +1061,8 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
                     text_out.push(TextNode::Stored {
                         keyw: keyw.to_string(),
                         cas: hexhash,
+                        indent: indent.to_string(),
+                        lines: lines.clone(),
                     });
                     continue;
                 };
@@ -655,6 +1075,8 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
                 text_out.push(TextNode::BeginEnd {
                     keyw: keyw.to_string(),
                     txt: block,
+                    indent: indent.to_string(),
+                    lines: lines.clone(),
                 });
                 continue;
             }
@@ -664,45 +1086,12 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
                 ref keyw,
                 ref txt,
                 ref extfields,
+                ref indent,
+                ref lines,
             } => {
                 // decrypt it
                 if paops.decrypt.contains(keyw) {
-                    // get ciphertext
-                    let ct = match txt[0] {
-                        TextNode::Data(ref data) => data.to_vec(),
-                        TextNode::Stored {
-                            keyw: _,
-                            cas: ref hexhash,
-                        } => cas::load(&hexhash, paops)?,
-                        _ => panic!("No data in ENCRYPTED."),
-                    };
-
-                    // get password
-                    let (newpass, pass) = match paops.passwords.get(keyw) {
-                        Some(pass) => (false, pass.to_string()),
-                        None => (true, prot::get_password(keyw, false)),
-                    };
-                    if newpass {
-                        paops
-                            .passwords
-                            .insert(keyw.clone().to_string(), pass.clone());
-                    }
-
-                    // decrypt
-                    let pt = match prot::decrypt(
-                        ct,
-                        &pass,
-                        &extfields.get("pbkdf"),
-                        &extfields.get("cipher"),
-                        &mut paops.pbkdf_cache,
-                        &paops.policy,
-                    ) {
-                        Ok(ct) => ct.to_vec(),
-                        Err(e) => {
-                            eprintln!("Error decrypting {}: {}.", &keyw, e);
-                            return Err(e);
-                        }
-                    };
+                    let pt = decrypt_segment(keyw, txt, extfields, paops)?;
 
                     // parse to tree
                     let mut block = blob_to_tree(pt, "decrypted".to_string(), &mut paops)?;
@@ -714,57 +1103,91 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
                     text_out.push(TextNode::BeginEnd {
                         keyw: keyw.to_string(),
                         txt: block,
+                        indent: indent.to_string(),
+                        lines: lines.clone(),
                     });
                     continue;
                 } else {
+                    // re-encrypt with a new password or the current settings
+                    let (txt, mut extfields) = if paops.rekey.contains(keyw) {
+                        rekey(keyw, txt, extfields, paops)
+                            .map_err(|e| Error::segment("rekeying", keyw, e))?
+                    } else if paops.migrate.contains(keyw) {
+                        migrate(keyw, txt, extfields, paops)
+                            .map_err(|e| Error::segment("migrating", keyw, e))?
+                    } else {
+                        (txt.clone(), extfields.clone())
+                    };
+
+                    // add or remove recipients, the ciphertext stays as is
+                    if paops.grant.contains(keyw) || paops.revoke.contains_key(keyw) {
+                        change_recipients(keyw, &mut extfields, paops)
+                            .map_err(|e| Error::segment("changing recipients of", keyw, e))?;
+                    }
+
                     // store (store) ciphertext
                     if paops.store.contains(keyw) {
-                        let hexhash = match txt[0] {
-                            TextNode::Data(ref data) => cas::save(data.to_vec(), paops)?,
-                            TextNode::Stored {
-                                keyw: _,
-                                cas: ref hexhash,
-                            } => hexhash.to_string(),
+                        let node = match txt[0] {
+                            TextNode::Data { ref data, .. } => vec![TextNode::Stored {
+                                keyw: "ct".to_string(),
+                                cas: cas::save(data.to_vec(), paops)?,
+                                indent: indent.to_string(),
+                                lines: Vec::new(),
+                            }],
+                            TextNode::Stored { .. } => txt.clone(),
                             _ => panic!("No data in ENCRYPTED."),
                         };
-                        let node = vec![TextNode::Stored {
-                            keyw: "ct".to_string(),
-                            cas: hexhash,
-                        }];
                         text_out.push(TextNode::Encrypted {
                             keyw: keyw.to_string(),
                             txt: node,
-                            extfields: BTreeMap::new(),
+                            extfields,
+                            indent: indent.to_string(),
+                            lines: lines.clone(),
                         });
                         continue;
                     }
 
                     // fetch (include) ciphertext
                     if paops.fetch.contains(keyw) {
-                        let ct = match txt[0] {
-                            TextNode::Data(ref data) => data.to_vec(),
+                        let node = match txt[0] {
+                            TextNode::Data { .. } => txt.clone(),
                             TextNode::Stored {
-                                keyw: _,
-                                cas: ref hexhash,
-                            } => cas::load(&hexhash, paops)?,
+                                cas: ref hexhash, ..
+                            } => vec![TextNode::Data {
+                                data: cas::load(&hexhash, paops)?,
+                                indent: indent.to_string(),
+                                lines: Vec::new(),
+                            }],
                             _ => panic!("No data in ENCRYPTED."),
                         };
-                        let node = vec![TextNode::Data(ct)];
 
                         text_out.push(TextNode::Encrypted {
                             keyw: keyw.to_string(),
                             txt: node,
-                            extfields: BTreeMap::new(),
+                            extfields,
+                            indent: indent.to_string(),
+                            lines: lines.clone(),
                         });
                         continue;
                     };
-                }
 
-                text_out.push(elem.clone());
+                    text_out.push(TextNode::Encrypted {
+                        keyw: keyw.to_string(),
+                        txt,
+                        extfields,
+                        indent: indent.to_string(),
+                        lines: lines.clone(),
+                    });
+                }
             }
 
             // STORED
-            TextNode::Stored { ref keyw, ref cas } => {
+            TextNode::Stored {
+                ref keyw,
+                ref cas,
+                ref indent,
+                ref lines,
+            } => {
                 // fetch it ?
                 if paops.fetch.contains(keyw) {
                     let blob = cas::load(&cas, paops)?;
@@ -777,6 +1200,8 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
                     text_out.push(TextNode::BeginEnd {
                         keyw: keyw.to_string(),
                         txt: block,
+                        indent: indent.to_string(),
+                        lines: lines.clone(),
                     });
                     continue;
                 }
@@ -793,19 +1218,545 @@ pub fn transform(text_in: &TextTree, mut paops: &mut ParseOps) -> Result<TextTre
 
 // convenience functions
 
-fn blob_to_tree(
-    data: Vec<u8>,
-    path: String,
-    mut paops: &mut ParseOps,
-) -> Result<TextTree, &'static str> {
-    paops.fname = path.clone();
-    let tree = parse(Cursor::new(data), &mut paops)?;
-    Ok(tree)
+// password for a keyword (or KEYWORD/LABEL), asked for once
+fn password(name: &str, rep: bool, paops: &mut ParseOps) -> Result<String> {
+    if let Some(pass) = paops.passwords.get(name) {
+        return Ok(pass.to_string());
+    }
+    let shared = paops.shared.clone();
+    let mut shared = Shared::lock(&shared);
+    if let Some(pass) = given_password(name, paops, &mut shared)? {
+        return Ok(pass);
+    }
+    if !paops.interactive {
+        return Err(Error::MissingPassword(name.to_string()));
+    }
+    let pass = prot::get_password(name, rep);
+    if let Some(agent) = paops.agent.as_ref() {
+        let _ = agent.add_password(name, &pass);
+    }
+    paops.passwords.insert(name.to_string(), pass.clone());
+    if let Some(shared) = shared.as_mut() {
+        shared.passwords.insert(name.to_string(), pass.clone());
+    }
+    // a repeated password is stored right away, others once they worked
+    if rep {
+        keep_password(name, &pass, paops);
+    } else {
+        paops.unconfirmed.insert(name.to_string());
+    }
+    Ok(pass)
+}
+
+// store a password that was asked for in the keyring
+fn keep_password(name: &str, pass: &str, paops: &mut ParseOps) {
+    let shared = paops.shared.clone();
+    let mut shared = Shared::keyring(&shared);
+    if let Some(keyring) = paops.keyring.as_mut().or(shared.as_deref_mut()) {
+        let stored = keyring.store(name, pass, paops.interactive, &paops.rng, &paops.policy);
+        if let Err(e) = stored {
+            paops.log.push(format!("Warning: {}", e));
+        }
+    }
+}
+
+// the plaintext of an ENCRYPTED segment
+pub fn decrypt_segment(
+    keyw: &str,
+    txt: &TextTree,
+    extfields: &BTreeMap<String, String>,
+    paops: &mut ParseOps,
+) -> Result<Vec<u8>> {
+    // get ciphertext
+    let ct = match txt[0] {
+        TextNode::Data { ref data, .. } => data.to_vec(),
+        TextNode::Stored {
+            cas: ref hexhash, ..
+        } => cas::load(&hexhash, paops)?,
+        _ => panic!("No data in ENCRYPTED."),
+    };
+
+    // decrypt
+    let result = if prot::has_recipients(extfields) {
+        unwrap_data_key(keyw, extfields, paops).and_then(|dek| {
+            prot::decrypt_with_data_key(
+                ct,
+                keyw,
+                &dek,
+                extfields,
+                &paops.cipheropts.doc_id,
+                &paops.policy,
+            )
+        })
+    } else {
+        let pass = password(keyw, false, paops)?;
+        let result = prot::decrypt(
+            ct,
+            keyw,
+            &pass,
+            extfields,
+            &paops.cipheropts.doc_id,
+            &mut paops.pbkdf_cache,
+            &paops.policy,
+        );
+        if result.is_ok() && paops.unconfirmed.remove(keyw) {
+            let shared = paops.shared.clone();
+            let _shared = Shared::lock(&shared);
+            keep_password(keyw, &pass, paops);
+        }
+        result
+    };
+    result.map_err(|e| Error::segment("decrypting", keyw, e))
+}
+
+// password for a keyword (or KEYWORD/LABEL) that was given, or can be read
+// from its source, the agent or the keyring without asking (except for the
+// passphrase of the keystore)
+fn given_password(
+    name: &str,
+    paops: &mut ParseOps,
+    shared: &mut Option<MutexGuard<Passwords>>,
+) -> Result<Option<String>> {
+    if let Some(pass) = paops.passwords.get(name) {
+        return Ok(Some(pass.to_string()));
+    }
+    let pass = if let Some(pass) = shared
+        .as_ref()
+        .and_then(|shared| shared.passwords.get(name))
+    {
+        pass.to_string()
+    } else if let Some(source) = paops.key_sources.get(name) {
+        source.read(name)?
+    } else if let Some(pass) = paops.agent.as_ref().and_then(|agent| agent.password(name)) {
+        pass
+    } else if let Some(keyring) = paops
+        .keyring
+        .as_mut()
+        .or(Shared::keyring(&paops.shared).as_deref_mut())
+    {
+        match keyring.password(name, paops.interactive, &paops.policy)? {
+            Some(pass) => pass,
+            None => return Ok(None),
+        }
+    } else {
+        return Ok(None);
+    };
+    paops.passwords.insert(name.to_string(), pass.clone());
+    if let Some(shared) = shared.as_mut() {
+        shared.passwords.insert(name.to_string(), pass.clone());
+    }
+    Ok(Some(pass))
+}
+
+// new password for a keyword (or KEYWORD/LABEL) when rekeying
+fn new_password(name: &str, paops: &mut ParseOps) -> Result<String> {
+    if let Some(pass) = paops.new_passwords.get(name) {
+        return Ok(pass.to_string());
+    }
+    let shared = paops.shared.clone();
+    let mut shared = Shared::lock(&shared);
+    let pass = match shared
+        .as_ref()
+        .and_then(|shared| shared.new_passwords.get(name))
+    {
+        Some(pass) => pass.to_string(),
+        None if !paops.interactive => return Err(Error::MissingPassword(name.to_string())),
+        None => prot::get_new_password(name),
+    };
+    paops.new_passwords.insert(name.to_string(), pass.clone());
+    if let Some(shared) = shared.as_mut() {
+        shared.new_passwords.insert(name.to_string(), pass.clone());
+    }
+    Ok(pass)
+}
+
+// re-encrypt envelope encrypted segments by wrapping the data key for the
+// password recipients again, with new passwords. The data key and the
+// ciphertext stay as is. Other segments are re-encrypted from scratch.
+fn rekey(
+    keyw: &str,
+    txt: &TextTree,
+    extfields: &BTreeMap<String, String>,
+    paops: &mut ParseOps,
+) -> Result<(TextTree, BTreeMap<String, String>)> {
+    if !prot::has_recipients(extfields) {
+        return reencrypt(keyw, txt, extfields, true, paops);
+    }
+    let dek = unwrap_data_key(keyw, extfields, paops)?;
+    let mut extfields = extfields.clone();
+    let labels: Vec<String> = extfields
+        .keys()
+        .filter(|key| key.starts_with(prot::PASSWORD_EXTFIELD_PREFIX))
+        .map(|key| key[prot::PASSWORD_EXTFIELD_PREFIX.len()..].to_string())
+        .collect();
+    for label in labels {
+        let pass = new_password(&format!("{}/{}", keyw, label), paops)?;
+        let value = prot::wrap_for_password(
+            &dek,
+            &pass,
+            &paops.rng,
+            &paops.pbkdfopts,
+            &mut paops.pbkdf_cache,
+            &paops.policy,
+        )?;
+        extfields.insert(prot::PASSWORD_EXTFIELD_PREFIX.to_string() + &label, value);
+    }
+    Ok((txt.clone(), extfields))
+}
+
+// re-encrypt segments using legacy key derivation, or algorithms that the
+// policy or the policy of the current settings consider weak, with the
+// current settings and the same password, and report what changed
+fn migrate(
+    keyw: &str,
+    txt: &TextTree,
+    extfields: &BTreeMap<String, String>,
+    paops: &mut ParseOps,
+) -> Result<(TextTree, BTreeMap<String, String>)> {
+    let mut changes = Vec::new();
+    if !prot::has_recipients(extfields) {
+        let pbkdf = prot::pbkdf_alg(extfields)?;
+        if pbkdf != paops.pbkdfopts.alg
+            && (pbkdf == "legacy"
+                || paops.policy.is_weak_pbkdf(&pbkdf)
+                || paops.defaults.is_weak_pbkdf(&pbkdf))
+        {
+            changes.push(format!("pbkdf {} -> {}", pbkdf, paops.pbkdfopts.alg));
+        }
+        let cipher = prot::cipher_alg(extfields)?;
+        if cipher != paops.cipheropts.alg
+            && (paops.policy.is_weak_cipher(&cipher) || paops.defaults.is_weak_cipher(&cipher))
+        {
+            changes.push(format!("cipher {} -> {}", cipher, paops.cipheropts.alg));
+        }
+    }
+    if changes.is_empty() {
+        return Ok((txt.clone(), extfields.clone()));
+    }
+    let migrated = reencrypt(keyw, txt, extfields, false, paops)?;
+    if paops.verbose {
+        let message = format!(
+            "Migrated {} in {}: {}",
+            keyw,
+            paops.fname,
+            changes.join(", ")
+        );
+        paops.log.push(message);
+    }
+    Ok(migrated)
+}
+
+// decrypt with the current password and encrypt again with the current
+// settings, and a new password if rekeying, all in memory. Ciphertext kept
+// in the CAS is saved back to the CAS.
+fn reencrypt(
+    keyw: &str,
+    txt: &TextTree,
+    extfields: &BTreeMap<String, String>,
+    rekey: bool,
+    paops: &mut ParseOps,
+) -> Result<(TextTree, BTreeMap<String, String>)> {
+    let (ct, stored, indent) = match txt[0] {
+        TextNode::Data {
+            ref data,
+            ref indent,
+            ..
+        } => (data.to_vec(), false, indent.to_string()),
+        TextNode::Stored {
+            cas: ref hexhash,
+            ref indent,
+            ..
+        } => (cas::load(&hexhash, paops)?, true, indent.to_string()),
+        _ => panic!("No data in ENCRYPTED."),
+    };
+    let pass = password(keyw, false, paops)?;
+    let pt = prot::decrypt(
+        ct,
+        keyw,
+        &pass,
+        extfields,
+        &paops.cipheropts.doc_id,
+        &mut paops.pbkdf_cache,
+        &paops.policy,
+    )?;
+
+    // keep the associated data binding
+    let mut cipheropts = paops.cipheropts.clone();
+    if extfields.contains_key(prot::AD_EXTFIELD) {
+        cipheropts.ad = true;
+        if cipheropts.doc_id.is_none() {
+            cipheropts.doc_id = prot::doc_id(extfields)?;
+        }
+    }
+    let newpass = if rekey {
+        new_password(keyw, paops)?
+    } else {
+        pass
+    };
+    let (ct, extfields) = prot::encrypt(
+        pt,
+        keyw,
+        &newpass,
+        &paops.rng,
+        &paops.pbkdfopts,
+        &cipheropts,
+        &mut paops.pbkdf_cache,
+        &paops.policy,
+    )?;
+    let node = if stored {
+        TextNode::Stored {
+            keyw: "ct".to_string(),
+            cas: cas::save(ct, paops)?,
+            indent,
+            lines: Vec::new(),
+        }
+    } else {
+        TextNode::Data {
+            data: ct,
+            indent,
+            lines: Vec::new(),
+        }
+    };
+    Ok((vec![node], extfields))
+}
+
+// grant and revoke recipients of an envelope encrypted segment
+fn change_recipients(
+    keyw: &str,
+    extfields: &mut BTreeMap<String, String>,
+    paops: &mut ParseOps,
+) -> Result<()> {
+    if !prot::has_recipients(extfields) {
+        return Err(Error::Operation("Segment is not envelope encrypted"));
+    }
+    if paops.grant.contains(keyw) {
+        let dek = unwrap_data_key(keyw, extfields, paops)?;
+        let recipients = paops.recipients.get(keyw).cloned().unwrap_or_default();
+        wrap_data_key(keyw, &dek, &recipients, extfields, paops)?;
+    }
+    if let Some(names) = paops.revoke.get(keyw) {
+        for name in names {
+            if extfields.remove(name).is_none() {
+                paops
+                    .log
+                    .push(format!("Warning: {} is not a recipient of {}.", name, keyw));
+            }
+        }
+        if !prot::has_recipients(extfields) {
+            return Err(Error::Operation("Refusing to remove the last recipient"));
+        }
+    }
+    Ok(())
+}
+
+// wrap the data key for any recipients not already present
+fn wrap_data_key(
+    keyw: &str,
+    dek: &[u8],
+    recipients: &[Recipient],
+    extfields: &mut BTreeMap<String, String>,
+    paops: &mut ParseOps,
+) -> Result<()> {
+    for recipient in recipients {
+        let name = recipient.name(&paops.policy)?;
+        if extfields.contains_key(&name) {
+            continue;
+        }
+        let value = match recipient {
+            Recipient::PublicKey(pk) => prot::wrap_for_pubkey(dek, pk, &paops.rng, &paops.policy)?,
+            Recipient::Password(label) => {
+                let pass = password(&format!("{}/{}", keyw, label), true, paops)?;
+                prot::wrap_for_password(
+                    dek,
+                    &pass,
+                    &paops.rng,
+                    &paops.pbkdfopts,
+                    &mut paops.pbkdf_cache,
+                    &paops.policy,
+                )?
+            }
+        };
+        extfields.insert(name, value);
+    }
+    Ok(())
+}
+
+// recover the data key with an identity or the password for a label
+fn unwrap_data_key(
+    keyw: &str,
+    extfields: &BTreeMap<String, String>,
+    paops: &mut ParseOps,
+) -> Result<Vec<u8>> {
+    for identity in &paops.identities {
+        if let Some(dek) = prot::unwrap_with_identity(extfields, identity, &paops.policy)? {
+            return Ok(dek);
+        }
+    }
+    let labels: Vec<(String, &String)> = extfields
+        .iter()
+        .filter(|(key, _)| key.starts_with(prot::PASSWORD_EXTFIELD_PREFIX))
+        .map(|(key, value)| {
+            let label = &key[prot::PASSWORD_EXTFIELD_PREFIX.len()..];
+            (format!("{}/{}", keyw, label), value)
+        })
+        .collect();
+    // a password given up front decides, otherwise ask for each label
+    let shared = paops.shared.clone();
+    let mut shared = Shared::lock(&shared);
+    for (name, value) in &labels {
+        if let Some(pass) = given_password(name, paops, &mut shared)? {
+            drop(shared);
+            return prot::unwrap_with_password(value, &pass, &mut paops.pbkdf_cache, &paops.policy);
+        }
+    }
+    if !paops.interactive {
+        return Err(Error::Operation(
+            "No identity or password matches the recipients",
+        ));
+    }
+    for (name, value) in &labels {
+        let pass = prot::get_password(name, false);
+        if let Ok(dek) =
+            prot::unwrap_with_password(value, &pass, &mut paops.pbkdf_cache, &paops.policy)
+        {
+            if let Some(shared) = shared.as_mut() {
+                shared.passwords.insert(name.to_string(), pass.clone());
+            }
+            paops.passwords.insert(name.to_string(), pass);
+            return Ok(dek);
+        }
+    }
+    Err(Error::Operation(
+        "No identity or password matches the recipients",
+    ))
+}
+
+// remember the ENCRYPTED segments in text that are to be encrypted again,
+// have no recipients and were made with the current settings, so that
+// segments whose plaintext is unchanged keep their ciphertext. Those that
+// do not decrypt with the current password are left out.
+pub fn reuse_from(text: &TextTree, paops: &mut ParseOps) -> Result<()> {
+    for node in text {
+        match node {
+            TextNode::BeginEnd { ref txt, .. } => reuse_from(txt, paops)?,
+            TextNode::Encrypted {
+                ref keyw,
+                ref txt,
+                ref extfields,
+                ..
+            } => {
+                if !paops.encrypt.contains(keyw)
+                    || prot::has_recipients(extfields)
+                    || !same_settings(extfields, paops)
+                {
+                    continue;
+                }
+                let ct = match txt.first() {
+                    Some(TextNode::Data { ref data, .. }) => data.to_vec(),
+                    Some(TextNode::Stored {
+                        cas: ref hexhash, ..
+                    }) => match cas::load(hexhash, paops) {
+                        Ok(ct) => ct,
+                        Err(_) => continue,
+                    },
+                    _ => continue,
+                };
+                let pass = password(keyw, true, paops)?;
+                let pt = match prot::decrypt(
+                    ct.clone(),
+                    keyw,
+                    &pass,
+                    extfields,
+                    &paops.cipheropts.doc_id,
+                    &mut paops.pbkdf_cache,
+                    &paops.policy,
+                ) {
+                    Ok(pt) => pt,
+                    Err(_) => continue,
+                };
+
+                // and the segments within
+                let block = blob_to_tree(pt.clone(), "decrypted".to_string(), paops)?;
+                reuse_from(&block, paops)?;
+
+                paops
+                    .reuse
+                    .entry(keyw.to_string())
+                    .or_insert_with(Vec::new)
+                    .push(PreviousEncryption {
+                        pt,
+                        ct,
+                        extfields: extfields.clone(),
+                    });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// whether a segment was encrypted the way it would be now. Explicit PBKDF
+// params must match those of the segment, and a PBKDF time other than the
+// default of the policy may give other params, so it is never reused.
+fn same_settings(extfields: &BTreeMap<String, String>, paops: &ParseOps) -> bool {
+    let same_pbkdf = match extfields.get("pbkdf") {
+        Some(pbkdf) => match prot::pbkdf_options_from_phc(pbkdf) {
+            Ok(previous) => {
+                let salt_len = previous.salt.as_ref().map_or(0, |salt| salt.len());
+                salt_len == paops.pbkdfopts.saltlen
+                    && match paops.pbkdfopts.params {
+                        Some(ref params) => previous.params.as_ref() == Some(params),
+                        None => paops.pbkdfopts.msec == Some(paops.policy.default_pbkdf_millis()),
+                    }
+            }
+            Err(_) => false,
+        },
+        None => true,
+    };
+    same_pbkdf
+        && prot::pbkdf_alg(extfields).ok().as_ref() == Some(&paops.pbkdfopts.alg)
+        && prot::cipher_alg(extfields).ok().as_ref() == Some(&paops.cipheropts.alg)
+        && extfields.contains_key(prot::AD_EXTFIELD) == paops.cipheropts.ad
+        && prot::doc_id(extfields).ok().as_ref() == Some(&paops.cipheropts.doc_id)
+}
+
+// make new encryptions use the settings of an ENCRYPTED segment
+pub fn adopt_settings(extfields: &BTreeMap<String, String>, paops: &mut ParseOps) -> Result<()> {
+    if !prot::has_recipients(extfields) {
+        paops.pbkdfopts = match extfields.get("pbkdf") {
+            Some(pbkdf) => {
+                let previous = prot::pbkdf_options_from_phc(pbkdf)?;
+                PBKDFOptions {
+                    saltlen: previous.salt.as_ref().map_or(0, |salt| salt.len()),
+                    salt: None,
+                    ..previous
+                }
+            }
+            None => PBKDFOptions {
+                alg: "legacy".to_string(),
+                ..paops.pbkdfopts.clone()
+            },
+        };
+    }
+    paops.cipheropts.alg = prot::cipher_alg(extfields)?;
+    paops.cipheropts.ad = extfields.contains_key(prot::AD_EXTFIELD);
+    paops.cipheropts.doc_id = prot::doc_id(extfields)?;
+    Ok(())
+}
+
+pub fn blob_to_tree(data: Vec<u8>, path: String, mut paops: &mut ParseOps) -> Result<TextTree> {
+    let fname = std::mem::replace(&mut paops.fname, path);
+    let eol = paops.eol.clone();
+    let tree = parse(Cursor::new(data), &mut paops);
+    paops.fname = fname;
+    paops.eol = eol;
+    tree
 }
 
 fn tree_to_blob(text: &TextTree, mut paops: &mut ParseOps) -> Vec<u8> {
     let mut blob = Vec::new();
-    tree_write(&mut blob, text, &mut paops);
+    // writing to memory does not fail
+    tree_write(&mut blob, text, &mut paops).unwrap();
     blob
 }
 
@@ -837,6 +1788,39 @@ mod tests {
         (tree, paops, casdir)
     }
 
+    // test that parse errors point at the offending token
+    #[test]
+    fn parse_error_location() {
+        let mut paops = ParseOps {
+            fname: "broken.ept".to_string(),
+            ..ParseOps::new(Box::new(CryptoPolicyDefault {}))
+        };
+        let ept = "text\n// <( BEGIN GEHEIM )>\n  // <( END Agent_007 )>\n";
+        match parse(Cursor::new(ept), &mut paops) {
+            Err(Error::Parse {
+                file,
+                line,
+                column,
+                message,
+            }) => {
+                assert_eq!(file, "broken.ept");
+                assert_eq!(line, 3);
+                assert_eq!(column, 13);
+                assert_eq!(message, "END mismatch (expected 'GEHEIM')");
+            }
+            _ => panic!("expected a parse error"),
+        }
+
+        let ept = "// <( BEGIN GEHEIM )>\nsecret\n";
+        match parse(Cursor::new(ept), &mut paops) {
+            Err(Error::Parse { line, message, .. }) => {
+                assert_eq!(line, 2);
+                assert_eq!(message, "BEGIN GEHEIM without END");
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
+
     // test that we can call transform on this file without any options
     // set and it will remain unchanged
     #[test]
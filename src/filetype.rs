@@ -0,0 +1,309 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	comment separators by file type
+
+use phf::phf_map;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub static COMMENT_SEPARATORS: phf::Map<&'static str, (&'static str, &'static str)> = phf_map! {
+    // comment style   left sep, right sep
+    "slash" => ("// <(", ")>"),
+    "hash" => ("# <(", ")>"),
+    "html" => ("<!-- <(", ")> -->"),
+    "percent" => ("% <(", ")>"),
+    "dash" => ("-- <(", ")>"),
+    "semicolon" => ("; <(", ")>"),
+    "block" => ("/* <(", ")> */"),
+};
+
+// file types as named by modelines and interpreters
+pub static FILETYPE_COMMENTS: phf::Map<&'static str, &'static str> = phf_map! {
+    "asciidoc" => "slash",
+    "c" => "slash",
+    "cpp" => "slash",
+    "cs" => "slash",
+    "go" => "slash",
+    "java" => "slash",
+    "javascript" => "slash",
+    "kotlin" => "slash",
+    "node" => "slash",
+    "php" => "slash",
+    "rust" => "slash",
+    "scala" => "slash",
+    "swift" => "slash",
+    "typescript" => "slash",
+    "awk" => "hash",
+    "bash" => "hash",
+    "cmake" => "hash",
+    "conf" => "hash",
+    "dockerfile" => "hash",
+    "make" => "hash",
+    "perl" => "hash",
+    "python" => "hash",
+    "r" => "hash",
+    "ruby" => "hash",
+    "sh" => "hash",
+    "tcl" => "hash",
+    "toml" => "hash",
+    "yaml" => "hash",
+    "zsh" => "hash",
+    "html" => "html",
+    "markdown" => "html",
+    "svg" => "html",
+    "xml" => "html",
+    "latex" => "percent",
+    "matlab" => "percent",
+    "tex" => "percent",
+    "haskell" => "dash",
+    "lua" => "dash",
+    "sql" => "dash",
+    "clojure" => "semicolon",
+    "lisp" => "semicolon",
+    "scheme" => "semicolon",
+    "css" => "block",
+};
+
+pub static EXTENSION_FILETYPES: phf::Map<&'static str, &'static str> = phf_map! {
+    "adoc" => "asciidoc",
+    "c" => "c",
+    "h" => "c",
+    "cc" => "cpp",
+    "cpp" => "cpp",
+    "hpp" => "cpp",
+    "cs" => "cs",
+    "go" => "go",
+    "java" => "java",
+    "js" => "javascript",
+    "kt" => "kotlin",
+    "php" => "php",
+    "rs" => "rust",
+    "scala" => "scala",
+    "swift" => "swift",
+    "ts" => "typescript",
+    "awk" => "awk",
+    "bash" => "bash",
+    "cmake" => "cmake",
+    "conf" => "conf",
+    "mk" => "make",
+    "pl" => "perl",
+    "pm" => "perl",
+    "py" => "python",
+    "r" => "r",
+    "rb" => "ruby",
+    "sh" => "sh",
+    "tcl" => "tcl",
+    "toml" => "toml",
+    "yaml" => "yaml",
+    "yml" => "yaml",
+    "zsh" => "zsh",
+    "htm" => "html",
+    "html" => "html",
+    "markdown" => "markdown",
+    "md" => "markdown",
+    "svg" => "svg",
+    "xml" => "xml",
+    "cls" => "latex",
+    "sty" => "latex",
+    "tex" => "tex",
+    "hs" => "haskell",
+    "lua" => "lua",
+    "sql" => "sql",
+    "clj" => "clojure",
+    "el" => "lisp",
+    "lisp" => "lisp",
+    "scm" => "scheme",
+    "css" => "css",
+};
+
+// files known by their whole name
+pub static FILENAME_FILETYPES: phf::Map<&'static str, &'static str> = phf_map! {
+    "CMakeLists.txt" => "cmake",
+    "Dockerfile" => "dockerfile",
+    "GNUmakefile" => "make",
+    "Makefile" => "make",
+    "makefile" => "make",
+};
+
+// lines at the start of a file searched for a shebang or modeline
+const HEAD_LINES: usize = 5;
+
+#[derive(Default)]
+pub struct FileTypes {
+    extensions: HashMap<String, (String, String)>, // configured separators
+}
+
+impl FileTypes {
+    pub fn new() -> FileTypes {
+        FileTypes::default()
+    }
+
+    // set the separators for an extension, overriding the built-in ones
+    pub fn insert(&mut self, ext: &str, left: &str, right: &str) {
+        self.extensions
+            .insert(ext.to_string(), (left.to_string(), right.to_string()));
+    }
+
+    // separators for a file, by its name or else by a shebang or modeline
+    // in head, the start of its contents
+    pub fn separators(&self, path: &Path, head: &[u8]) -> Option<(String, String)> {
+        let ext = path.extension().and_then(|ext| ext.to_str());
+        if let Some(seps) = ext.and_then(|ext| self.extensions.get(ext)) {
+            return Some(seps.clone());
+        }
+        let filetype = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| FILENAME_FILETYPES.get::<str>(name))
+            .or_else(|| ext.and_then(|ext| EXTENSION_FILETYPES.get::<str>(ext)))
+            .map(|filetype| filetype.to_string())
+            .or_else(|| content_filetype(&String::from_utf8_lossy(head)))?;
+        let style = FILETYPE_COMMENTS.get::<str>(&filetype)?;
+        let (left, right) = COMMENT_SEPARATORS.get::<str>(style)?;
+        Some((left.to_string(), right.to_string()))
+    }
+}
+
+// whether contents has command lines with these separators, as files that
+// were protected before their type was detected do
+pub fn has_commands(contents: &[u8], left: &str, right: &str) -> bool {
+    String::from_utf8_lossy(contents).lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with(left) && line.contains(right)
+    })
+}
+
+// file type from a shebang, or a vim or emacs modeline
+fn content_filetype(head: &str) -> Option<String> {
+    let mut lines = head.lines().take(HEAD_LINES);
+    if let Some(first) = lines.next() {
+        if first.starts_with("#!") {
+            return shebang_filetype(&first[2..]);
+        }
+        if let Some(filetype) = modeline_filetype(first) {
+            return Some(filetype);
+        }
+    }
+    lines.filter_map(modeline_filetype).next()
+}
+
+// "/usr/bin/env python3" -> python
+fn shebang_filetype(cmd: &str) -> Option<String> {
+    let mut words = cmd.split_whitespace();
+    let mut interp = words.next()?.rsplit('/').next()?;
+    if interp == "env" {
+        interp = words.find(|word| !word.starts_with('-'))?;
+    }
+    let name = interp.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(name.to_lowercase())
+}
+
+// "vim: set ft=python:" or "-*- mode: python -*-" -> python
+fn modeline_filetype(line: &str) -> Option<String> {
+    if let Some(start) = line.find("-*-") {
+        let rest = &line[start + 3..];
+        let vars = &rest[..rest.find("-*-")?];
+        let mode = vars
+            .split(';')
+            .filter_map(|var| {
+                let mut it = var.splitn(2, ':');
+                match (it.next(), it.next()) {
+                    (Some(key), Some(value)) if key.trim() == "mode" => Some(value),
+                    (Some(value), None) => Some(value),
+                    _ => None,
+                }
+            })
+            .next()?;
+        return Some(mode.trim().to_lowercase());
+    }
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .filter_map(|tag| line.find(tag).map(|i| i + tag.len()))
+        .next()?;
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .filter_map(|opt| {
+            let mut it = opt.splitn(2, '=');
+            match (it.next(), it.next()) {
+                (Some("ft"), Some(value)) | (Some("filetype"), Some(value)) => Some(value),
+                _ => None,
+            }
+        })
+        .last()
+        .map(|value| value.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seps(name: &str, head: &str) -> Option<(String, String)> {
+        FileTypes::new().separators(Path::new(name), head.as_bytes())
+    }
+
+    #[test]
+    fn separators_by_name() {
+        assert_eq!(
+            seps("a/b/script.py", ""),
+            Some(("# <(".into(), ")>".into()))
+        );
+        assert_eq!(
+            seps("README.md", ""),
+            Some(("<!-- <(".into(), ")> -->".into()))
+        );
+        assert_eq!(seps("Makefile", ""), Some(("# <(".into(), ")>".into())));
+        assert_eq!(seps("paper.tex", ""), Some(("% <(".into(), ")>".into())));
+        assert_eq!(seps("notes.ept", ""), None);
+    }
+
+    #[test]
+    fn separators_by_contents() {
+        let hash = Some(("# <(".into(), ")>".into()));
+        assert_eq!(seps("run", "#!/bin/sh\n"), hash);
+        assert_eq!(seps("run", "#!/usr/bin/env python3\nimport os\n"), hash);
+        assert_eq!(seps("notes", "text\n# vim: set ft=yaml:\n"), hash);
+        assert_eq!(seps("notes", "# -*- mode: ruby -*-\n"), hash);
+        assert_eq!(seps("run", "#!/usr/bin/unknown\n"), None);
+        // the name decides over the contents
+        assert_eq!(
+            seps("run.rs", "#!/bin/sh\n"),
+            Some(("// <(".into(), ")>".into()))
+        );
+    }
+
+    #[test]
+    fn separators_configured() {
+        let mut filetypes = FileTypes::new();
+        filetypes.insert("txt", "<<", ">>");
+        filetypes.insert("py", "## <(", ")>");
+        assert_eq!(
+            filetypes.separators(Path::new("a.txt"), b""),
+            Some(("<<".into(), ">>".into()))
+        );
+        assert_eq!(
+            filetypes.separators(Path::new("a.py"), b""),
+            Some(("## <(".into(), ")>".into()))
+        );
+    }
+}
@@ -0,0 +1,290 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	git clean/smudge filters
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+use error::{Error, Result};
+
+// name of the filter in .gitattributes and the git configuration
+pub const FILTER_NAME: &str = "enprot";
+
+// largest payload of a pkt-line
+const MAX_PACKET_DATA: usize = 65516;
+
+fn stdio_error(e: io::Error) -> Error {
+    Error::Io {
+        path: Path::new("-").to_path_buf(),
+        source: e,
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// read a pkt-line, None for a flush packet
+fn read_packet<R: Read>(input: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| invalid_data("invalid pkt-line length"))?;
+    match len {
+        0 => Ok(None),
+        1..=4 => Err(invalid_data("invalid pkt-line length")),
+        _ => {
+            let mut data = vec![0; len - 4];
+            input.read_exact(&mut data)?;
+            Ok(Some(data))
+        }
+    }
+}
+
+// read text pkt-lines up to a flush packet
+fn read_text<R: Read>(input: &mut R) -> io::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    while let Some(data) = read_packet(input)? {
+        let line = String::from_utf8(data).map_err(|_| invalid_data("invalid pkt-line text"))?;
+        lines.push(line.trim_end_matches('\n').to_string());
+    }
+    Ok(lines)
+}
+
+fn write_packet<W: Write>(output: &mut W, data: &[u8]) -> io::Result<()> {
+    write!(output, "{:04x}", data.len() + 4)?;
+    output.write_all(data)
+}
+
+fn write_text<W: Write>(output: &mut W, line: &str) -> io::Result<()> {
+    write_packet(output, format!("{}\n", line).as_bytes())
+}
+
+fn write_flush<W: Write>(output: &mut W) -> io::Result<()> {
+    output.write_all(b"0000")
+}
+
+// agree on version 2 of the protocol and the clean and smudge capabilities
+fn handshake<R: Read, W: Write>(input: &mut R, output: &mut W) -> io::Result<()> {
+    let welcome = read_text(input)?;
+    if welcome.first().map(String::as_str) != Some("git-filter-client")
+        || !welcome.iter().any(|line| line == "version=2")
+    {
+        return Err(invalid_data("unsupported git filter protocol"));
+    }
+    write_text(output, "git-filter-server")?;
+    write_text(output, "version=2")?;
+    write_flush(output)?;
+    output.flush()?;
+
+    let capabilities = read_text(input)?;
+    for capability in &["capability=clean", "capability=smudge"] {
+        if capabilities.iter().any(|line| line == capability) {
+            write_text(output, capability)?;
+        }
+    }
+    write_flush(output)?;
+    output.flush()
+}
+
+// serve the long-running filter process protocol of git until it closes
+// the input. filter is called with whether to clean (or else smudge), the
+// path and the contents of each file. Failures are reported to git, which
+// decides whether to go on.
+pub fn process<R, W, F>(input: &mut R, output: &mut W, mut filter: F) -> Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(bool, &str, &[u8]) -> Result<Vec<u8>>,
+{
+    handshake(input, output).map_err(stdio_error)?;
+    loop {
+        let header = match read_text(input) {
+            Ok(header) => header,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(stdio_error(e)),
+        };
+        let mut command = "";
+        let mut pathname = "";
+        for line in &header {
+            let keyval = line.splitn(2, '=').collect::<Vec<&str>>();
+            match keyval.as_slice() {
+                ["command", value] => command = *value,
+                ["pathname", value] => pathname = *value,
+                _ => {}
+            }
+        }
+        let mut content = Vec::new();
+        while let Some(data) = read_packet(input).map_err(stdio_error)? {
+            content.extend_from_slice(&data);
+        }
+
+        let result = match command {
+            "clean" => filter(true, pathname, &content),
+            "smudge" => filter(false, pathname, &content),
+            _ => Err(Error::Operation("Unsupported git filter command")),
+        };
+        let written = match result {
+            Ok(data) => {
+                write_text(output, "status=success")
+                    .and_then(|_| write_flush(output))
+                    .and_then(|_| {
+                        data.chunks(MAX_PACKET_DATA)
+                            .try_for_each(|chunk| write_packet(output, chunk))
+                    })
+                    .and_then(|_| write_flush(output))
+                    // an empty list keeps the status
+                    .and_then(|_| write_flush(output))
+            }
+            Err(e) => {
+                eprintln!("{} in {}", e, pathname);
+                write_text(output, "status=error").and_then(|_| write_flush(output))
+            }
+        };
+        written.and_then(|_| output.flush()).map_err(stdio_error)?;
+    }
+}
+
+// run git with args in the current directory, returning its output
+fn git(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| Error::Io {
+            path: Path::new("git").to_path_buf(),
+            source: e,
+        })?;
+    if !output.status.success() {
+        return Err(Error::Git(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+// whether the repository holding dir trusts its project configuration with
+// commands and other password sources, by `git config enprot.trustConfig
+// true`. This setting is never part of a clone.
+pub fn trusts_config(dir: &Path) -> bool {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["config", "--bool", "--get", "enprot.trustConfig"])
+        .output()
+        .map(|output| output.status.success() && output.stdout == b"true\n")
+        .unwrap_or(false)
+}
+
+// the contents of path in the index, if it is there
+pub fn indexed(path: &str) -> Option<Vec<u8>> {
+    if path.is_empty() {
+        return None;
+    }
+    git(&["cat-file", "blob", &format!(":{}", path)]).ok()
+}
+
+// quote for the shell that git runs filters with
+fn shell_quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// make exe the filter, diff and merge driver for files matching patterns in
+// the repository of the current directory
+pub fn setup(exe: &Path, patterns: &[&str]) -> Result<()> {
+    let toplevel = git(&["rev-parse", "--show-toplevel"])?;
+    let toplevel = String::from_utf8_lossy(&toplevel).trim().to_string();
+    let exe = shell_quote(&exe.to_string_lossy());
+    let filter = format!("filter.{}", FILTER_NAME);
+    git(&[
+        "config",
+        &format!("{}.clean", filter),
+        &format!("{} git-filter clean %f", exe),
+    ])?;
+    git(&[
+        "config",
+        &format!("{}.smudge", filter),
+        &format!("{} git-filter smudge %f", exe),
+    ])?;
+    git(&[
+        "config",
+        &format!("{}.process", filter),
+        &format!("{} git-filter process", exe),
+    ])?;
+    git(&["config", &format!("{}.required", filter), "true"])?;
+    git(&[
+        "config",
+        &format!("diff.{}.textconv", FILTER_NAME),
+        &format!("{} textconv", exe),
+    ])?;
+    let merge = format!("merge.{}", FILTER_NAME);
+    git(&[
+        "config",
+        &format!("{}.name", merge),
+        "enprot merge of the plaintext",
+    ])?;
+    git(&[
+        "config",
+        &format!("{}.driver", merge),
+        &format!("{} merge-driver %O %A %B %P", exe),
+    ])?;
+
+    // add the patterns that are not there yet
+    let path = Path::new(&toplevel).join(".gitattributes");
+    let io_error = |e| Error::Io {
+        path: path.clone(),
+        source: e,
+    };
+    let existing = match fs::read_to_string(&path) {
+        Ok(existing) => existing,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(io_error(e)),
+    };
+    let mut lines = String::new();
+    if !existing.is_empty() && !existing.ends_with('\n') {
+        lines.push('\n');
+    }
+    for pattern in patterns {
+        let line = format!(
+            "{} filter={} diff={} merge={}",
+            pattern, FILTER_NAME, FILTER_NAME, FILTER_NAME
+        );
+        if !existing.lines().any(|existing| existing.trim() == line) {
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+    }
+    if lines.trim().is_empty() {
+        return Ok(());
+    }
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(lines.as_bytes()))
+        .map_err(io_error)
+}
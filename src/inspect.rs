@@ -0,0 +1,256 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	read-only views of a parsed file
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+
+use cas;
+use error::{Error, Result};
+use etree::{self, ParseOps, TextNode, TextTree};
+use prot;
+
+// print the segments of text as a tree, one per line
+pub fn list<W: Write>(outw: &mut W, text: &TextTree, level: usize) -> io::Result<()> {
+    let indent = "  ".repeat(level);
+    for elem in text {
+        match elem {
+            TextNode::BeginEnd { keyw, txt, .. } => {
+                writeln!(outw, "{}BEGIN {}", indent, keyw)?;
+                list(outw, txt, level + 1)?;
+            }
+            TextNode::Encrypted {
+                keyw,
+                txt,
+                extfields,
+                ..
+            } => {
+                let cas = match txt.first() {
+                    Some(TextNode::Stored { cas, .. }) => Some(cas.as_str()),
+                    _ => None,
+                };
+                let cmd = etree::encrypted_command(keyw, cas, extfields);
+                writeln!(outw, "{}{}", indent, cmd)?;
+            }
+            TextNode::Stored { keyw, cas, .. } => {
+                writeln!(outw, "{}STORED {} {}", indent, keyw, cas)?;
+            }
+            TextNode::Plain(_) | TextNode::Data { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+// the ENCRYPTED segments of words in text, with their extended fields;
+// segments inside encrypted or stored ones are not visible
+pub fn encrypted<'a>(
+    text: &'a TextTree,
+    words: &HashSet<String>,
+    segments: &mut Vec<(&'a str, &'a BTreeMap<String, String>)>,
+) {
+    for elem in text {
+        match elem {
+            TextNode::BeginEnd { txt, .. } => encrypted(txt, words, segments),
+            TextNode::Encrypted {
+                keyw, extfields, ..
+            } if words.contains(keyw) => segments.push((keyw, extfields)),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Status {
+    pub plaintext: usize,
+    pub encrypted: usize,
+    pub stored: usize,
+}
+
+// like "2 plaintext, 1 stored"
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let counts = [
+            (self.plaintext, "plaintext"),
+            (self.encrypted, "encrypted"),
+            (self.stored, "stored"),
+        ];
+        let states: Vec<String> = counts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, state)| format!("{} {}", count, state))
+            .collect();
+        write!(f, "{}", states.join(", "))
+    }
+}
+
+// count the segments of each keyword by state; segments inside encrypted or
+// stored ones are not visible
+pub fn status(text: &TextTree, counts: &mut BTreeMap<String, Status>) {
+    for elem in text {
+        match elem {
+            TextNode::BeginEnd { keyw, txt, .. } => {
+                counts.entry(keyw.to_string()).or_default().plaintext += 1;
+                status(txt, counts);
+            }
+            TextNode::Encrypted { keyw, .. } => {
+                counts.entry(keyw.to_string()).or_default().encrypted += 1;
+            }
+            TextNode::Stored { keyw, .. } => {
+                counts.entry(keyw.to_string()).or_default().stored += 1;
+            }
+            TextNode::Plain(_) | TextNode::Data { .. } => {}
+        }
+    }
+}
+
+// check that the CAS objects referenced from text exist and match their
+// hashes, following references from stored segments as well; problems are
+// collected in errors rather than ending the check
+pub fn verify(
+    text: &TextTree,
+    paops: &mut ParseOps,
+    level: usize,
+    errors: &mut Vec<Error>,
+) -> Result<()> {
+    if paops.max_depth != 0 && level > paops.max_depth {
+        return Err(Error::Operation("Maximum recursion depth exceeded"));
+    }
+    for elem in text {
+        match elem {
+            TextNode::BeginEnd { txt, .. } => verify(txt, paops, level + 1, errors)?,
+            TextNode::Encrypted { keyw, txt, .. } => {
+                // the ciphertext may be stored, but cannot be looked into
+                if let Some(TextNode::Stored { cas, .. }) = txt.first() {
+                    if let Err(e) = cas::load(cas, paops) {
+                        errors.push(Error::segment("verifying", keyw, e));
+                    }
+                }
+            }
+            TextNode::Stored { keyw, cas, .. } => {
+                let block = cas::load(cas, paops)
+                    .and_then(|blob| etree::blob_to_tree(blob, cas.to_string(), paops));
+                match block {
+                    Ok(block) => verify(&block, paops, level + 1, errors)?,
+                    Err(e) => errors.push(Error::segment("verifying", keyw, e)),
+                }
+            }
+            TextNode::Plain(_) | TextNode::Data { .. } => {}
+        }
+    }
+    Ok(())
+}
+
+// the size of the ciphertext of an ENCRYPTED segment
+fn size(txt: &TextTree, paops: &mut ParseOps) -> String {
+    let len = match txt.first() {
+        Some(TextNode::Data { data, .. }) => Some(data.len()),
+        Some(TextNode::Stored { cas, .. }) => cas::load(cas, paops).ok().map(|ct| ct.len()),
+        _ => None,
+    };
+    len.map_or("missing from CAS".to_string(), |len| {
+        format!("{} bytes", len)
+    })
+}
+
+// like "argon2/aes-256-siv", or "recipients/aes-256-siv" for segments
+// encrypted to recipients
+fn algorithms(extfields: &BTreeMap<String, String>) -> String {
+    let pbkdf = if prot::has_recipients(extfields) {
+        Ok("recipients".to_string())
+    } else {
+        prot::pbkdf_alg(extfields)
+    };
+    let cipher = prot::cipher_alg(extfields);
+    format!(
+        "{}/{}",
+        pbkdf.unwrap_or_else(|_| "unknown".to_string()),
+        cipher.unwrap_or_else(|_| "unknown".to_string())
+    )
+}
+
+// text for reading: segments that can be decrypted or fetched from CAS are
+// shown as BEGIN-END blocks, and the encrypted ones that cannot as
+// placeholders like "[ENCRYPTED alice, 312 bytes, argon2/aes-256-siv]"
+pub fn render(text: &TextTree, paops: &mut ParseOps, level: usize) -> Result<TextTree> {
+    if paops.max_depth != 0 && level > paops.max_depth {
+        return Err(Error::Operation("Maximum recursion depth exceeded"));
+    }
+    let mut text_out = Vec::new();
+    for elem in text {
+        let (keyw, block, indent, lines) = match elem {
+            TextNode::BeginEnd {
+                keyw,
+                txt,
+                indent,
+                lines,
+            } => (keyw, Ok(txt.to_vec()), indent, lines.clone()),
+            TextNode::Encrypted {
+                keyw,
+                txt,
+                extfields,
+                indent,
+                ..
+            } => {
+                let block = etree::decrypt_segment(keyw, txt, extfields, paops)
+                    .and_then(|pt| etree::blob_to_tree(pt, "decrypted".to_string(), paops));
+                if block.is_err() {
+                    let placeholder = format!(
+                        "{}[ENCRYPTED {}, {}, {}]{}",
+                        indent,
+                        keyw,
+                        size(txt, paops),
+                        algorithms(extfields),
+                        paops.eol
+                    );
+                    text_out.push(TextNode::Plain(placeholder));
+                    continue;
+                }
+                (keyw, block, indent, Vec::new())
+            }
+            TextNode::Stored {
+                keyw, cas, indent, ..
+            } => {
+                let block = cas::load(cas, paops)
+                    .and_then(|blob| etree::blob_to_tree(blob, cas.to_string(), paops));
+                if block.is_err() {
+                    text_out.push(elem.clone());
+                    continue;
+                }
+                (keyw, block, indent, Vec::new())
+            }
+            TextNode::Plain(_) | TextNode::Data { .. } => {
+                text_out.push(elem.clone());
+                continue;
+            }
+        };
+        text_out.push(TextNode::BeginEnd {
+            keyw: keyw.to_string(),
+            txt: render(&block?, paops, level + 1)?,
+            indent: indent.to_string(),
+            lines,
+        });
+    }
+    Ok(text_out)
+}
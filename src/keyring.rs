@@ -0,0 +1,231 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	stored keyword passwords, in the freedesktop Secret Service or in the
+//	keystore file
+
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crypto::CryptoPolicy;
+use error::{Error, Result};
+use keystore::{self, Keystore};
+use pubkey::PrivateKey;
+
+// the attributes of our items in the Secret Service, with the keyword
+const APPLICATION: [&str; 2] = ["application", "enprot"];
+
+enum Backend {
+    // through libsecret's secret-tool, which talks D-Bus for us
+    SecretService,
+    // opened when first needed
+    File {
+        path: PathBuf,
+        keystore: Option<Keystore>,
+    },
+}
+
+pub struct Keyring {
+    pub name: String, // as configured
+    backend: Backend,
+}
+
+fn secret_tool_installed() -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join("secret-tool").is_file()))
+}
+
+fn secret_tool(name: &str, args: &[&str], input: Option<&str>) -> Result<(bool, String)> {
+    let error = |message: String| Error::KeySource {
+        keyword: name.to_string(),
+        message: format!("secret-tool: {}", message),
+    };
+    let mut child = Command::new("secret-tool")
+        .args(args)
+        .args(APPLICATION)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| error(e.to_string()))?;
+    if let Some(input) = input {
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .map_err(|e| error(e.to_string()))?;
+    }
+    let output = child.wait_with_output().map_err(|e| error(e.to_string()))?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !stderr.trim().is_empty() {
+        return Err(error(stderr.trim().to_string()));
+    }
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+    ))
+}
+
+impl Keyring {
+    // secret-service, file, or auto for the Secret Service if there is a
+    // session bus and secret-tool, and the file otherwise. The file is
+    // only possible where it has a default location.
+    pub fn new(name: &str) -> Option<Keyring> {
+        let secret_service = match name {
+            "secret-service" => true,
+            "file" => false,
+            _ => env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some() && secret_tool_installed(),
+        };
+        let backend = if secret_service {
+            Backend::SecretService
+        } else {
+            Backend::File {
+                path: keystore::default_path()?,
+                keystore: None,
+            }
+        };
+        Some(Keyring {
+            name: name.to_string(),
+            backend,
+        })
+    }
+
+    // the keystore at path
+    pub fn file(path: PathBuf) -> Keyring {
+        Keyring {
+            name: "file".to_string(),
+            backend: Backend::File {
+                path,
+                keystore: None,
+            },
+        }
+    }
+
+    // the keystore, asking for its passphrase the first time unless it is
+    // in ENPROT_KEYSTORE_PASSWORD. None if it does not exist and is not to
+    // be created, or if it cannot be asked for.
+    fn keystore(
+        &mut self,
+        create: bool,
+        interactive: bool,
+        policy: &Box<dyn CryptoPolicy>,
+    ) -> Result<Option<&mut Keystore>> {
+        let (path, keystore) = match self.backend {
+            Backend::File {
+                ref path,
+                ref mut keystore,
+            } => (path, keystore),
+            Backend::SecretService => return Ok(None),
+        };
+        if keystore.is_none() {
+            if !create && !path.exists() {
+                return Ok(None);
+            }
+            let passphrase = match keystore::passphrase(path, interactive) {
+                Some(passphrase) => passphrase,
+                None => return Ok(None),
+            };
+            *keystore = Some(Keystore::open(path, &passphrase, policy)?);
+        }
+        Ok(keystore.as_mut())
+    }
+
+    // the stored password for name (a keyword or KEYWORD/LABEL)
+    pub fn password(
+        &mut self,
+        name: &str,
+        interactive: bool,
+        policy: &Box<dyn CryptoPolicy>,
+    ) -> Result<Option<String>> {
+        if let Backend::SecretService = self.backend {
+            // not found is a failure without a message
+            let (found, pass) = secret_tool(name, &["lookup", "keyword", name], None)?;
+            return Ok(Some(pass).filter(|pass| found && !pass.is_empty()));
+        }
+        Ok(self
+            .keystore(false, interactive, policy)?
+            .and_then(|keystore| keystore.entries.passwords.get(name).cloned()))
+    }
+
+    pub fn store(
+        &mut self,
+        name: &str,
+        pass: &str,
+        interactive: bool,
+        rng: &Option<botan::RandomNumberGenerator>,
+        policy: &Box<dyn CryptoPolicy>,
+    ) -> Result<()> {
+        if let Backend::SecretService = self.backend {
+            let label = format!("--label=enprot: {}", name);
+            secret_tool(name, &["store", &label, "keyword", name], Some(pass))?;
+            return Ok(());
+        }
+        if let Some(keystore) = self.keystore(true, interactive, policy)? {
+            let _lock = keystore.lock()?;
+            keystore.reload(policy)?;
+            keystore
+                .entries
+                .passwords
+                .insert(name.to_string(), pass.to_string());
+            keystore.save(rng, policy)?;
+        }
+        Ok(())
+    }
+
+    // the private keys in the keystore
+    pub fn private_keys(
+        &mut self,
+        interactive: bool,
+        policy: &Box<dyn CryptoPolicy>,
+    ) -> Result<Vec<PrivateKey>> {
+        match self.keystore(false, interactive, policy)? {
+            Some(keystore) => keystore.private_keys(),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // the names there are passwords for, as far as they can be listed
+    // without asking
+    pub fn names(&mut self, policy: &Box<dyn CryptoPolicy>) -> Vec<String> {
+        if let Backend::SecretService = self.backend {
+            let output = secret_tool("", &["search", "--all"], None);
+            return output.map_or_else(
+                |_| Vec::new(),
+                |(_, output)| {
+                    output
+                        .lines()
+                        .filter_map(|line| line.trim().strip_prefix("attribute.keyword = "))
+                        .map(|name| name.to_string())
+                        .collect()
+                },
+            );
+        }
+        match self.keystore(false, false, policy) {
+            Ok(Some(keystore)) => keystore.entries.passwords.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
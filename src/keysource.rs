@@ -0,0 +1,155 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	non-interactive password sources
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+#[cfg(unix)]
+use std::process::{Command, Stdio};
+
+use error::{Error, Result};
+
+// where the password for a keyword comes from, instead of a prompt. In the
+// project configuration these are tables like { cmd = "pass show x" }.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    File(PathBuf), // the first line of a file
+    Env(String),   // an environment variable
+    Fd(u32),       // the first line read from an inherited file descriptor
+    Cmd(String),   // the first line printed by a shell command
+}
+
+// the environment variables a project configuration may name
+const ENV_PREFIX: &str = "ENPROT_";
+
+fn error(name: &str, message: String) -> Error {
+    Error::KeySource {
+        keyword: name.to_string(),
+        message,
+    }
+}
+
+// whether path, relative to dir, stays below it once the symbolic links in
+// the part of it that exists are resolved
+fn stays_below(path: &Path, dir: &Path) -> bool {
+    let lexical = path
+        .components()
+        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir));
+    let dir = match fs::canonicalize(dir) {
+        Ok(dir) if lexical => dir,
+        _ => return false,
+    };
+    let mut existing = dir.join(path);
+    while fs::symlink_metadata(&existing).is_err() {
+        if !existing.pop() {
+            return false;
+        }
+    }
+    fs::canonicalize(&existing).is_ok_and(|resolved| resolved.starts_with(&dir))
+}
+
+// the first line of input, without its terminator
+fn first_line<R: Read>(input: R) -> io::Result<String> {
+    let mut line = String::new();
+    BufReader::new(input).read_line(&mut line)?;
+    let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+    line.truncate(len);
+    Ok(line)
+}
+
+impl KeySource {
+    // a source given as kind and value, like ("fd", "3")
+    pub fn parse(kind: &str, value: &str) -> Option<KeySource> {
+        match kind {
+            "file" => Some(KeySource::File(PathBuf::from(value))),
+            "env" => Some(KeySource::Env(value.to_string())),
+            "fd" => value.parse().ok().map(KeySource::Fd),
+            "cmd" => Some(KeySource::Cmd(value.to_string())),
+            _ => None,
+        }
+    }
+
+    // whether the source only reads an ENPROT_ environment variable or a
+    // file below dir, the directory of the configuration, which a project
+    // configuration may do without trust
+    pub fn is_confined(&self, dir: &Path) -> bool {
+        match self {
+            KeySource::File(path) => stays_below(path, dir),
+            KeySource::Env(var) => var.starts_with(ENV_PREFIX),
+            KeySource::Fd(_) | KeySource::Cmd(_) => false,
+        }
+    }
+
+    // relative files are relative to dir
+    pub fn relative_to(&self, dir: &Path) -> KeySource {
+        match self {
+            KeySource::File(path) => KeySource::File(dir.join(path)),
+            source => source.clone(),
+        }
+    }
+
+    // the password for name
+    pub fn read(&self, name: &str) -> Result<String> {
+        let pass = match self {
+            KeySource::File(path) => File::open(path)
+                .and_then(first_line)
+                .map_err(|e| error(name, format!("{}: {}", path.display(), e)))?,
+            KeySource::Env(var) => env::var(var)
+                .map_err(|_| error(name, format!("environment variable {} is not set", var)))?,
+            #[cfg(unix)]
+            KeySource::Fd(fd) => File::open(format!("/dev/fd/{}", fd))
+                .and_then(first_line)
+                .map_err(|e| error(name, format!("file descriptor {}: {}", fd, e)))?,
+            #[cfg(unix)]
+            KeySource::Cmd(cmd) => {
+                // standard input may be the data being filtered
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(cmd)
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::inherit())
+                    .output()
+                    .map_err(|e| error(name, format!("{}: {}", cmd, e)))?;
+                if !output.status.success() {
+                    return Err(error(name, format!("{}: {}", cmd, output.status)));
+                }
+                first_line(&output.stdout[..]).map_err(|e| error(name, e.to_string()))?
+            }
+            #[cfg(not(unix))]
+            KeySource::Fd(_) | KeySource::Cmd(_) => {
+                return Err(error(
+                    name,
+                    "file descriptors and commands are unsupported on this platform".to_string(),
+                ));
+            }
+        };
+        if pass.is_empty() {
+            return Err(error(name, "empty password".to_string()));
+        }
+        Ok(pass)
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	the keystore, a file of keyword passwords and private keys sealed with
+//	a passphrase
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use atomic;
+use consts;
+use crypto::CryptoPolicy;
+use error::{Error, Result};
+use etree::{CipherOptions, PBKDFOptions};
+use prot;
+use pubkey::PrivateKey;
+use utils;
+
+// the first word of the file, followed by the extended fields
+const HEADER: &str = "ENPROT-KEYSTORE";
+// bound to the ciphertext as its keyword
+const KEYWORD: &str = "keystore";
+const DATA_BYTES_PER_LINE: usize = 48;
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Entries {
+    #[serde(default)]
+    pub passwords: BTreeMap<String, String>,
+    #[serde(default)]
+    pub private_keys: BTreeMap<String, String>, // PEM encoded, by keyword
+}
+
+pub struct Keystore {
+    pub path: PathBuf,
+    passphrase: String,
+    pub entries: Entries,
+}
+
+pub fn error(path: &Path, source: Error) -> Error {
+    Error::Keystore {
+        path: path.to_path_buf(),
+        source: Box::new(source),
+    }
+}
+
+// $XDG_DATA_HOME/enprot/keystore, or ~/.local/share/enprot/keystore
+pub fn default_path() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .map(|dir| dir.join("enprot").join("keystore"))
+}
+
+// a lock on the keystore at a path, released when dropped
+pub struct Lock {
+    _file: File,
+}
+
+// take the lock on the keystore at path, waiting for others to release it.
+// It is held on a file beside the keystore, which is replaced when saved.
+fn lock(path: &Path) -> Result<Lock> {
+    let mut name = path
+        .file_name()
+        .map_or(OsString::new(), |name| name.to_owned());
+    name.push(".lock");
+    let lock_path = path.with_file_name(name);
+    let io_error = |e| Error::Io {
+        path: lock_path.clone(),
+        source: e,
+    };
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(io_error)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let file = options.open(&lock_path).map_err(io_error)?;
+    file.lock().map_err(io_error)?;
+    Ok(Lock { _file: file })
+}
+
+// the passphrases asked for, so that the workers of --jobs ask only once
+static PASSPHRASES: Mutex<BTreeMap<PathBuf, String>> = Mutex::new(BTreeMap::new());
+
+// the passphrase from ENPROT_KEYSTORE_PASSWORD, or else asked for (twice
+// for a new keystore) if possible
+pub fn passphrase(path: &Path, interactive: bool) -> Option<String> {
+    if let Ok(passphrase) = env::var(consts::KEYSTORE_PASSWORD_ENV) {
+        return Some(passphrase);
+    }
+    let mut passphrases = PASSPHRASES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(passphrase) = passphrases.get(path) {
+        return Some(passphrase.clone());
+    }
+    if !interactive {
+        return None;
+    }
+    let passphrase = prot::get_password("the keystore", !path.exists());
+    passphrases.insert(path.to_path_buf(), passphrase.clone());
+    Some(passphrase)
+}
+
+// the file: a header line with the extended fields of the encryption, like
+// an ENCRYPTED segment, followed by the base64 encoded ciphertext
+fn seal(
+    pt: Vec<u8>,
+    passphrase: &str,
+    rng: &Option<botan::RandomNumberGenerator>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<String> {
+    let (ct, extfields) = prot::encrypt(
+        pt,
+        KEYWORD,
+        passphrase,
+        rng,
+        &PBKDFOptions::new(policy),
+        &CipherOptions::new(policy),
+        &mut None,
+        policy,
+    )?;
+    let mut sealed = HEADER.to_string();
+    for (name, value) in &extfields {
+        sealed += &format!(" {}:{}", name, value);
+    }
+    sealed += "\n";
+    for line in ct.chunks(DATA_BYTES_PER_LINE) {
+        sealed += &utils::base64_encode(line)?;
+        sealed += "\n";
+    }
+    Ok(sealed)
+}
+
+fn unseal(sealed: &str, passphrase: &str, policy: &Box<dyn CryptoPolicy>) -> Result<Vec<u8>> {
+    let mut lines = sealed.lines();
+    let mut header = lines.next().unwrap_or("").split_whitespace();
+    if header.next() != Some(HEADER) {
+        return Err(Error::Format("Not a keystore"));
+    }
+    let mut extfields = BTreeMap::new();
+    for field in header {
+        let mut it = field.splitn(2, ':');
+        let name = it.next().unwrap();
+        let value = it.next().ok_or(Error::Format("Invalid extended field"))?;
+        extfields.insert(name.to_string(), value.to_string());
+    }
+    let mut ct = Vec::new();
+    for line in lines {
+        ct.extend(utils::base64_decode(line.trim())?);
+    }
+    prot::decrypt(
+        ct, KEYWORD, passphrase, &extfields, &None, &mut None, policy,
+    )
+}
+
+impl Keystore {
+    // the keystore at path, empty if there is none yet
+    pub fn open(path: &Path, passphrase: &str, policy: &Box<dyn CryptoPolicy>) -> Result<Keystore> {
+        let entries = if path.exists() {
+            let sealed = fs::read_to_string(path).map_err(|e| Error::Io {
+                path: path.to_path_buf(),
+                source: e,
+            })?;
+            let pt = unseal(&sealed, passphrase, policy).map_err(|e| error(path, e))?;
+            let pt = String::from_utf8(pt)
+                .map_err(|_| error(path, Error::Format("Invalid keystore contents")))?;
+            toml::from_str(&pt)
+                .map_err(|_| error(path, Error::Format("Invalid keystore contents")))?
+        } else {
+            Entries::default()
+        };
+        Ok(Keystore {
+            path: path.to_path_buf(),
+            passphrase: passphrase.to_string(),
+            entries,
+        })
+    }
+
+    // the lock on the keystore, for reading and saving it again while no
+    // other process does
+    pub fn lock(&self) -> Result<Lock> {
+        lock(&self.path)
+    }
+
+    // read the entries again, with what others saved since
+    pub fn reload(&mut self, policy: &Box<dyn CryptoPolicy>) -> Result<()> {
+        self.entries = Keystore::open(&self.path, &self.passphrase, policy)?.entries;
+        Ok(())
+    }
+
+    pub fn private_keys(&self) -> Result<Vec<PrivateKey>> {
+        self.entries
+            .private_keys
+            .values()
+            .map(|pem| PrivateKey::decode(pem).map_err(|e| error(&self.path, e)))
+            .collect()
+    }
+
+    // seal the entries again with the same passphrase, creating the file
+    // readable only by us. Between reload() and save(), hold lock() so that
+    // entries that others save meanwhile are kept.
+    pub fn save(
+        &self,
+        rng: &Option<botan::RandomNumberGenerator>,
+        policy: &Box<dyn CryptoPolicy>,
+    ) -> Result<()> {
+        let pt = toml::to_string(&self.entries)
+            .map_err(|_| error(&self.path, Error::Format("Invalid keystore contents")))?;
+        let sealed = seal(pt.into_bytes(), &self.passphrase, rng, policy)
+            .map_err(|e| error(&self.path, e))?;
+        let io_error = |e| Error::Io {
+            path: self.path.clone(),
+            source: e,
+        };
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+        atomic::write_private(&self.path, sealed.as_bytes()).map_err(io_error)
+    }
+}
@@ -26,30 +26,58 @@ extern crate aes_gcm_siv;
 extern crate block_cipher_trait;
 extern crate botan;
 extern crate clap;
+extern crate diffy;
+extern crate glob;
 extern crate hex;
 extern crate num;
 extern crate phc;
 extern crate phf;
 extern crate rpassword;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
+mod agent;
+mod atomic;
 mod cas;
 mod cipher;
+mod config;
 mod consts;
 pub mod crypto;
+mod document;
+mod edit;
+mod error;
 mod etree;
+mod filetype;
+mod gitfilter;
+mod inspect;
+mod keyring;
+mod keysource;
+mod keystore;
+mod merge;
 mod pbkdf;
 mod policy;
 mod prot;
+mod pubkey;
 pub mod utils;
+mod walk;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsString;
 use std::fs;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
-use std::path::Path;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
-use clap::{App, AppSettings, Arg, ArgSettings, ErrorKind};
+use clap::{App, AppSettings, Arg, ArgMatches, ArgSettings, ErrorKind, SubCommand};
+
+pub use document::{Credentials, Document, Operations};
+pub use error::Error;
+pub use etree::TextNode;
 
 fn validate_positive<T>(v: String) -> Result<(), String>
 where
@@ -69,6 +97,82 @@ where
     v.parse::<T>().map_err(|_| err.clone()).map(|_| ())
 }
 
+// like m=65536,p=1,t=3
+fn validate_pbkdf_params(v: String) -> Result<(), String> {
+    for val in v.split(",") {
+        let pair = val.splitn(2, '=').collect::<Vec<&str>>();
+        if pair.len() != 2 || pair[0].is_empty() || pair[1].parse::<usize>().is_err() {
+            return Err("Must be of the form NAME=NUMBER[,NAME=NUMBER]".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn validate_word_pairs(v: &str, form: &str) -> Result<(), String> {
+    for val in v.split(",") {
+        let pair = val.splitn(2, '=').collect::<Vec<&str>>();
+        if pair.len() != 2 || pair[0].len() == 0 || pair[1].len() == 0 {
+            return Err(format!("Must be of the form {}[,{}]", form, form));
+        }
+    }
+    Ok(())
+}
+
+// a single WORD=SOURCE, not split at commas since commands may have them
+fn validate_key_source(v: &str, kind: &str, form: &str) -> Result<(), String> {
+    let pair = v.splitn(2, '=').collect::<Vec<&str>>();
+    if pair.len() != 2 || pair[0].is_empty() || pair[1].is_empty() {
+        return Err(format!("Must be of the form {}", form));
+    }
+    match keysource::KeySource::parse(kind, pair[1]) {
+        Some(_) => Ok(()),
+        None => Err(format!("Invalid {} '{}'", kind, pair[1])),
+    }
+}
+
+// the password sources given on the command line, like "--key-fd WORD=3"
+fn key_source_args(matches: &ArgMatches) -> Vec<(String, keysource::KeySource)> {
+    let mut sources = Vec::new();
+    for kind in &["file", "env", "fd", "cmd"] {
+        for val in matches
+            .values_of(format!("key-{}", kind))
+            .unwrap_or(clap::Values::default())
+        {
+            let pair = val.splitn(2, '=').collect::<Vec<&str>>();
+            let source = keysource::KeySource::parse(kind, pair[1]).unwrap();
+            sources.push((pair[0].to_string(), source));
+        }
+    }
+    sources
+}
+
+fn validate_labels(v: &str) -> Result<(), String> {
+    validate_word_pairs(v, "WORD=LABEL")?;
+    for val in v.split(",") {
+        let label = val.splitn(2, '=').nth(1).unwrap();
+        if !label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            return Err(format!("Invalid label '{}'", label));
+        }
+    }
+    Ok(())
+}
+
+fn validate_glob(v: &str) -> Result<(), String> {
+    glob::Pattern::new(v)
+        .map(|_| ())
+        .map_err(|e| format!("Invalid glob '{}': {}", v, e))
+}
+
+fn validate_doc_id(v: &str) -> Result<(), String> {
+    if v.is_empty() || v.chars().any(|c| c.is_whitespace() || c == '$' || c == '=') {
+        return Err(format!("Invalid document ID '{}'", v));
+    }
+    Ok(())
+}
+
 fn err_exit(app: &mut App, desc: &str, kind: ErrorKind, show_help: bool) -> ! {
     if show_help {
         app.print_help().unwrap();
@@ -79,380 +183,691 @@ fn err_exit(app: &mut App, desc: &str, kind: ErrorKind, show_help: bool) -> ! {
     std::process::exit(1);
 }
 
-fn make_policy(app: &mut App, name: &str) -> Box<dyn crypto::CryptoPolicy> {
-    match name {
-        "default" => Box::new(crypto::CryptoPolicyDefault {}),
-        "nist" => Box::new(crypto::CryptoPolicyNIST {}),
-        value => {
-            // shouldn't happen
-            err_exit(
-                app,
-                &format!("Invalid policy: '{}'", value),
-                ErrorKind::InvalidValue,
-                true,
-            );
-        }
+// the message of an error, naming the file unless the error already does
+fn error_message(e: &Error, path: &str) -> String {
+    match e {
+        Error::Parse { .. }
+        | Error::Config { .. }
+        | Error::Io { .. }
+        | Error::Git(_)
+        | Error::Agent(_)
+        | Error::Keystore { .. }
+        | Error::KeySource { .. } => format!("{}, aborting.", e),
+        _ => format!("{} in {}, aborting.", e, path),
     }
 }
 
-// Handle command line parameters
-
-pub fn app_main<I, T>(args: I)
-where
-    I: IntoIterator<Item = T>,
-    T: Into<OsString> + Clone,
-{
-    // <( ENCRYPTED AUTHOR )>
-    // <( DATA X417HVMRRAs6Z1xGo5yY4TxUQ2tpAHEKQ1sg9+kfku5uUikK3y2tODtsUiGqfRGW )>
-    // <( DATA xUCGYFu02BCdqPM7uuX5UNvbfrLvKkj6gLYwg/cr42PJmr4o5xnw1qo= )>
-    // <( END AUTHOR )>
-
-    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+// print an error and exit
+fn abort(e: &Error, path: &str) -> ! {
+    eprintln!("{}", error_message(e, path));
+    std::process::exit(1);
+}
 
-    let default_max_depth = consts::DEFAULT_MAX_DEPTH.to_string();
+// what to do with each input file
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Transform, // apply the operations and write the result
+    List,
+    Status,
+    Verify,
+    Textconv,    // show what can be decrypted or fetched
+    GitFilter,   // between standard input and output, for git
+    MergeDriver, // three-way merge of the plaintext, for git
+    Diff,        // compare two files as shown by textconv
+    Edit,        // decrypt into $EDITOR and encrypt again
+}
 
-    let mut app = App::new("enprot")
-        .version(VERSION)
-        .setting(AppSettings::DeriveDisplayOrder)
-        .setting(AppSettings::ColoredHelp)
-        .setting(AppSettings::ColorAuto)
-        .arg(
-            Arg::with_name("verbose")
-                .short("v")
-                .long("verbose")
-                .help("Produce more verbose output"),
-        )
-        .arg(
-            Arg::with_name("quiet")
-                .short("q")
-                .long("quiet")
-                .help("Suppress unnecessary output"),
-        )
-        .arg(
-            Arg::with_name("max-depth")
-                .long("max-depth")
-                .takes_value(true)
-                .value_name("DEPTH")
-                .validator(validate_non_negative::<usize>)
-                .default_value(&default_max_depth)
-                .help("Maximum recursion depth (use 0 for infinite)"),
-        )
-        .arg(
-            Arg::with_name("left-separator")
-                .short("l")
-                .long("left-separator")
-                .takes_value(true)
-                .value_name("SEP")
-                .default_value(consts::DEFAULT_LEFT_SEP)
-                .help("Specify left separator in parsing"),
-        )
-        .arg(
-            Arg::with_name("right-separator")
-                .short("r")
-                .long("right-separator")
-                .takes_value(true)
-                .value_name("SEP")
-                .default_value(consts::DEFAULT_RIGHT_SEP)
-                .help("Specify right separator in parsing"),
-        )
-        .arg(
-            Arg::with_name("store")
-                .short("s")
-                .long("store")
-                .takes_value(true)
-                .value_name("WORD")
-                .multiple(true)
-                .number_of_values(1)
-                .help("Store (unencrypted) WORD segments to CAS"),
-        )
-        .arg(
-            Arg::with_name("fetch")
-                .short("f")
-                .long("fetch")
-                .takes_value(true)
-                .value_name("WORD")
-                .multiple(true)
-                .number_of_values(1)
-                .help("Fetch (unencrypted) WORD segments to CAS"),
-        )
-        .arg(
-            Arg::with_name("password")
-                .short("k")
-                .long("key")
-                .takes_value(true)
-                .value_name("WORD=PASSWORD")
-                .multiple(true)
-                .number_of_values(1)
-                .validator(|v: String| -> Result<(), String> {
-                    for val in v.split(",") {
-                        let wordpass = val.splitn(2, '=').collect::<Vec<&str>>();
-                        if wordpass.len() != 2 || wordpass[0].len() == 0 || wordpass[1].len() == 0 {
-                            return Err(String::from(
-                                "Must be of the form WORD=PASSWORD[,WORD=PASSWORD]",
-                            ));
-                        }
-                    }
-                    Ok(())
-                })
-                .help("Specify a secret PASSWORD for WORD"),
-        )
-        .arg(
-            Arg::with_name("encrypt")
-                .short("e")
-                .long("encrypt")
-                .takes_value(true)
-                .value_name("WORD")
-                .multiple(true)
-                .number_of_values(1)
-                .help("Encrypt WORD segments"),
-        )
-        .arg(
-            Arg::with_name("encrypt-store")
-                .short("E")
-                .long("encrypt-store")
-                .takes_value(true)
-                .value_name("WORD")
-                .multiple(true)
-                .number_of_values(1)
-                .help("Encrypt and store WORD segments"),
-        )
-        .arg(
-            Arg::with_name("policy")
-                .long("policy")
-                .takes_value(true)
-                .value_name("POLICY")
-                .default_value(consts::DEFAULT_POLICY)
-                .possible_values(consts::VALID_POLICIES)
-                .help("Set the policy to restrict cryptographic algorithms"),
-        )
-        .arg(
-            Arg::with_name("defaults")
-                .long("defaults")
-                .takes_value(true)
-                .value_name("POLICY")
-                .possible_values(consts::VALID_POLICIES)
-                .help("Load settings from POLICY, but do not enforce the policy"),
-        )
-        .arg(Arg::with_name("fips").long("fips").help(
-            "Select and enforce the use of FIPS-compliant algorithms (implies --policy=nist)",
-        ))
-        .arg(
-            Arg::with_name("pbkdf")
-                .long("pbkdf")
-                .takes_value(true)
-                .value_name("ALG")
-                .possible_values(consts::VALID_PBKDF_ALGS)
-                .help("Set the PBKDF algorithm to use when encrypting"),
-        )
-        .arg(
-            Arg::with_name("pbkdf-msec")
-                .long("pbkdf-msec")
-                .takes_value(true)
-                .value_name("MSEC")
-                .validator(validate_positive::<u32>)
-                .help("Set the millisecond count for the PBKDF algorithm"),
-        )
-        .arg(
-            Arg::with_name("pbkdf-salt-len")
-                .long("pbkdf-salt-len")
-                .takes_value(true)
-                .value_name("BYTES")
-                .validator(&validate_positive::<usize>)
-                .help("Set the salt length for the PBKDF"),
-        )
-        .arg(
-            Arg::with_name("pbkdf-params")
-                .long("pbkdf-params")
-                .takes_value(true)
-                .value_name("PARAMS")
-                .hidden(true)
-                .help("Advanced option for testing, do not use"),
-        )
-        .arg(
-            Arg::with_name("pbkdf-salt")
-                .long("pbkdf-salt")
-                .takes_value(true)
-                .value_name("HEX")
-                .hidden(true)
-                .help("Advanced option for testing, do not use"),
-        )
-        .arg(
-            Arg::with_name("pbkdf-disable-cache")
-                .long("pbkdf-disable-cache")
-                .help("Disable the PBKDF cache mechanism"),
-        )
-        .arg(
-            Arg::with_name("cipher")
-                .long("cipher")
-                .takes_value(true)
-                .value_name("ALG")
-                .possible_values(consts::VALID_CIPHER_ALGS)
-                .help("Set the cipher algorithm to use when encrypting"),
-        )
-        .arg(
-            Arg::with_name("cipher-iv")
-                .long("cipher-iv")
-                .takes_value(true)
-                .value_name("ALG")
-                .hidden(true)
-                .help("Advanced option for testing, do not use"),
-        )
-        .arg(
-            Arg::with_name("decrypt")
-                .short("d")
-                .long("decrypt")
-                .takes_value(true)
-                .value_name("WORD")
-                .multiple(true)
-                .number_of_values(1)
-                .help("Decrypt WORD segments"),
-        )
-        .arg(
-            Arg::with_name("casdir")
-                .short("c")
-                .long("casdir")
-                .takes_value(true)
-                .value_name("DIRECTORY")
-                .default_value("./")
-                .set(ArgSettings::HideDefaultValue)
-                .validator(|v: String| -> Result<(), String> {
-                    if Path::new(&v).is_dir() {
-                        return Ok(());
-                    } else {
-                        Err(String::from("Must be a directory"))
-                    }
-                })
-                .help("Directory for CAS files (default \"cas\" if exists, else \".\")"),
-        )
-        .arg(
-            Arg::with_name("prefix")
-                .short("p")
-                .long("prefix")
-                .takes_value(true)
-                .value_name("PREFIX")
-                .default_value("")
-                .set(ArgSettings::HideDefaultValue)
-                .set(ArgSettings::EmptyValues)
-                .help("Use PREFIX for output filenames"),
-        )
-        .arg(
-            Arg::with_name("output")
-                .short("o")
-                .long("output")
-                .takes_value(true)
-                .value_name("FILE")
-                .multiple(true)
-                .number_of_values(1)
-                .help("Specify output file for previous input"),
-        )
-        .arg(
-            Arg::with_name("input")
-                .required(true)
-                .index(1)
-                .value_name("FILE")
-                .default_value("-")
-                .multiple(true)
-                .help("The input file(s)"),
-        );
-    let matches = app.clone().get_matches_from(args);
+// the policy of a name that was validated as one of VALID_POLICIES
+fn make_policy(name: &str) -> Box<dyn crypto::CryptoPolicy> {
+    match name {
+        "default" => Box::new(crypto::CryptoPolicyDefault {}),
+        "nist" => Box::new(crypto::CryptoPolicyNIST {}),
+        "strict" => Box::new(crypto::CryptoPolicyStrict {}),
+        value => panic!("Invalid policy: '{}'", value),
+    }
+}
 
-    let mut policy = matches.value_of("policy").unwrap();
-    // check if fips mode is requested (implicitly or explicitly)
+// whether FIPS mode is requested, explicitly or by the system, exiting when
+// that conflicts with the policy given
+fn fips_mode(app: &mut App, matches: &ArgMatches) -> bool {
     let fips = matches.occurrences_of("fips") != 0
         || (cfg!(unix)
             && match fs::read_to_string("/proc/sys/crypto/fips_enabled") {
                 Ok(str) => str.chars().next() == Some('1'),
                 Err(_) => false,
             });
-    if fips {
-        // check if the user specified a conflicting policy
+    // check if the user specified a conflicting policy
+    if let (true, Some(policy)) = (fips, matches.value_of("policy")) {
         if matches.occurrences_of("policy") != 0 && policy != "nist" {
             err_exit(
-                &mut app,
+                app,
                 &format!("Policy setting of '{}' conflicts with --fips", policy),
                 ErrorKind::ArgumentConflict,
                 false,
             );
         }
-        // override policy
-        policy = "nist";
     }
-    assert!(!fips || (fips && policy == "nist"));
-    // instantiate the actual policy
-    let policy = make_policy(&mut app, policy);
+    fips
+}
 
-    // the policy will set default crypto-related values
-    let mut paops;
-    if let Some(defaults) = matches.value_of("defaults") {
-        paops = etree::ParseOps::new(make_policy(&mut app, defaults));
-        paops.policy = policy;
+// the policy that fips implies, or else the one of the command line, of the
+// configuration or the default
+fn policy_name<'a>(
+    matches: &'a ArgMatches,
+    fips: bool,
+    conf: Option<&'a config::Config>,
+) -> &'a str {
+    if fips {
+        "nist"
+    } else if matches.occurrences_of("policy") != 0 {
+        matches.value_of("policy").unwrap()
     } else {
-        paops = etree::ParseOps::new(policy);
+        conf.and_then(|conf| conf.policy.as_ref())
+            .map_or(consts::DEFAULT_POLICY, |policy| policy.as_str())
     }
+}
 
-    // casdir
-    if matches.occurrences_of("casdir") == 0 && Path::new("cas").is_dir() {
-        paops.casdir = Path::new("cas").to_path_buf();
-    } else {
-        paops.casdir = Path::new(matches.value_of("casdir").unwrap()).to_path_buf();
+// write the report of a read-only command on a parsed file, returning
+// whether it passed
+fn report<W: Write>(
+    out: &mut W,
+    command: Command,
+    text: &etree::TextTree,
+    paops: &mut etree::ParseOps,
+) -> Result<bool, Error> {
+    let mut passed = true;
+    let result = match command {
+        Command::List => {
+            writeln!(out, "{}:", paops.fname).and_then(|_| inspect::list(out, text, 1))
+        }
+        Command::Status => {
+            let mut counts = BTreeMap::new();
+            inspect::status(text, &mut counts);
+            writeln!(out, "{}:", paops.fname).and_then(|_| {
+                counts
+                    .iter()
+                    .map(|(keyw, status)| writeln!(out, "  {}: {}", keyw, status))
+                    .collect()
+            })
+        }
+        Command::Verify => {
+            let mut errors = Vec::new();
+            inspect::verify(text, paops, 0, &mut errors)?;
+            passed = errors.is_empty();
+            if passed {
+                writeln!(out, "{}: OK", paops.fname)
+            } else {
+                errors
+                    .iter()
+                    .map(|e| writeln!(out, "{}: {}", paops.fname, e))
+                    .collect()
+            }
+        }
+        Command::Textconv => {
+            let text = inspect::render(text, paops, 0)?;
+            etree::tree_write(out, &text, paops)
+        }
+        Command::Transform
+        | Command::GitFilter
+        | Command::MergeDriver
+        | Command::Diff
+        | Command::Edit => Ok(()),
+    };
+    result.map_err(|e| Error::Io {
+        path: Path::new("-").to_path_buf(),
+        source: e,
+    })?;
+    Ok(passed)
+}
+
+// the WORDs that have a password or a password source, or whose password
+// the agent or the keyring has, for WORD or WORD/LABEL
+fn keyed_words(paops: &mut etree::ParseOps) -> HashSet<String> {
+    let agent_words = paops
+        .agent
+        .as_ref()
+        .and_then(|agent| agent.status().ok())
+        .filter(|&(locked, _, _)| !locked)
+        .map_or_else(Vec::new, |(_, _, words)| words);
+    let policy = &paops.policy;
+    let keyring_words = paops
+        .keyring
+        .as_mut()
+        .map_or_else(Vec::new, |keyring| keyring.names(policy));
+    paops
+        .passwords
+        .keys()
+        .chain(paops.key_sources.keys())
+        .chain(agent_words.iter())
+        .chain(keyring_words.iter())
+        .map(|name| name.split('/').next().unwrap().to_string())
+        .collect()
+}
+
+// the "agent" subcommands
+fn agent_command(matches: &ArgMatches) -> Result<(), Error> {
+    let (name, sub) = match matches.subcommand() {
+        (name, Some(sub)) => (name, sub),
+        _ => unreachable!(),
+    };
+    let socket = sub
+        .value_of("socket")
+        .or(matches.value_of("socket"))
+        .map(PathBuf::from);
+    if name == "start" || name == "serve" {
+        let timeout = sub.value_of("timeout").unwrap().parse().unwrap();
+        let socket = match socket {
+            Some(socket) => socket,
+            None => agent::new_socket(&botan::RandomNumberGenerator::new().ok())?,
+        };
+        let export = format!(
+            "{}={}; export {};",
+            consts::AGENT_SOCKET_ENV,
+            socket.display(),
+            consts::AGENT_SOCKET_ENV
+        );
+        if name == "serve" {
+            println!("{}", export);
+            return agent::serve(&socket, timeout);
+        }
+        let exe = std::env::current_exe().map_err(|e| Error::Io {
+            path: PathBuf::from("enprot"),
+            source: e,
+        })?;
+        agent::start(&exe, &socket, timeout)?;
+        println!("{}", export);
+        return Ok(());
     }
-    // verbosity
-    paops.verbose = matches.occurrences_of("verbose") != 0;
-    if matches.occurrences_of("quiet") != 0 {
-        paops.verbose = false;
+
+    let agent = socket
+        .map(|socket| agent::Agent { socket })
+        .or_else(agent::Agent::from_env)
+        .ok_or_else(|| Error::Agent(format!("{} is not set", consts::AGENT_SOCKET_ENV)))?;
+    match name {
+        "stop" => agent.stop(),
+        "status" => {
+            let (locked, keys, words) = agent.status()?;
+            let state = if locked { "locked" } else { "unlocked" };
+            println!("{}: {}, {} key(s)", agent.socket.display(), state, keys);
+            for word in words {
+                println!("  {}", word);
+            }
+            Ok(())
+        }
+        "add" => {
+            for word in sub.values_of("word").unwrap() {
+                agent.add_password(word, &prot::get_password(word, false))?;
+            }
+            Ok(())
+        }
+        "lock" => agent.lock(&prot::get_password("the agent", true)),
+        "unlock" => agent.unlock(&prot::get_password("the agent", false)),
+        "forget" => agent.forget(
+            &sub.values_of("word")
+                .map_or_else(Vec::new, |words| words.collect()),
+        ),
+        _ => unreachable!(),
+    }
+}
+
+// the "keystore" subcommands
+fn keystore_command(matches: &ArgMatches, fips: bool) -> Result<(), Error> {
+    let (name, sub) = match matches.subcommand() {
+        (name, Some(sub)) => (name, sub),
+        _ => unreachable!(),
+    };
+    let path = match sub.value_of("keystore").or(matches.value_of("keystore")) {
+        Some(path) => PathBuf::from(path),
+        None => keystore::default_path().ok_or(Error::Operation(
+            "No keystore given and no home directory to find one in",
+        ))?,
+    };
+    if name != "add" && !path.exists() {
+        return Err(Error::Io {
+            path,
+            source: std::io::Error::from(std::io::ErrorKind::NotFound),
+        });
+    }
+    let policy = make_policy(policy_name(matches, fips, None));
+    let passphrase = keystore::passphrase(&path, true).unwrap();
+    let mut keystore = keystore::Keystore::open(&path, &passphrase, &policy)?;
+    let words = sub
+        .values_of("word")
+        .map_or_else(Vec::new, |words| words.collect());
+    match name {
+        "add" => {
+            let word = words[0];
+            // asked for before others have to wait for the keystore
+            let privkey = match sub.value_of("private-key") {
+                Some(keyfile) => Some(pubkey::load_private_key(Path::new(keyfile))?.encode()?),
+                None => None,
+            };
+            let pass = match privkey {
+                Some(_) => None,
+                None => Some(prot::get_password(word, true)),
+            };
+            let _lock = keystore.lock()?;
+            keystore.reload(&policy)?;
+            let entries = &mut keystore.entries;
+            entries.passwords.remove(word);
+            entries.private_keys.remove(word);
+            if let Some(pem) = privkey {
+                entries.private_keys.insert(word.to_string(), pem);
+            }
+            if let Some(pass) = pass {
+                entries.passwords.insert(word.to_string(), pass);
+            }
+            keystore.save(&botan::RandomNumberGenerator::new().ok(), &policy)
+        }
+        "remove" => {
+            let _lock = keystore.lock()?;
+            keystore.reload(&policy)?;
+            let entries = &mut keystore.entries;
+            for word in words {
+                let password = entries.passwords.remove(word);
+                let private_key = entries.private_keys.remove(word);
+                if password.is_none() && private_key.is_none() {
+                    return Err(keystore::error(
+                        &path,
+                        Error::Operation("No such keyword in the keystore"),
+                    ));
+                }
+            }
+            keystore.save(&botan::RandomNumberGenerator::new().ok(), &policy)
+        }
+        "list" => {
+            let mut lines = BTreeMap::new();
+            for word in keystore.entries.passwords.keys() {
+                lines.insert(word, "password".to_string());
+            }
+            for (word, pem) in &keystore.entries.private_keys {
+                let privkey =
+                    pubkey::PrivateKey::decode(pem).map_err(|e| keystore::error(&path, e))?;
+                let fingerprint = privkey.public.fingerprint(&policy)?;
+                lines.insert(word, format!("private key {}", fingerprint));
+            }
+            for (word, line) in lines {
+                println!("{}: {}", word, line);
+            }
+            Ok(())
+        }
+        "export" => {
+            let entries = &mut keystore.entries;
+            if !words.is_empty() {
+                entries
+                    .passwords
+                    .retain(|word, _| words.contains(&word.as_str()));
+                entries
+                    .private_keys
+                    .retain(|word, _| words.contains(&word.as_str()));
+            }
+            let exported = toml::to_string(&entries)
+                .map_err(|_| Error::Format("Invalid keystore contents"))?;
+            print!("{}", exported);
+            Ok(())
+        }
+        _ => unreachable!(),
+    }
+}
+
+// decrypt the segments of path into a private temporary file for the
+// user to edit, and encrypt the same WORDs again. Segments that were not
+// changed keep their ciphertext, and new encryptions use the settings of
+// the first segment unless they are given.
+fn edit_file(
+    matches: &ArgMatches,
+    fips: bool,
+    configs: &mut BTreeMap<PathBuf, config::Config>,
+    cwd: &Path,
+    path: &str,
+    paops: &mut etree::ParseOps,
+) -> Result<(), Error> {
+    let io_error = |e| Error::Io {
+        path: PathBuf::from(path),
+        source: e,
+    };
+    let content = fs::read(path).map_err(io_error)?;
+    let path_abs = cwd.join(path);
+    let dir = path_abs.parent().unwrap_or(cwd);
+    let config = configure_for(matches, fips, configs, dir, paops)?;
+    set_separators(
+        matches,
+        config,
+        Path::new(path),
+        Some(&path_abs),
+        &content,
+        paops,
+    );
+    paops.fname = path.to_string();
+    let tree = etree::parse(&content[..], paops)?;
+
+    let mut segments = Vec::new();
+    inspect::encrypted(&tree, &paops.decrypt, &mut segments);
+    if segments.is_empty() {
+        return Err(Error::Operation("No encrypted segments to edit"));
+    }
+    if segments.iter().any(|(keyw, extfields)| {
+        prot::has_recipients(extfields) && !paops.recipients.contains_key(*keyw)
+    }) {
+        return Err(Error::Operation(
+            "Segments with recipients need their recipients given again",
+        ));
+    }
+    let explicit = ["pbkdf", "pbkdf-params", "cipher", "bind-ad", "doc-id"]
+        .iter()
+        .any(|arg| matches.occurrences_of(arg) != 0);
+    if !explicit {
+        etree::adopt_settings(segments[0].1, paops)?;
+    }
+
+    let decrypted = etree::transform(&tree, paops)?;
+    let mut plaintext = Vec::new();
+    etree::tree_write(&mut plaintext, &decrypted, paops).map_err(io_error)?;
+    let name = Path::new(path)
+        .file_name()
+        .map_or("edit".into(), |name| name.to_string_lossy());
+    let temp = edit::TempFile::create(&name, &paops.rng)?;
+    temp.write(&plaintext)?;
+
+    // until it parses, or the user gives up
+    let (edited, tree_edited) = loop {
+        edit::run_editor(&temp.path)?;
+        let edited = temp.read()?;
+        match etree::parse(&edited[..], paops) {
+            Ok(tree) => break (edited, tree),
+            Err(e) => {
+                eprintln!("{}", e);
+                if !paops.interactive || !edit::confirm("Edit again?") {
+                    return Err(Error::Operation("Not saved, the file is unchanged"));
+                }
+            }
+        }
+    };
+    drop(temp);
+    if edited == plaintext {
+        if paops.verbose {
+            eprintln!("No changes to {}", path);
+        }
+        return Ok(());
+    }
+
+    paops.encrypt = paops.decrypt.drain().collect();
+    paops.reuse.clear();
+    etree::reuse_from(&tree, paops)?;
+    let tree_out = etree::transform(&tree_edited, paops)?;
+    let mut data = Vec::new();
+    etree::tree_write(&mut data, &tree_out, paops).map_err(io_error)?;
+    atomic::write(Path::new(path), &data, backup_suffix(matches)).map_err(io_error)
+}
+
+// compare what would be written with the original, showing the changes as a
+// diff and naming the protected keywords that appear in plaintext
+fn check_file(
+    done: &mut Processed,
+    path_in: &str,
+    path_out: &str,
+    original: &[u8],
+    tree_in: &etree::TextTree,
+    tree_out: &etree::TextTree,
+    protect: &[String],
+    paops: &mut etree::ParseOps,
+) -> Result<(), Error> {
+    let stdout_error = |e| Error::Io {
+        path: Path::new("-").to_path_buf(),
+        source: e,
+    };
+    let out = &mut done.out;
+    let mut changed = Vec::new();
+    etree::tree_write(&mut changed, tree_out, paops).map_err(|e| Error::Io {
+        path: Path::new(path_out).to_path_buf(),
+        source: e,
+    })?;
+    if changed != original {
+        done.passed = false;
+        let patch = diffy::DiffOptions::new()
+            .set_original_filename(path_in.to_string())
+            .set_modified_filename(path_out.to_string())
+            .create_patch_bytes(original, &changed);
+        out.write_all(&patch.to_bytes()).map_err(stdout_error)?;
+    }
+
+    let mut counts = BTreeMap::new();
+    inspect::status(tree_in, &mut counts);
+    for keyw in protect {
+        match counts.get(keyw) {
+            Some(status) if status.plaintext > 0 => {
+                done.passed = false;
+                writeln!(out, "{}: {} appears in plaintext", paops.fname, keyw)
+                    .map_err(stdout_error)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// the nearest configuration file for files in dir, each loaded only once
+fn find_config<'a>(
+    configs: &'a mut BTreeMap<PathBuf, config::Config>,
+    dir: &Path,
+) -> Result<Option<&'a config::Config>, Error> {
+    let path = match config::Config::find(dir) {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    if !configs.contains_key(&path) {
+        let config = config::Config::load(&path)?;
+        configs.insert(path.clone(), config);
+    }
+    Ok(configs.get(&path))
+}
+
+// find the configuration file for files in dir and configure paops with it
+fn configure_for<'a>(
+    matches: &ArgMatches,
+    fips: bool,
+    configs: &'a mut BTreeMap<PathBuf, config::Config>,
+    dir: &Path,
+    paops: &mut etree::ParseOps,
+) -> Result<Option<&'a config::Config>, Error> {
+    let config = find_config(configs, dir)?;
+    configure(matches, fips, config, paops)?;
+    if let (true, Some(config)) = (paops.verbose, config) {
+        paops.log.push(format!("Using {}", config.path.display()));
+    }
+    Ok(config)
+}
+
+// the files below dir for -R. The globs of the command line are relative to
+// dir, those of its configuration file to that file, and --include replaces
+// the include globs of the configuration.
+fn find_files(
+    matches: &ArgMatches,
+    fips: bool,
+    configs: &mut BTreeMap<PathBuf, config::Config>,
+    dir: &Path,
+    paops: &mut etree::ParseOps,
+) -> Result<Vec<PathBuf>, Error> {
+    let dir_abs = fs::canonicalize(dir).map_err(|e| Error::Io {
+        path: dir.to_path_buf(),
+        source: e,
+    })?;
+    let config = configure_for(matches, fips, configs, &dir_abs, paops)?;
+    let globs = |base: &Path, globs: &mut dyn Iterator<Item = &str>| {
+        globs
+            .map(|glob| walk::Glob::new(base, glob).unwrap())
+            .collect::<Vec<walk::Glob>>()
+    };
+    let arg_globs = |name: &str| {
+        matches
+            .values_of(name)
+            .map_or_else(Vec::new, |mut values| globs(&dir_abs, &mut values))
+    };
+    let mut include = arg_globs("include");
+    let mut exclude = arg_globs("exclude");
+    if let Some(config) = config {
+        if include.is_empty() {
+            include = globs(config.dir(), &mut config.include.iter().map(String::as_str));
+        }
+        exclude.extend(globs(
+            config.dir(),
+            &mut config.exclude.iter().map(String::as_str),
+        ));
+    }
+    let walk = walk::Walk {
+        include,
+        exclude,
+        casdir: fs::canonicalize(&paops.casdir).ok(),
+    };
+    walk.files(dir)
+}
+
+// separators by path or file type, unless given on the command line.
+// contents are those of the file at path (or path_abs, when known).
+fn set_separators(
+    matches: &ArgMatches,
+    config: Option<&config::Config>,
+    path: &Path,
+    path_abs: Option<&Path>,
+    contents: &[u8],
+    paops: &mut etree::ParseOps,
+) {
+    let filetypes = config.map_or_else(filetype::FileTypes::new, |config| config.filetypes());
+    let (left_sep, right_sep) = config
+        .and_then(|config| {
+            let path = path_abs?.strip_prefix(config.dir()).ok()?;
+            config.separators(path)
+        })
+        .or_else(|| {
+            // files with the default separators keep them
+            if filetype::has_commands(
+                contents,
+                consts::DEFAULT_LEFT_SEP,
+                consts::DEFAULT_RIGHT_SEP,
+            ) {
+                None
+            } else {
+                filetypes.separators(path, contents)
+            }
+        })
+        .unwrap_or((
+            consts::DEFAULT_LEFT_SEP.to_string(),
+            consts::DEFAULT_RIGHT_SEP.to_string(),
+        ));
+    if matches.occurrences_of("left-separator") == 0 {
+        paops.left_sep = left_sep;
+    }
+    if matches.occurrences_of("right-separator") == 0 {
+        paops.right_sep = right_sep;
+    }
+    if paops.verbose {
+        let message = format!(
+            "LEFT_SEP='{}' RIGHT_SEP='{}' casdir = '{}'",
+            paops.left_sep,
+            paops.right_sep,
+            paops.casdir.display(),
+        );
+        paops.log.push(message);
     }
+}
+
+// apply the settings that the project configuration may provide, with the
+// command line taking precedence over config and config over the defaults
+fn configure(
+    matches: &ArgMatches,
+    fips: bool,
+    config: Option<&config::Config>,
+    paops: &mut etree::ParseOps,
+) -> Result<(), Error> {
+    let empty = config::Config::default();
+    let conf = config.unwrap_or(&empty);
+
+    // fips overrides any policy setting
+    let policy = policy_name(matches, fips, Some(conf));
+    // the policy will set default crypto-related values
+    let defaults = make_policy(matches.value_of("defaults").unwrap_or(policy));
+    paops.pbkdfopts = etree::PBKDFOptions::new(&defaults);
+    paops.cipheropts = etree::CipherOptions::new(&defaults);
+    paops.pubkey_alg = defaults.default_pubkey_alg();
+    paops.defaults = defaults;
+    paops.policy = make_policy(policy);
+
+    // casdir
+    paops.casdir = if matches.occurrences_of("casdir") != 0 {
+        Path::new(matches.value_of("casdir").unwrap()).to_path_buf()
+    } else if let Some(casdir) = conf.casdir() {
+        casdir
+    } else if Path::new("cas").is_dir() {
+        Path::new("cas").to_path_buf()
+    } else {
+        Path::new(matches.value_of("casdir").unwrap()).to_path_buf()
+    };
     // max recursion depth
-    paops.max_depth = matches
-        .value_of("max-depth")
-        .unwrap()
-        .parse::<usize>()
-        .unwrap();
-    // separators
-    paops.left_sep = matches.value_of("left-separator").unwrap().to_string();
-    paops.right_sep = matches.value_of("right-separator").unwrap().to_string();
-    // transforms arguments like ["a", "b,c", "d"] into ["a", "b", "c", "d"]
+    paops.max_depth = match conf.max_depth {
+        Some(depth) if matches.occurrences_of("max-depth") == 0 => depth,
+        _ => matches
+            .value_of("max-depth")
+            .unwrap()
+            .parse::<usize>()
+            .unwrap(),
+    };
+
+    // transforms arguments like ["a", "b,c", "@d"] into ["a", "b", "c", ...]
+    // with the keywords of group d
     macro_rules! csep_arg {
         ( $set:expr, $name:expr ) => {
-            $set.extend(
-                matches
+            $set.extend(config::expand_groups(
+                config,
+                &matches
                     .values_of($name)
                     .unwrap_or(clap::Values::default())
                     .flat_map(|arg| arg.split(",").map(|val| val.to_string()))
                     .collect::<Vec<String>>(),
-            );
+            )?);
         };
     }
     // expand comma-separated args
+    paops.store.clear();
+    paops.fetch.clear();
+    paops.encrypt.clear();
+    paops.decrypt.clear();
+    paops.grant.clear();
+    paops.rekey.clear();
+    paops.migrate.clear();
     csep_arg!(paops.store, "store");
     csep_arg!(paops.fetch, "fetch");
     csep_arg!(paops.encrypt, "encrypt");
     csep_arg!(paops.encrypt, "encrypt-store");
     csep_arg!(paops.store, "encrypt-store");
     csep_arg!(paops.decrypt, "decrypt");
-    // password
-    // ["word1=pass1", "word2=pass2,word3=pass3"] ->
-    //   [(word1, pass1), (word2, pass2), (word3, pass3)]
-    paops.passwords.extend(
-        matches
-            .values_of("password")
-            .unwrap_or(clap::Values::default())
-            .flat_map(|arg| {
-                arg.split(",").map(|val| {
-                    let wordpass = val.splitn(2, '=').collect::<Vec<&str>>();
-                    (wordpass[0].to_string(), wordpass[1].to_string())
-                })
-            }),
-    );
+    csep_arg!(paops.grant, "grant");
+    csep_arg!(paops.rekey, "rekey");
+    csep_arg!(paops.migrate, "migrate");
+
+    // password sources, from the command line before the configuration
+    paops.key_sources.clear();
+    paops.key_sources.extend(key_source_args(matches));
+    for (name, source) in config.into_iter().flat_map(|config| config.key_sources()) {
+        paops.key_sources.entry(name.to_string()).or_insert(source);
+    }
 
     // pbkdf
-    if let Some(pbkdf) = matches.value_of("pbkdf") {
+    if let Some(pbkdf) = matches.value_of("pbkdf").or(conf.pbkdf.as_deref()) {
         paops.pbkdfopts.alg = pbkdf.to_string();
     }
-    if let Some(saltlen) = matches.value_of("pbkdf-salt-len") {
-        paops.pbkdfopts.saltlen = saltlen.parse::<usize>().unwrap();
+    if let Some(saltlen) = matches
+        .value_of("pbkdf-salt-len")
+        .map(|v| v.parse::<usize>().unwrap())
+        .or(conf.pbkdf_salt_len)
+    {
+        paops.pbkdfopts.saltlen = saltlen;
     }
-    if let Some(msec) = matches.value_of("pbkdf-msec") {
-        paops.pbkdfopts.msec = Some(msec.parse::<u32>().unwrap());
+    if let Some(msec) = matches
+        .value_of("pbkdf-msec")
+        .map(|v| v.parse::<u32>().unwrap())
+        .or(conf.pbkdf_msec)
+    {
+        paops.pbkdfopts.msec = Some(msec);
     }
     if let Some(val) = matches.value_of("pbkdf-params") {
         paops.pbkdfopts.msec = None;
@@ -466,34 +881,1522 @@ where
     if let Some(val) = matches.value_of("pbkdf-salt") {
         paops.pbkdfopts.salt = Some(hex::decode(val).unwrap());
     }
-    if matches.occurrences_of("pbkdf-disable-cache") != 0 {
-        paops.pbkdf_cache = None;
-    }
     // cipher
-    if let Some(cipher) = matches.value_of("cipher") {
+    if let Some(cipher) = matches.value_of("cipher").or(conf.cipher.as_deref()) {
         paops.cipheropts.alg = cipher.to_string();
     }
     if let Some(iv) = matches.value_of("cipher-iv") {
         paops.cipheropts.iv = Some(hex::decode(iv).unwrap());
     }
+    if let Some(ad) = conf.bind_ad {
+        paops.cipheropts.ad = ad;
+    }
+    if matches.is_present("bind-ad") {
+        paops.cipheropts.ad = true;
+    }
+    if let Some(doc_id) = matches.value_of("doc-id") {
+        paops.cipheropts.ad = true;
+        paops.cipheropts.doc_id = Some(doc_id.to_string());
+    }
+    paops.cipheropts.deterministic = matches.is_present("deterministic");
 
-    // print some of the processing parameters if verbose
-    if paops.verbose {
-        eprintln!(
-            "LEFT_SEP='{}' RIGHT_SEP='{}' casdir = '{}'",
-            paops.left_sep,
-            paops.right_sep,
-            paops.casdir.display(),
-        );
+    // stored passwords, kept open as long as the backend stays the same,
+    // unless a keystore is given
+    let keyring = matches.value_of("keyring").or(conf.keyring.as_deref());
+    if !matches.is_present("keystore")
+        && keyring != paops.keyring.as_ref().map(|keyring| keyring.name.as_str())
+    {
+        paops.keyring = keyring.and_then(keyring::Keyring::new);
+    }
+
+    // algorithm for key pair generation
+    if let Some(alg) = matches.value_of("pubkey-alg") {
+        paops.pubkey_alg = alg.to_string();
+    }
+    Ok(())
+}
+
+// arguments accepted by all commands
+fn common_args<'a, 'b>(default_max_depth: &'a str) -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("Produce more verbose output"),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Suppress unnecessary output"),
+        Arg::with_name("max-depth")
+            .long("max-depth")
+            .takes_value(true)
+            .value_name("DEPTH")
+            .validator(validate_non_negative::<usize>)
+            .default_value(default_max_depth)
+            .help("Maximum recursion depth (use 0 for infinite)"),
+        Arg::with_name("left-separator")
+            .short("l")
+            .long("left-separator")
+            .takes_value(true)
+            .value_name("SEP")
+            .default_value(consts::DEFAULT_LEFT_SEP)
+            .help("Specify left separator in parsing"),
+        Arg::with_name("right-separator")
+            .short("r")
+            .long("right-separator")
+            .takes_value(true)
+            .value_name("SEP")
+            .default_value(consts::DEFAULT_RIGHT_SEP)
+            .help("Specify right separator in parsing"),
+        Arg::with_name("policy")
+            .long("policy")
+            .takes_value(true)
+            .value_name("POLICY")
+            .default_value(consts::DEFAULT_POLICY)
+            .possible_values(consts::VALID_POLICIES)
+            .help("Set the policy to restrict cryptographic algorithms"),
+        Arg::with_name("defaults")
+            .long("defaults")
+            .takes_value(true)
+            .value_name("POLICY")
+            .possible_values(consts::VALID_POLICIES)
+            .help("Load settings from POLICY, but do not enforce the policy"),
+        Arg::with_name("fips").long("fips").help(
+            "Select and enforce the use of FIPS-compliant algorithms (implies --policy=nist)",
+        ),
+        Arg::with_name("casdir")
+            .short("c")
+            .long("casdir")
+            .takes_value(true)
+            .value_name("DIRECTORY")
+            .default_value("./")
+            .set(ArgSettings::HideDefaultValue)
+            .validator(|v: String| -> Result<(), String> {
+                if Path::new(&v).is_dir() {
+                    return Ok(());
+                } else {
+                    Err(String::from("Must be a directory"))
+                }
+            })
+            .help("Directory for CAS files (default \"cas\" if exists, else \".\")"),
+    ]
+}
+
+// the operations of the default command
+fn action_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("store")
+            .short("s")
+            .long("store")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Store (unencrypted) WORD segments to CAS"),
+        Arg::with_name("fetch")
+            .short("f")
+            .long("fetch")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Fetch (unencrypted) WORD segments to CAS"),
+        Arg::with_name("rekey")
+            .long("rekey")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Re-encrypt WORD segments with a new password and current settings"),
+        Arg::with_name("migrate")
+            .long("migrate")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Re-encrypt WORD segments using older algorithms with current settings"),
+        Arg::with_name("new-key")
+            .long("new-key")
+            .takes_value(true)
+            .value_name("WORD=PASSWORD")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_word_pairs(&v, "WORD=PASSWORD"))
+            .help("Specify the new PASSWORD for WORD when rekeying"),
+        Arg::with_name("grant")
+            .long("grant")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Add the given recipients to encrypted WORD segments"),
+        Arg::with_name("revoke")
+            .long("revoke")
+            .takes_value(true)
+            .value_name("WORD=RECIPIENT")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_word_pairs(&v, "WORD=RECIPIENT"))
+            .help("Remove RECIPIENT (pk.FINGERPRINT or pw.LABEL) from encrypted WORD segments"),
+        Arg::with_name("gen-keypair")
+            .long("gen-keypair")
+            .takes_value(true)
+            .value_name("KEYFILE")
+            .help("Generate a private KEYFILE and a public KEYFILE.pub, then exit"),
+        Arg::with_name("pubkey-alg")
+            .long("pubkey-alg")
+            .takes_value(true)
+            .value_name("ALG")
+            .possible_values(consts::VALID_PUBKEY_ALGS)
+            .help("Set the public key algorithm to use for --gen-keypair"),
+        Arg::with_name("encrypt")
+            .short("e")
+            .long("encrypt")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Encrypt WORD segments"),
+        Arg::with_name("encrypt-store")
+            .short("E")
+            .long("encrypt-store")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Encrypt and store WORD segments"),
+        Arg::with_name("decrypt")
+            .short("d")
+            .long("decrypt")
+            .takes_value(true)
+            .value_name("WORD")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Decrypt WORD segments"),
+    ]
+}
+
+// keys and cryptographic settings for commands that encrypt or decrypt
+fn crypto_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("password")
+            .short("k")
+            .long("key")
+            .takes_value(true)
+            .value_name("WORD=PASSWORD")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_word_pairs(&v, "WORD=PASSWORD"))
+            .help("Specify a secret PASSWORD for WORD"),
+        Arg::with_name("key-file")
+            .long("key-file")
+            .takes_value(true)
+            .value_name("WORD=FILE")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_key_source(&v, "file", "WORD=FILE"))
+            .help("Read the password for WORD from the first line of FILE"),
+        Arg::with_name("key-env")
+            .long("key-env")
+            .takes_value(true)
+            .value_name("WORD=VAR")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_key_source(&v, "env", "WORD=VAR"))
+            .help("Read the password for WORD from the environment variable VAR"),
+        Arg::with_name("key-fd")
+            .long("key-fd")
+            .takes_value(true)
+            .value_name("WORD=FD")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_key_source(&v, "fd", "WORD=FD"))
+            .help("Read the password for WORD from the first line of file descriptor FD"),
+        Arg::with_name("key-cmd")
+            .long("key-cmd")
+            .takes_value(true)
+            .value_name("WORD=COMMAND")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_key_source(&v, "cmd", "WORD=COMMAND"))
+            .help("Read the password for WORD from the first line of the output of COMMAND"),
+        Arg::with_name("keyring")
+            .long("keyring")
+            .takes_value(true)
+            .value_name("BACKEND")
+            .possible_values(consts::VALID_KEYRINGS)
+            .help("Look up passwords in the Secret Service or the keystore file, and keep the ones asked for"),
+        Arg::with_name("keystore")
+            .long("keystore")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("Use the passwords and private keys in the keystore file at PATH"),
+        Arg::with_name("recipient")
+            .long("recipient")
+            .takes_value(true)
+            .value_name("WORD=KEYFILE")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_word_pairs(&v, "WORD=KEYFILE"))
+            .help("Encrypt WORD segments to the public key in KEYFILE"),
+        Arg::with_name("password-recipient")
+            .long("password-recipient")
+            .takes_value(true)
+            .value_name("WORD=LABEL")
+            .multiple(true)
+            .number_of_values(1)
+            .validator(|v: String| validate_labels(&v))
+            .help("Encrypt WORD segments to the password for WORD/LABEL"),
+        Arg::with_name("identity")
+            .short("i")
+            .long("identity")
+            .takes_value(true)
+            .value_name("KEYFILE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Decrypt with the private key in KEYFILE"),
+        Arg::with_name("pbkdf")
+            .long("pbkdf")
+            .takes_value(true)
+            .value_name("ALG")
+            .possible_values(consts::VALID_PBKDF_ALGS)
+            .help("Set the PBKDF algorithm to use when encrypting"),
+        Arg::with_name("pbkdf-msec")
+            .long("pbkdf-msec")
+            .takes_value(true)
+            .value_name("MSEC")
+            .validator(validate_positive::<u32>)
+            .help("Set the millisecond count for the PBKDF algorithm"),
+        Arg::with_name("pbkdf-salt-len")
+            .long("pbkdf-salt-len")
+            .takes_value(true)
+            .value_name("BYTES")
+            .validator(&validate_positive::<usize>)
+            .help("Set the salt length for the PBKDF"),
+        Arg::with_name("pbkdf-params")
+            .long("pbkdf-params")
+            .takes_value(true)
+            .value_name("PARAMS")
+            .validator(validate_pbkdf_params)
+            .help("Set the PBKDF parameters, such as m=65536,p=1,t=3, instead of timing it"),
+        Arg::with_name("pbkdf-salt")
+            .long("pbkdf-salt")
+            .takes_value(true)
+            .value_name("HEX")
+            .hidden(true)
+            .help("Advanced option for testing, do not use"),
+        Arg::with_name("pbkdf-disable-cache")
+            .long("pbkdf-disable-cache")
+            .help("Disable the PBKDF cache mechanism"),
+        Arg::with_name("cipher")
+            .long("cipher")
+            .takes_value(true)
+            .value_name("ALG")
+            .possible_values(consts::VALID_CIPHER_ALGS)
+            .help("Set the cipher algorithm to use when encrypting"),
+        Arg::with_name("cipher-iv")
+            .long("cipher-iv")
+            .takes_value(true)
+            .value_name("ALG")
+            .hidden(true)
+            .help("Advanced option for testing, do not use"),
+        Arg::with_name("bind-ad")
+            .long("bind-ad")
+            .help("Bind ciphertext to its keyword and settings as associated data"),
+        Arg::with_name("doc-id")
+            .long("doc-id")
+            .takes_value(true)
+            .value_name("ID")
+            .validator(|v: String| validate_doc_id(&v))
+            .help("Bind ciphertext to the document ID (implies --bind-ad)"),
+        Arg::with_name("deterministic")
+            .long("deterministic")
+            .conflicts_with("cipher-iv")
+            .help("Derive salt and IV from the plaintext (needs --pbkdf-params)"),
+        Arg::with_name("reuse-from")
+            .long("reuse-from")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Keep the ciphertext of segments in FILE whose plaintext is unchanged"),
+    ]
+}
+
+// output files for commands that write
+fn output_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("prefix")
+            .short("p")
+            .long("prefix")
+            .takes_value(true)
+            .value_name("PREFIX")
+            .default_value("")
+            .set(ArgSettings::HideDefaultValue)
+            .set(ArgSettings::EmptyValues)
+            .help("Use PREFIX for output filenames"),
+        Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .takes_value(true)
+            .value_name("FILE")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Specify output file for previous input"),
+        backup_arg(),
+        Arg::with_name("check")
+            .long("check")
+            .help("Write nothing, show the changes as a diff and fail if there are any"),
+    ]
+}
+
+fn backup_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("backup")
+        .long("backup")
+        .takes_value(true)
+        .value_name("SUFFIX")
+        .min_values(0)
+        .max_values(1)
+        .require_equals(true)
+        .help("Keep each file that is overwritten as FILE followed by SUFFIX, ~ by default")
+}
+
+// the suffix of the backups of overwritten files, if they are kept
+fn backup_suffix<'a>(matches: &'a ArgMatches) -> Option<&'a str> {
+    if matches.is_present("backup") {
+        Some(
+            matches
+                .value_of("backup")
+                .unwrap_or(consts::DEFAULT_BACKUP_SUFFIX),
+        )
+    } else {
+        None
+    }
+}
+
+fn input_arg<'a, 'b>(index: u64) -> Arg<'a, 'b> {
+    Arg::with_name("input")
+        .required(true)
+        .index(index)
+        .value_name("FILE")
+        .default_value("-")
+        .multiple(true)
+        .help("The input file(s)")
+}
+
+// files found below directories instead of, or besides, the input files, and
+// how many of them are processed at once
+fn files_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("recursive")
+            .short("R")
+            .long("recursive")
+            .takes_value(true)
+            .value_name("DIR")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Process the text files below DIR that are not ignored"),
+        Arg::with_name("include")
+            .long("include")
+            .takes_value(true)
+            .value_name("GLOB")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("recursive")
+            .validator(|v: String| validate_glob(&v))
+            .help("Process only the files below DIR matching GLOB"),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .takes_value(true)
+            .value_name("GLOB")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("recursive")
+            .validator(|v: String| validate_glob(&v))
+            .help("Skip the files and directories below DIR matching GLOB"),
+        Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .takes_value(true)
+            .value_name("N")
+            .default_value("1")
+            .validator(validate_positive::<usize>)
+            .help("Process up to N files at the same time"),
+    ]
+}
+
+fn agent_timeout_arg<'a, 'b>(default_timeout: &'a str) -> Arg<'a, 'b> {
+    Arg::with_name("timeout")
+        .long("timeout")
+        .takes_value(true)
+        .value_name("SECONDS")
+        .default_value(default_timeout)
+        .validator(validate_non_negative::<u64>)
+        .help("Forget passwords and keys after this long, 0 for never")
+}
+
+// a command performing one operation on WORD segments, like the flag of the
+// same name
+fn operation_command<'a, 'b>(
+    name: &'a str,
+    about: &'b str,
+    default_max_depth: &'a str,
+) -> App<'a, 'b> {
+    SubCommand::with_name(name)
+        .about(about)
+        .args(&common_args(default_max_depth))
+        .args(&crypto_args())
+        .args(&output_args())
+        .args(&files_args())
+        .arg(
+            Arg::with_name(name)
+                .required(true)
+                .index(1)
+                .value_name("WORD")
+                .help("The keywords of the segments, separated by commas"),
+        )
+        .arg(input_arg(2))
+}
+
+// the settings of the command line that are the same for all input files
+fn parse_ops(matches: &ArgMatches, command: Command, fips: bool) -> etree::ParseOps {
+    // the policy is set for each input file by configure(), this one of the
+    // command line is for the keystore
+    let mut paops = etree::ParseOps::new(make_policy(policy_name(matches, fips, None)));
+
+    // verbosity
+    paops.verbose = matches.occurrences_of("verbose") != 0;
+    if matches.occurrences_of("quiet") != 0 {
+        paops.verbose = false;
+    }
+    // separators
+    paops.left_sep = matches.value_of("left-separator").unwrap().to_string();
+    paops.right_sep = matches.value_of("right-separator").unwrap().to_string();
+    // passwords from the environment, ENPROT_KEY_word1=pass1 -> (word1, pass1),
+    // unless a source is given for the word on the command line
+    let sourced: HashSet<String> = key_source_args(matches)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    paops.passwords.extend(
+        std::env::vars()
+            .filter_map(|(name, pass)| {
+                if name.len() > consts::KEY_ENV_PREFIX.len()
+                    && name.starts_with(consts::KEY_ENV_PREFIX)
+                {
+                    Some((name[consts::KEY_ENV_PREFIX.len()..].to_string(), pass))
+                } else {
+                    None
+                }
+            })
+            .filter(|(name, _)| !sourced.contains(name)),
+    );
+    // password, taking precedence over the environment
+    // ["word1=pass1", "word2=pass2,word3=pass3"] ->
+    //   [(word1, pass1), (word2, pass2), (word3, pass3)]
+    paops.passwords.extend(
+        matches
+            .values_of("password")
+            .unwrap_or(clap::Values::default())
+            .flat_map(|arg| {
+                arg.split(",").map(|val| {
+                    let wordpass = val.splitn(2, '=').collect::<Vec<&str>>();
+                    (wordpass[0].to_string(), wordpass[1].to_string())
+                })
+            }),
+    );
+
+    // new passwords for rekeying, same form as above
+    paops.new_passwords.extend(
+        matches
+            .values_of("new-key")
+            .unwrap_or(clap::Values::default())
+            .flat_map(|arg| {
+                arg.split(",").map(|val| {
+                    let wordpass = val.splitn(2, '=').collect::<Vec<&str>>();
+                    (wordpass[0].to_string(), wordpass[1].to_string())
+                })
+            }),
+    );
+
+    // recipients
+    // ["word1=file1", "word2=file2,word1=file3"] ->
+    //   {word1: [key1, key3], word2: [key2]}
+    for arg in matches
+        .values_of("recipient")
+        .unwrap_or(clap::Values::default())
+    {
+        for val in arg.split(",") {
+            let wordfile = val.splitn(2, '=').collect::<Vec<&str>>();
+            let pubkey = match pubkey::load_public_key(Path::new(wordfile[1])) {
+                Ok(pubkey) => pubkey,
+                Err(e) => abort(&e, wordfile[1]),
+            };
+            paops
+                .recipients
+                .entry(wordfile[0].to_string())
+                .or_insert_with(Vec::new)
+                .push(prot::Recipient::PublicKey(pubkey));
+        }
+    }
+    for arg in matches
+        .values_of("password-recipient")
+        .unwrap_or(clap::Values::default())
+    {
+        for val in arg.split(",") {
+            let wordlabel = val.splitn(2, '=').collect::<Vec<&str>>();
+            paops
+                .recipients
+                .entry(wordlabel[0].to_string())
+                .or_insert_with(Vec::new)
+                .push(prot::Recipient::Password(wordlabel[1].to_string()));
+        }
+    }
+    for arg in matches
+        .values_of("revoke")
+        .unwrap_or(clap::Values::default())
+    {
+        for val in arg.split(",") {
+            let wordname = val.splitn(2, '=').collect::<Vec<&str>>();
+            paops
+                .revoke
+                .entry(wordname[0].to_string())
+                .or_insert_with(Vec::new)
+                .push(wordname[1].to_string());
+        }
+    }
+    // private keys
+    for path in matches
+        .values_of("identity")
+        .unwrap_or(clap::Values::default())
+    {
+        match pubkey::load_private_key(Path::new(path)) {
+            Ok(privkey) => paops.identities.push(privkey),
+            Err(e) => abort(&e, path),
+        }
+    }
+
+    // the agent, shared by the password prompt and the PBKDF cache
+    paops.agent = agent::Agent::from_env();
+    if let Some(cache) = paops.pbkdf_cache.as_mut() {
+        cache.agent = paops.agent.clone();
+    }
+    if matches.occurrences_of("pbkdf-disable-cache") != 0 {
+        paops.pbkdf_cache = None;
+    }
+
+    // textconv and diff show what can be opened without asking
+    if command == Command::Textconv || command == Command::Diff {
+        paops.interactive = false;
+    }
+
+    // the keystore, whose passphrase the git filters cannot ask for
+    if let Some(path) = matches.value_of("keystore") {
+        let interactive =
+            paops.interactive && command != Command::GitFilter && command != Command::MergeDriver;
+        let mut keyring = keyring::Keyring::file(PathBuf::from(path));
+        match keyring.private_keys(interactive, &paops.policy) {
+            Ok(privkeys) => paops.identities.extend(privkeys),
+            Err(e) => abort(&e, path),
+        }
+        paops.keyring = Some(keyring);
+    }
+
+    // check mode writes neither files nor CAS objects
+    paops.dry_run = matches.is_present("check");
+    paops
+}
+
+// the settings of a run over the input files
+struct Run<'a> {
+    matches: &'a ArgMatches<'a>,
+    command: Command,
+    fips: bool,
+    check_mode: bool,
+    cwd: &'a Path,
+}
+
+// what processing an input file produced, shown in the order of the files
+// whatever order they are done in
+#[derive(Default)]
+struct Processed {
+    out: Vec<u8>,     // for standard output
+    log: Vec<String>, // for standard error
+    passed: bool,     // of the read-only commands and check mode
+    changed: bool,    // a file found by -R was written
+    error: Option<String>,
+}
+
+// show the messages of commands that process files one by one themselves
+fn show_log(paops: &mut etree::ParseOps) {
+    for line in paops.log.drain(..) {
+        eprintln!("{}", line);
+    }
+}
+
+// show what processing a file produced, returning whether to go on
+fn show(done: &Processed) -> bool {
+    for line in &done.log {
+        eprintln!("{}", line);
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if let Err(e) = out.write_all(&done.out).and_then(|_| out.flush()) {
+        let e = Error::Io {
+            path: PathBuf::from("-"),
+            source: e,
+        };
+        eprintln!("{}", error_message(&e, "-"));
+        return false;
+    }
+    if let Some(message) = &done.error {
+        eprintln!("{}", message);
+        return false;
+    }
+    true
+}
+
+// parse an input file and report on it or transform it
+fn process_file(
+    run: &Run,
+    configs: &mut BTreeMap<PathBuf, config::Config>,
+    paops: &mut etree::ParseOps,
+    path_in: &str,
+    path_out: &str,
+    walked: bool,
+) -> Processed {
+    let mut done = Processed {
+        passed: true,
+        ..Processed::default()
+    };
+    paops.log.clear();
+    if let Err(message) = transform_file(run, configs, paops, path_in, path_out, walked, &mut done)
+    {
+        done.error = Some(message);
+    }
+    // with the messages of parsing and transforming it, in order
+    done.log = std::mem::take(&mut paops.log);
+    done
+}
+
+fn transform_file(
+    run: &Run,
+    configs: &mut BTreeMap<PathBuf, config::Config>,
+    paops: &mut etree::ParseOps,
+    path_in: &str,
+    path_out: &str,
+    walked: bool,
+    done: &mut Processed,
+) -> Result<(), String> {
+    let matches = run.matches;
+    let io_error = |path: &str, e| {
+        let e = Error::Io {
+            path: PathBuf::from(path),
+            source: e,
+        };
+        error_message(&e, path)
+    };
+    if paops.verbose {
+        paops.log.push(format!("Reading {}", path_in));
+    }
+
+    // the nearest configuration file applies to this input
+    let path_abs = if path_in == "-" {
+        None
+    } else {
+        fs::canonicalize(path_in).ok()
+    };
+    let dir = path_abs
+        .as_ref()
+        .and_then(|path| path.parent())
+        .unwrap_or(run.cwd);
+    let config = configure_for(matches, run.fips, configs, dir, paops)
+        .map_err(|e| error_message(&e, path_in))?;
+
+    // read the input file; check mode compares the result with the
+    // original, and so do the files found by -R
+    let mut original = Vec::new();
+    if path_in == "-" {
+        std::io::stdin()
+            .read_to_end(&mut original)
+            .map_err(|e| io_error(path_in, e))?;
+    } else {
+        let mut file_in = File::open(path_in)
+            .map_err(|e| format!("Failed to open {} for reading: {}", path_in, e))?;
+        file_in
+            .read_to_end(&mut original)
+            .map_err(|e| io_error(path_in, e))?;
+    }
+
+    // separators by path or file type, unless given on the command line
+    set_separators(
+        matches,
+        config,
+        Path::new(path_in),
+        path_abs.as_deref(),
+        &original,
+        paops,
+    );
+
+    // earlier versions of the file
+    paops.reuse.clear();
+    for path in matches
+        .values_of("reuse-from")
+        .unwrap_or(clap::Values::default())
+    {
+        paops.fname = path.to_string();
+        File::open(path)
+            .map_err(|e| Error::Io {
+                path: PathBuf::from(path),
+                source: e,
+            })
+            .and_then(|file| etree::parse(BufReader::new(file), paops))
+            .and_then(|tree| etree::reuse_from(&tree, paops))
+            .map_err(|e| error_message(&e, path))?;
+    }
+
+    // parse input
+    paops.fname = if path_in == "-" {
+        "<stdin>".to_string()
+    } else {
+        path_in.to_string()
+    };
+    let tree_in = etree::parse(&original[..], paops).map_err(|e| error_message(&e, path_in))?;
+
+    // the read-only commands report on the file instead
+    if run.command != Command::Transform {
+        done.passed = report(&mut done.out, run.command, &tree_in, paops)
+            .map_err(|e| error_message(&e, path_in))?;
+        return Ok(());
+    }
+
+    // transform it
+    if paops.verbose {
+        paops.log.push(format!("Transforming {}", path_in));
+    }
+    let tree_out = etree::transform(&tree_in, paops).map_err(|e| error_message(&e, path_in))?;
+
+    // a keyword that was mistyped, or separators other than expected
+    if !walked && matches.occurrences_of("quiet") == 0 {
+        let mut counts = BTreeMap::new();
+        inspect::status(&tree_in, &mut counts);
+        inspect::status(&tree_out, &mut counts);
+        for arg in &["encrypt", "encrypt-store", "decrypt"] {
+            for keyw in matches
+                .values_of(arg)
+                .unwrap_or(clap::Values::default())
+                .flat_map(|v| v.split(','))
+            {
+                if !counts.contains_key(keyw) {
+                    paops
+                        .log
+                        .push(format!("Warning: no {} segments in {}", keyw, paops.fname));
+                }
+            }
+        }
+    }
+
+    if run.check_mode {
+        let protect = config.map_or(Ok(Vec::new()), |config| {
+            config::expand_groups(Some(config), &config.protect)
+        });
+        protect
+            .and_then(|protect| {
+                check_file(
+                    done, path_in, path_out, &original, &tree_in, &tree_out, &protect, paops,
+                )
+            })
+            .map_err(|e| error_message(&e, path_in))?;
+        return Ok(());
+    }
+
+    // write it out
+    if paops.verbose {
+        paops.log.push(format!("Writing {}", path_out));
+    }
+
+    // leave the files found by -R alone if nothing changed
+    if walked {
+        let mut data = Vec::new();
+        etree::tree_write(&mut data, &tree_out, paops).map_err(|e| io_error(path_out, e))?;
+        if data != original || path_out != path_in {
+            atomic::write(Path::new(path_out), &data, backup_suffix(matches))
+                .map_err(|e| io_error(path_out, e))?;
+            done.changed = true;
+        }
+        return Ok(());
+    }
+
+    // write it to standard output, or replace the file with it
+    if path_out == "-" {
+        return etree::tree_write(&mut done.out, &tree_out, paops)
+            .map_err(|e| io_error(path_out, e));
+    }
+    let mut data = Vec::new();
+    etree::tree_write(&mut data, &tree_out, paops).map_err(|e| io_error(path_out, e))?;
+    atomic::write(Path::new(path_out), &data, backup_suffix(matches))
+        .map_err(|e| io_error(path_out, e))
+}
+
+// Handle command line parameters
+
+pub fn app_main<I, T>(args: I)
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    // <( ENCRYPTED AUTHOR )>
+    // <( DATA X417HVMRRAs6Z1xGo5yY4TxUQ2tpAHEKQ1sg9+kfku5uUikK3y2tODtsUiGqfRGW )>
+    // <( DATA xUCGYFu02BCdqPM7uuX5UNvbfrLvKkj6gLYwg/cr42PJmr4o5xnw1qo= )>
+    // <( END AUTHOR )>
+
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    let default_max_depth = consts::DEFAULT_MAX_DEPTH.to_string();
+    let default_agent_timeout = consts::DEFAULT_AGENT_TIMEOUT.to_string();
+
+    let mut app = App::new("enprot")
+        .version(VERSION)
+        .global_setting(AppSettings::DeriveDisplayOrder)
+        .global_setting(AppSettings::ColoredHelp)
+        .global_setting(AppSettings::ColorAuto)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .setting(AppSettings::VersionlessSubcommands)
+        .args(&common_args(&default_max_depth))
+        .args(&action_args())
+        .args(&crypto_args())
+        .args(&output_args())
+        .args(&files_args())
+        .arg(input_arg(1))
+        .subcommand(
+            SubCommand::with_name("list")
+                .about("List the segments of each file as a tree")
+                .args(&common_args(&default_max_depth))
+                .args(&files_args())
+                .arg(input_arg(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Count the plaintext, encrypted and stored segments of each keyword")
+                .args(&common_args(&default_max_depth))
+                .args(&files_args())
+                .arg(input_arg(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check that stored segments exist in CAS and match their hashes")
+                .args(&common_args(&default_max_depth))
+                .args(&files_args())
+                .arg(input_arg(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("textconv")
+                .about("Show files with the segments that can be opened decrypted or fetched")
+                .args(&common_args(&default_max_depth))
+                .args(&crypto_args())
+                .arg(input_arg(1)),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare two files as shown by textconv")
+                .args(&common_args(&default_max_depth))
+                .args(&crypto_args())
+                .arg(
+                    Arg::with_name("old")
+                        .required(true)
+                        .index(1)
+                        .value_name("A")
+                        .help("The file to compare from"),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .required(true)
+                        .index(2)
+                        .value_name("B")
+                        .help("The file to compare to"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("edit")
+                .about("Edit WORD segments decrypted in $EDITOR and encrypt them again")
+                .args(&common_args(&default_max_depth))
+                .args(&crypto_args())
+                .arg(backup_arg())
+                .arg(
+                    Arg::with_name("decrypt")
+                        .short("d")
+                        .long("decrypt")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("WORD")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Decrypt WORD segments for editing"),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .index(1)
+                        .value_name("FILE")
+                        .help("The file to edit"),
+                ),
+        )
+        .subcommand(operation_command(
+            "encrypt",
+            "Encrypt WORD segments",
+            &default_max_depth,
+        ))
+        .subcommand(operation_command(
+            "decrypt",
+            "Decrypt WORD segments",
+            &default_max_depth,
+        ))
+        .subcommand(operation_command(
+            "store",
+            "Store (unencrypted) WORD segments to CAS",
+            &default_max_depth,
+        ))
+        .subcommand(operation_command(
+            "fetch",
+            "Fetch (unencrypted) WORD segments from CAS",
+            &default_max_depth,
+        ))
+        .subcommand(
+            SubCommand::with_name("git-filter")
+                .about("Run as a git filter from standard input to standard output")
+                .args(&common_args(&default_max_depth))
+                .args(&crypto_args())
+                .arg(
+                    Arg::with_name("mode")
+                        .required(true)
+                        .index(1)
+                        .possible_values(&["clean", "smudge", "process"])
+                        .help("Encrypt (clean), decrypt (smudge) or serve the filter protocol"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .index(2)
+                        .value_name("FILE")
+                        .help("Path of the file in the repository"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge-driver")
+                .about("Merge three versions of a file as a git merge driver")
+                .args(&common_args(&default_max_depth))
+                .args(&crypto_args())
+                .arg(
+                    Arg::with_name("base")
+                        .required(true)
+                        .index(1)
+                        .value_name("BASE")
+                        .help("The common ancestor (%O)"),
+                )
+                .arg(
+                    Arg::with_name("current")
+                        .required(true)
+                        .index(2)
+                        .value_name("CURRENT")
+                        .help("The current version, replaced with the result (%A)"),
+                )
+                .arg(
+                    Arg::with_name("other")
+                        .required(true)
+                        .index(3)
+                        .value_name("OTHER")
+                        .help("The version being merged (%B)"),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .index(4)
+                        .value_name("FILE")
+                        .help("Path of the file in the repository (%P)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("agent")
+                .about("Keep passwords and derived keys in memory for later invocations")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(
+                    Arg::with_name("socket")
+                        .long("socket")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .global(true)
+                        .help(
+                            "The socket of the agent [default: ENPROT_AGENT_SOCK, or a new \
+                             one when starting]",
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("start")
+                        .about("Start an agent in the background and print its environment")
+                        .arg(agent_timeout_arg(&default_agent_timeout)),
+                )
+                .subcommand(
+                    SubCommand::with_name("serve")
+                        .about("Run an agent in the foreground")
+                        .arg(agent_timeout_arg(&default_agent_timeout)),
+                )
+                .subcommand(SubCommand::with_name("stop").about("Stop the agent"))
+                .subcommand(
+                    SubCommand::with_name("status")
+                        .about("Show whether the agent is locked and the WORDs it has"),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Ask for the passwords of WORDs and give them to the agent")
+                        .arg(
+                            Arg::with_name("word")
+                                .required(true)
+                                .index(1)
+                                .multiple(true)
+                                .value_name("WORD")
+                                .help("The keywords"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("lock")
+                        .about("Keep the agent from giving out anything until unlocked"),
+                )
+                .subcommand(
+                    SubCommand::with_name("unlock")
+                        .about("Unlock the agent with the password it was locked with"),
+                )
+                .subcommand(
+                    SubCommand::with_name("forget")
+                        .about("Forget the passwords of WORDs and their keys, or everything")
+                        .arg(
+                            Arg::with_name("word")
+                                .index(1)
+                                .multiple(true)
+                                .value_name("WORD")
+                                .help("The keywords [default: all]"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("keystore")
+                .about("Manage the passwords and private keys in a keystore file")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .arg(
+                    Arg::with_name("keystore")
+                        .long("keystore")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .global(true)
+                        .help("The keystore file [default: $XDG_DATA_HOME/enprot/keystore]"),
+                )
+                .arg(
+                    Arg::with_name("policy")
+                        .long("policy")
+                        .takes_value(true)
+                        .value_name("POLICY")
+                        .possible_values(consts::VALID_POLICIES)
+                        .global(true)
+                        .help("Set the policy to restrict cryptographic algorithms"),
+                )
+                .arg(
+                    Arg::with_name("fips").long("fips").global(true).help(
+                        "Enforce the use of FIPS-compliant algorithms (implies --policy=nist)",
+                    ),
+                )
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add the password of WORD, or a private key for it")
+                        .arg(
+                            Arg::with_name("word")
+                                .required(true)
+                                .index(1)
+                                .value_name("WORD")
+                                .help("The keyword"),
+                        )
+                        .arg(
+                            Arg::with_name("private-key")
+                                .long("private-key")
+                                .takes_value(true)
+                                .value_name("KEYFILE")
+                                .help("Add the private key in KEYFILE instead of a password"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove the entries of WORDs")
+                        .arg(
+                            Arg::with_name("word")
+                                .required(true)
+                                .index(1)
+                                .multiple(true)
+                                .value_name("WORD")
+                                .help("The keywords"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("List the WORDs and whether they have a password or a private key"),
+                )
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Print the entries of WORDs, or all of them, in plaintext")
+                        .arg(
+                            Arg::with_name("word")
+                                .index(1)
+                                .multiple(true)
+                                .value_name("WORD")
+                                .help("The keywords [default: all]"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("git-setup")
+                .about("Set up the git filter, diff and merge driver in the current repository")
+                .arg(
+                    Arg::with_name("pattern")
+                        .index(1)
+                        .multiple(true)
+                        .value_name("PATTERN")
+                        .default_value("*.ept")
+                        .help("Patterns of the files to filter"),
+                ),
+        );
+    let top = app.clone().get_matches_from(args);
+    if let ("agent", Some(sub)) = top.subcommand() {
+        if let Err(e) = agent_command(sub) {
+            abort(&e, "agent");
+        }
+        return;
+    }
+    if let ("keystore", Some(sub)) = top.subcommand() {
+        let fips = fips_mode(&mut app, sub);
+        if let Err(e) = keystore_command(sub, fips) {
+            abort(&e, "keystore");
+        }
+        return;
+    }
+    if let ("git-setup", Some(sub)) = top.subcommand() {
+        let patterns = sub.values_of("pattern").unwrap().collect::<Vec<&str>>();
+        let result = std::env::current_exe()
+            .map_err(|e| Error::Io {
+                path: PathBuf::from("enprot"),
+                source: e,
+            })
+            .and_then(|exe| gitfilter::setup(&exe, &patterns));
+        if let Err(e) = result {
+            abort(&e, ".");
+        }
+        return;
+    }
+    // without a subcommand, the operations are given as flags
+    let (command, matches) = match top.subcommand() {
+        ("list", Some(sub)) => (Command::List, sub),
+        ("status", Some(sub)) => (Command::Status, sub),
+        ("verify", Some(sub)) => (Command::Verify, sub),
+        ("textconv", Some(sub)) => (Command::Textconv, sub),
+        ("diff", Some(sub)) => (Command::Diff, sub),
+        ("edit", Some(sub)) => (Command::Edit, sub),
+        ("git-filter", Some(sub)) => (Command::GitFilter, sub),
+        ("merge-driver", Some(sub)) => (Command::MergeDriver, sub),
+        (_, Some(sub)) => (Command::Transform, sub),
+        _ => (Command::Transform, &top),
+    };
+
+    // check if fips mode is requested (implicitly or explicitly)
+    let fips = fips_mode(&mut app, matches);
+
+    let mut paops = parse_ops(matches, command, fips);
+    let quiet = matches.occurrences_of("quiet") != 0;
+    let check_mode = matches.is_present("check");
+
+    // configuration files are looked up from each input file, or from the
+    // current directory for standard input and key pair generation
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let mut configs = BTreeMap::new();
+
+    // key pair generation
+    if let Some(path) = matches.value_of("gen-keypair") {
+        let result = find_config(&mut configs, &cwd)
+            .and_then(|config| configure(matches, fips, config, &mut paops))
+            .and_then(|_| {
+                pubkey::PrivateKey::generate(&paops.pubkey_alg, &paops.rng, &paops.policy)
+            })
+            .and_then(|privkey| pubkey::save_keypair(Path::new(path), &privkey));
+        if let Err(e) = result {
+            abort(&e, path);
+        }
+        return;
+    }
+
+    // git filters, which cannot prompt since standard input is the file
+    if command == Command::GitFilter {
+        paops.interactive = false;
+        let mode = matches.value_of("mode").unwrap();
+        let path = matches.value_of("path").unwrap_or("");
+        let mut filter = |clean: bool, path: &str, content: &[u8]| -> Result<Vec<u8>, Error> {
+            let result = (|| -> Result<Vec<u8>, Error> {
+                let path_abs = if path.is_empty() {
+                    None
+                } else {
+                    Some(cwd.join(path))
+                };
+                let dir = path_abs
+                    .as_ref()
+                    .and_then(|path| path.parent())
+                    .unwrap_or(&cwd);
+                let config = configure_for(matches, fips, &mut configs, dir, &mut paops)?;
+                set_separators(
+                    matches,
+                    config,
+                    Path::new(path),
+                    path_abs.as_deref(),
+                    content,
+                    &mut paops,
+                );
+                let protect = match config {
+                    Some(config) => config::expand_groups(Some(config), &config.protect)?,
+                    None => Vec::new(),
+                };
+
+                // segments are encrypted on clean and decrypted on smudge when
+                // there is a key for their WORD
+                let words = keyed_words(&mut paops);
+                if clean {
+                    paops.encrypt = words;
+                    paops.encrypt.extend(paops.recipients.keys().cloned());
+                } else {
+                    paops.decrypt = words;
+                }
+
+                paops.fname = if path.is_empty() {
+                    "<stdin>".to_string()
+                } else {
+                    path.to_string()
+                };
+
+                // the version in the index keeps its ciphertext where unchanged
+                paops.reuse.clear();
+                if let (true, Some(previous)) = (clean, gitfilter::indexed(path)) {
+                    let tree = etree::parse(&previous[..], &mut paops)?;
+                    etree::reuse_from(&tree, &mut paops)?;
+                }
+
+                let tree = etree::parse(content, &mut paops)?;
+                let tree = etree::transform(&tree, &mut paops)?;
+
+                // protected segments are never committed in plaintext, as when
+                // there is no password for them
+                if clean {
+                    let mut counts = BTreeMap::new();
+                    inspect::status(&tree, &mut counts);
+                    for keyw in &protect {
+                        match counts.get(keyw) {
+                            Some(status) if status.plaintext > 0 => {
+                                return Err(Error::segment(
+                                    "cleaning",
+                                    keyw,
+                                    Error::Operation("protected segment left in plaintext"),
+                                ));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
+                let mut data = Vec::new();
+                etree::tree_write(&mut data, &tree, &mut paops).map_err(|e| Error::Io {
+                    path: PathBuf::from("-"),
+                    source: e,
+                })?;
+                Ok(data)
+            })();
+            show_log(&mut paops);
+            result
+        };
+
+        let stdin = std::io::stdin();
+        let stdout = std::io::stdout();
+        let result = if mode == "process" {
+            gitfilter::process(&mut stdin.lock(), &mut stdout.lock(), filter)
+        } else {
+            let mut content = Vec::new();
+            let stdio_error = |e| Error::Io {
+                path: PathBuf::from("-"),
+                source: e,
+            };
+            stdin
+                .lock()
+                .read_to_end(&mut content)
+                .map_err(stdio_error)
+                .and_then(|_| filter(mode == "clean", path, &content))
+                .and_then(|data| {
+                    let mut out = stdout.lock();
+                    out.write_all(&data)
+                        .and_then(|_| out.flush())
+                        .map_err(stdio_error)
+                })
+        };
+        if let Err(e) = result {
+            abort(&e, path);
+        }
+        return;
+    }
+
+    // git merge driver, which cannot prompt either
+    if command == Command::MergeDriver {
+        paops.interactive = false;
+        let path_ours = matches.value_of("current").unwrap();
+        let paths = [
+            matches.value_of("base").unwrap(),
+            path_ours,
+            matches.value_of("other").unwrap(),
+        ];
+        let path = matches.value_of("path").unwrap_or(path_ours);
+        let result = (|| -> Result<usize, Error> {
+            let mut contents = Vec::new();
+            for path in &paths {
+                contents.push(fs::read(path).map_err(|e| Error::Io {
+                    path: PathBuf::from(path),
+                    source: e,
+                })?);
+            }
+            let path_abs = cwd.join(path);
+            let dir = path_abs.parent().unwrap_or(&cwd);
+            let config = configure_for(matches, fips, &mut configs, dir, &mut paops)?;
+            set_separators(
+                matches,
+                config,
+                Path::new(path),
+                Some(&path_abs),
+                &contents[1],
+                &mut paops,
+            );
+
+            // decrypt the segments there is a password for, ours last for
+            // its line terminator
+            paops.decrypt = keyed_words(&mut paops);
+            let mut trees = Vec::new();
+            let mut plain = Vec::new();
+            for i in &[0, 2, 1] {
+                paops.fname = path.to_string();
+                let tree = etree::parse(&contents[*i][..], &mut paops)?;
+                plain.push(etree::transform(&tree, &mut paops)?);
+                trees.push(tree);
+            }
+            let (merged, conflicts) = merge::merge(&plain[0], &plain[2], &plain[1], &paops.eol);
+
+            // and encrypt them again, keeping the ciphertext of segments
+            // that are the same as in either version
+            paops.encrypt = paops.decrypt.drain().collect();
+            paops.encrypt.extend(paops.recipients.keys().cloned());
+            paops.reuse.clear();
+            for tree in trees.iter().rev() {
+                etree::reuse_from(tree, &mut paops)?;
+            }
+            let merged = etree::transform(&merged, &mut paops)?;
+            let mut data = Vec::new();
+            etree::tree_write(&mut data, &merged, &mut paops)
+                .and_then(|_| atomic::write(Path::new(path_ours), &data, None))
+                .map_err(|e| Error::Io {
+                    path: PathBuf::from(path_ours),
+                    source: e,
+                })?;
+            Ok(conflicts)
+        })();
+        show_log(&mut paops);
+        match result {
+            Ok(0) => return,
+            Ok(conflicts) => {
+                eprintln!("{}: {} conflict(s)", path, conflicts);
+                std::process::exit(1);
+            }
+            Err(e) => abort(&e, path),
+        }
+    }
+
+    // two files compared after rendering
+    if command == Command::Diff {
+        let paths = [
+            matches.value_of("old").unwrap(),
+            matches.value_of("new").unwrap(),
+        ];
+        let mut rendered = Vec::new();
+        for path in &paths {
+            let result = (|| -> Result<Vec<u8>, Error> {
+                let content = fs::read(path).map_err(|e| Error::Io {
+                    path: PathBuf::from(path),
+                    source: e,
+                })?;
+                let path_abs = cwd.join(path);
+                let dir = path_abs.parent().unwrap_or(&cwd);
+                let config = configure_for(matches, fips, &mut configs, dir, &mut paops)?;
+                set_separators(
+                    matches,
+                    config,
+                    Path::new(path),
+                    Some(&path_abs),
+                    &content,
+                    &mut paops,
+                );
+                paops.fname = path.to_string();
+                let tree = etree::parse(&content[..], &mut paops)?;
+                let tree = inspect::render(&tree, &mut paops, 0)?;
+                let mut data = Vec::new();
+                etree::tree_write(&mut data, &tree, &mut paops).map_err(|e| Error::Io {
+                    path: PathBuf::from(path),
+                    source: e,
+                })?;
+                Ok(data)
+            })();
+            show_log(&mut paops);
+            match result {
+                Ok(data) => rendered.push(data),
+                Err(e) => abort(&e, path),
+            }
+        }
+        if rendered[0] != rendered[1] {
+            let patch = diffy::DiffOptions::new()
+                .set_original_filename(paths[0].to_string())
+                .set_modified_filename(paths[1].to_string())
+                .create_patch_bytes(&rendered[0], &rendered[1]);
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            if let Err(e) = out.write_all(&patch.to_bytes()).and_then(|_| out.flush()) {
+                abort(
+                    &Error::Io {
+                        path: PathBuf::from("-"),
+                        source: e,
+                    },
+                    "-",
+                );
+            }
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // editing in place
+    if command == Command::Edit {
+        let path = matches.value_of("file").unwrap();
+        let result = edit_file(matches, fips, &mut configs, &cwd, path, &mut paops);
+        show_log(&mut paops);
+        if let Err(e) = result {
+            abort(&e, path);
+        }
+        return;
     }
 
     // process all files
     let mut files = Vec::<(String, String)>::new();
-    let prefix = matches.value_of("prefix").unwrap();
+    let prefix = matches.value_of("prefix").unwrap_or("");
     let mut outiter = matches
         .values_of("output")
         .unwrap_or(clap::Values::default());
-    for input in matches.values_of("input").unwrap() {
+    // standard input is the default only without -R
+    let inputs = if matches.occurrences_of("input") == 0 && matches.is_present("recursive") {
+        clap::Values::default()
+    } else {
+        matches.values_of("input").unwrap()
+    };
+    for input in inputs {
         if let Some(output) = outiter.next() {
             files.push((input.to_string(), output.to_string()));
         } else {
@@ -504,69 +2407,140 @@ where
             files.push((input.to_string(), output));
         }
     }
-
-    for (path_in, path_out) in files {
-        if paops.verbose {
-            eprintln!("Reading {}", path_in);
-        }
-
-        // open input file
-        let reader_in: Box<dyn BufRead> = if path_in == "-" {
-            Box::new(BufReader::new(std::io::stdin()))
-        } else {
-            match File::open(&path_in) {
-                Ok(file_in) => Box::new(BufReader::new(file_in)),
-                Err(e) => {
-                    eprintln!("Failed to open {} for reading: {}", path_in, e);
-                    ::std::process::exit(1);
+    // the files found by -R follow, and are only written when they change
+    let listed = files.len();
+    for dir in matches
+        .values_of("recursive")
+        .unwrap_or(clap::Values::default())
+    {
+        match find_files(matches, fips, &mut configs, Path::new(dir), &mut paops) {
+            Ok(found) => {
+                for path in found {
+                    let input = path.to_string_lossy().to_string();
+                    files.push((input.clone(), prefix.to_string() + &input));
                 }
             }
-        };
-
-        // parse input
-        paops.fname = if path_in == "-" {
-            "<stdin>".to_string()
-        } else {
-            path_in.to_string()
-        };
-        let tree_in = match etree::parse(reader_in, &mut paops) {
-            Ok(tree) => tree,
-            Err(e) => {
-                eprintln!("{} in {}, aborting.", e, path_in);
-                ::std::process::exit(1);
-            }
-        };
+            Err(e) => abort(&e, dir),
+        }
+    }
+    let found = files.len() - listed;
 
-        // transform it
-        if paops.verbose {
-            eprintln!("Transforming {}", path_in);
+    let run = Run {
+        matches,
+        command,
+        fips,
+        check_mode,
+        cwd: &cwd,
+    };
+    let jobs = matches
+        .value_of("jobs")
+        .map_or(1, |v| v.parse::<usize>().unwrap());
+    let mut failed = false;
+    let mut changed = Vec::new();
+    // show what each file produced in the order of the files, returning
+    // whether to go on
+    let mut show_next = |i: usize, done: Processed| {
+        failed |= !done.passed;
+        if done.changed {
+            changed.push(files[i].1.clone());
         }
-        let tree_out = match etree::transform(&tree_in, &mut paops) {
-            Ok(tree) => tree,
-            Err(e) => {
-                eprintln!("{} in {}, aborting.", e, path_in);
-                ::std::process::exit(1);
+        show(&done)
+    };
+    if jobs == 1 {
+        for (i, (path_in, path_out)) in files.iter().enumerate() {
+            let done = process_file(
+                &run,
+                &mut configs,
+                &mut paops,
+                path_in,
+                path_out,
+                i >= listed,
+            );
+            if !show_next(i, done) {
+                std::process::exit(1);
             }
-        };
-
-        // write it out
-        if paops.verbose {
-            eprintln!("Writing {}", path_out);
         }
+    } else {
+        // the workers take the next file until there are none left or one
+        // of them failed; they start from the settings of the command line,
+        // with the keystore opened once, and the passwords they find and the
+        // keys they derive are shared
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let keystore = paops
+            .keyring
+            .take()
+            .filter(|_| matches.is_present("keystore"));
+        let shared = match etree::Shared::new(&paops, keystore) {
+            Ok(shared) => Arc::new(shared),
+            Err(e) => abort(&e, "-"),
+        };
+        let cache = Arc::new(Mutex::new(Vec::new()));
+        let (sender, receiver) = mpsc::channel();
+        let completed = thread::scope(|scope| {
+            for _ in 0..jobs.min(files.len()) {
+                let sender = sender.clone();
+                let (run, files, next, stop) = (&run, &files, &next, &stop);
+                let (shared, cache) = (shared.clone(), cache.clone());
+                scope.spawn(move || {
+                    let policy = make_policy(policy_name(run.matches, run.fips, None));
+                    let mut paops = etree::ParseOps::worker(&shared, policy);
+                    if let Some(pbkdf_cache) = paops.pbkdf_cache.as_mut() {
+                        pbkdf_cache.shared = Some(cache);
+                    }
+                    let mut configs = BTreeMap::new();
+                    while !stop.load(Ordering::SeqCst) {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        if i >= files.len() {
+                            break;
+                        }
+                        let (path_in, path_out) = &files[i];
+                        let done = process_file(
+                            run,
+                            &mut configs,
+                            &mut paops,
+                            path_in,
+                            path_out,
+                            i >= listed,
+                        );
+                        if done.error.is_some() {
+                            stop.store(true, Ordering::SeqCst);
+                        }
+                        if sender.send((i, done)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
 
-        // open output file
-        let mut writer_out: Box<dyn Write> = if path_out == "-" {
-            Box::new(BufWriter::new(std::io::stdout()))
-        } else {
-            match File::create(&path_out) {
-                Ok(file_out) => Box::new(BufWriter::new(file_out)),
-                Err(e) => {
-                    eprintln!("Failed to open {} for writing: {}", path_out, e);
-                    ::std::process::exit(1);
+            // the files done out of order wait for those before them
+            let mut pending = BTreeMap::new();
+            let mut shown = 0;
+            for (i, done) in receiver {
+                pending.insert(i, done);
+                while let Some(done) = pending.remove(&shown) {
+                    if !show_next(shown, done) {
+                        stop.store(true, Ordering::SeqCst);
+                        return false;
+                    }
+                    shown += 1;
                 }
             }
-        };
-
-        etree::tree_write(&mut writer_out, &tree_out, &mut paops);
+            true
+        });
+        if !completed {
+            std::process::exit(1);
+        }
+    }
+    // a summary of what -R changed
+    if found != 0 && command == Command::Transform && !check_mode && !quiet {
+        for path in &changed {
+            println!("{}", path);
+        }
+        println!("{} of {} file(s) changed", changed.len(), found);
+    }
+    if failed {
+        std::process::exit(1);
     }
 }
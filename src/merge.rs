@@ -0,0 +1,302 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use diffy::{ConflictStyle, MergeOptions};
+use std::collections::HashMap;
+
+use etree::{TextNode, TextTree};
+
+// a line of plain text, or the n-th segment of a keyword. Segments are
+// matched across the versions by their keyword and position, so that
+// changes inside them don't show up at this level.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Token {
+    Line(String),
+    Segment(String, usize),
+}
+
+// one level of a version as tokens, with the segments they stand for
+struct Version<'a> {
+    tokens: Vec<Token>,
+    segments: HashMap<Token, &'a TextNode>,
+}
+
+impl<'a> Version<'a> {
+    fn new(text: &'a TextTree) -> Version<'a> {
+        let mut version = Version {
+            tokens: Vec::new(),
+            segments: HashMap::new(),
+        };
+        let mut counts = HashMap::new();
+        for node in text {
+            let keyw = match node {
+                TextNode::Plain(lines) => {
+                    version.tokens.extend(
+                        lines
+                            .split_inclusive('\n')
+                            .map(|line| Token::Line(line.to_string())),
+                    );
+                    continue;
+                }
+                TextNode::BeginEnd { keyw, .. }
+                | TextNode::Encrypted { keyw, .. }
+                | TextNode::Stored { keyw, .. } => keyw.as_str(),
+                TextNode::Data { .. } => "",
+            };
+            let count = counts.entry(keyw).or_insert(0);
+            let token = Token::Segment(keyw.to_string(), *count);
+            *count += 1;
+            version.tokens.push(token.clone());
+            version.segments.insert(token, node);
+        }
+        version
+    }
+}
+
+// the merged text of one level
+struct Output<'a> {
+    text: TextTree,
+    conflicts: usize,
+    eol: &'a str,
+}
+
+impl<'a> Output<'a> {
+    fn plain(&mut self, line: &str) {
+        if let Some(TextNode::Plain(last)) = self.text.last_mut() {
+            *last += line;
+            return;
+        }
+        self.text.push(TextNode::Plain(line.to_string()));
+    }
+
+    // conflict markers go on lines of their own
+    fn marker(&mut self, marker: &str) {
+        let newline = match self.text.last() {
+            Some(TextNode::Plain(last)) => !last.ends_with('\n'),
+            _ => false,
+        };
+        if newline {
+            let eol = self.eol;
+            self.plain(eol);
+        }
+        let line = format!("{}{}", marker, self.eol);
+        self.plain(&line);
+    }
+
+    fn node(&mut self, node: &TextNode) {
+        match node {
+            TextNode::Plain(lines) => self.plain(lines),
+            _ => self.text.push(node.clone()),
+        }
+    }
+}
+
+// three-way merge of decrypted trees. Plain text is merged line by line and
+// segments recursively, so conflict markers end up inside the segments.
+// Returns the merged tree and the number of conflicts.
+pub fn merge(base: &TextTree, ours: &TextTree, theirs: &TextTree, eol: &str) -> (TextTree, usize) {
+    let versions = [Version::new(base), Version::new(ours), Version::new(theirs)];
+
+    // number the tokens, and merge the numbers as lines
+    let mut numbers = HashMap::new();
+    let mut tokens = Vec::new();
+    let mut lines = Vec::new();
+    for version in &versions {
+        let mut text = String::new();
+        for token in &version.tokens {
+            let number = *numbers.entry(token).or_insert_with(|| {
+                tokens.push(token);
+                tokens.len() - 1
+            });
+            text += &format!("{}\n", number);
+        }
+        lines.push(text);
+    }
+    let mut options = MergeOptions::new();
+    options.set_conflict_style(ConflictStyle::Merge);
+    let merged = match options.merge(&lines[0], &lines[1], &lines[2]) {
+        Ok(merged) | Err(merged) => merged,
+    };
+
+    let mut output = Output {
+        text: Vec::new(),
+        conflicts: 0,
+        eol,
+    };
+    // within a conflict, the side the lines come from
+    let mut side = None;
+    for line in merged.lines() {
+        if line.starts_with("<<<<<<<") {
+            output.marker("<<<<<<< ours");
+            output.conflicts += 1;
+            side = Some(&versions[1]);
+            continue;
+        } else if line.starts_with("=======") {
+            output.marker("=======");
+            side = Some(&versions[2]);
+            continue;
+        } else if line.starts_with(">>>>>>>") {
+            output.marker(">>>>>>> theirs");
+            side = None;
+            continue;
+        }
+
+        let token = tokens[line.parse::<usize>().unwrap()];
+        match (token, side) {
+            (Token::Line(line), _) => output.plain(line),
+            (Token::Segment(..), Some(version)) => output.node(version.segments[token]),
+            (Token::Segment(..), None) => {
+                let [base, ours, theirs] = &versions;
+                merge_segment(
+                    base.segments.get(token).cloned(),
+                    ours.segments.get(token).cloned(),
+                    theirs.segments.get(token).cloned(),
+                    &mut output,
+                );
+            }
+        }
+    }
+    (output.text, output.conflicts)
+}
+
+fn merge_segment(
+    base: Option<&TextNode>,
+    ours: Option<&TextNode>,
+    theirs: Option<&TextNode>,
+    output: &mut Output,
+) {
+    let (ours, theirs) = match (ours, theirs) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        (Some(node), None) | (None, Some(node)) => return output.node(node),
+        (None, None) => return,
+    };
+    if ours == theirs || base == Some(theirs) {
+        return output.node(ours);
+    }
+    if base == Some(ours) {
+        return output.node(theirs);
+    }
+
+    // both changed the plaintext
+    if let (
+        TextNode::BeginEnd {
+            keyw,
+            txt: ours_txt,
+            indent,
+            lines,
+        },
+        TextNode::BeginEnd {
+            txt: theirs_txt, ..
+        },
+    ) = (ours, theirs)
+    {
+        let empty = Vec::new();
+        let base_txt = match base {
+            Some(TextNode::BeginEnd { txt, .. }) => txt,
+            _ => &empty,
+        };
+        let (txt, conflicts) = merge(base_txt, ours_txt, theirs_txt, output.eol);
+        output.conflicts += conflicts;
+        return output.node(&TextNode::BeginEnd {
+            keyw: keyw.to_string(),
+            txt,
+            indent: indent.to_string(),
+            lines: lines.clone(),
+        });
+    }
+
+    // both changed a segment that could not be decrypted
+    output.marker("<<<<<<< ours");
+    output.node(ours);
+    output.marker("=======");
+    output.node(theirs);
+    output.marker(">>>>>>> theirs");
+    output.conflicts += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crypto::CryptoPolicyDefault;
+    use etree::{self, ParseOps};
+
+    fn merge_ept(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let mut paops = ParseOps::new(Box::new(CryptoPolicyDefault {}));
+        let mut parse = |ept: &str| etree::parse(Cursor::new(ept), &mut paops).unwrap();
+        let (base, ours, theirs) = (parse(base), parse(ours), parse(theirs));
+        let (merged, conflicts) = merge(&base, &ours, &theirs, "\n");
+        let mut out = Vec::new();
+        etree::tree_write(&mut out, &merged, &mut paops).unwrap();
+        (String::from_utf8(out).unwrap(), conflicts)
+    }
+
+    // changes to different lines of the same segment
+    #[test]
+    fn merge_in_segment() {
+        let base = "a\n// <( BEGIN GEHEIM )>\n1\n2\n3\n// <( END GEHEIM )>\nb\n";
+        let ours = "a\n// <( BEGIN GEHEIM )>\none\n2\n3\n// <( END GEHEIM )>\nb\n";
+        let theirs = "A\n// <( BEGIN GEHEIM )>\n1\n2\nthree\n// <( END GEHEIM )>\nb\n";
+        assert_eq!(
+            merge_ept(base, ours, theirs),
+            (
+                "A\n// <( BEGIN GEHEIM )>\none\n2\nthree\n// <( END GEHEIM )>\nb\n".to_string(),
+                0
+            )
+        );
+    }
+
+    // conflicting changes get markers inside the segment
+    #[test]
+    fn conflict_in_segment() {
+        let base = "// <( BEGIN GEHEIM )>\n1\n// <( END GEHEIM )>\n";
+        let ours = "// <( BEGIN GEHEIM )>\none\n// <( END GEHEIM )>\n";
+        let theirs = "// <( BEGIN GEHEIM )>\nuno\n// <( END GEHEIM )>\n";
+        assert_eq!(
+            merge_ept(base, ours, theirs),
+            (
+                "// <( BEGIN GEHEIM )>\n<<<<<<< ours\none\n=======\nuno\n>>>>>>> theirs\n\
+                 // <( END GEHEIM )>\n"
+                    .to_string(),
+                1
+            )
+        );
+    }
+
+    // segments that stay encrypted can only be taken as a whole
+    #[test]
+    fn conflict_encrypted() {
+        let base = "// <( ENCRYPTED GEHEIM )>\n// <( DATA AAAA )>\n// <( END GEHEIM )>\n";
+        let ours = "// <( ENCRYPTED GEHEIM )>\n// <( DATA BBBB )>\n// <( END GEHEIM )>\n";
+        let theirs = "// <( ENCRYPTED GEHEIM )>\n// <( DATA CCCC )>\n// <( END GEHEIM )>\n";
+        assert_eq!(merge_ept(base, ours, base), (ours.to_string(), 0));
+        assert_eq!(
+            merge_ept(base, ours, theirs),
+            (
+                format!("<<<<<<< ours\n{}=======\n{}>>>>>>> theirs\n", ours, theirs),
+                1
+            )
+        );
+    }
+}
@@ -23,9 +23,12 @@
 
 use phf::phf_map;
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
+use agent::Agent;
 use crypto;
 use crypto::CryptoPolicy;
+use error::{Error, Result};
 use etree;
 use utils;
 
@@ -37,6 +40,7 @@ pub static BOTAN_PBKDF_PARAM_MAP: phf::Map<&'static str, &[&[&str; 3]; 2]> = phf
     "pbkdf2-sha512" => &[&["i", "", ""],                   &["i", "", ""]],
 };
 
+#[derive(Clone)]
 pub struct PBKDFCacheEntry {
     pub password: String,
     pub alg: String,
@@ -45,13 +49,75 @@ pub struct PBKDFCacheEntry {
     pub key: Vec<u8>,
     pub params: BTreeMap<String, usize>,
 }
-pub type PBKDFCache = Vec<PBKDFCacheEntry>;
 
-fn pbkdf_legacy(
-    password: &str,
-    key_len: usize,
-    policy: &Box<dyn CryptoPolicy>,
-) -> Result<Vec<u8>, &'static str> {
+impl PBKDFCacheEntry {
+    // whether the key can be used for a derivation from password with opts:
+    // the same parameters and salt (if given), or the same msec
+    pub fn matches(&self, password: &str, key_len: usize, opts: &etree::PBKDFOptions) -> bool {
+        self.password == password
+            && self.alg == opts.alg
+            && self.key.len() == key_len
+            && match opts.params {
+                Some(ref params) => {
+                    self.msec == 0
+                        && self.params == *params
+                        && opts.salt.as_ref().map_or(true, |s| *s == self.salt)
+                }
+                None => Some(self.msec) == opts.msec,
+            }
+    }
+}
+
+// the keys derived so far, shared with other processes through the agent
+#[derive(Default)]
+pub struct PBKDFCache {
+    entries: Vec<PBKDFCacheEntry>,
+    pub shared: Option<Arc<Mutex<Vec<PBKDFCacheEntry>>>>, // of the workers of --jobs
+    pub agent: Option<Agent>,
+}
+
+impl PBKDFCache {
+    fn find(
+        &mut self,
+        password: &str,
+        key_len: usize,
+        opts: &etree::PBKDFOptions,
+    ) -> Option<PBKDFCacheEntry> {
+        if let Some(entry) = self
+            .entries
+            .iter()
+            .find(|e| e.matches(password, key_len, opts))
+        {
+            return Some(entry.clone());
+        }
+        let shared = self.shared.as_ref().and_then(|shared| {
+            let entries = shared.lock().unwrap_or_else(|e| e.into_inner());
+            entries
+                .iter()
+                .find(|e| e.matches(password, key_len, opts))
+                .cloned()
+        });
+        let entry = match shared {
+            Some(entry) => entry,
+            None => self.agent.as_ref()?.key(password, key_len, opts)?,
+        };
+        self.entries.push(entry.clone());
+        Some(entry)
+    }
+
+    fn insert(&mut self, entry: PBKDFCacheEntry) {
+        if let Some(agent) = self.agent.as_ref() {
+            let _ = agent.add_key(&entry);
+        }
+        if let Some(shared) = self.shared.as_ref() {
+            let mut entries = shared.lock().unwrap_or_else(|e| e.into_inner());
+            entries.push(entry.clone());
+        }
+        self.entries.push(entry);
+    }
+}
+
+fn pbkdf_legacy(password: &str, key_len: usize, policy: &Box<dyn CryptoPolicy>) -> Result<Vec<u8>> {
     policy.check_pbkdf("sha3-512", key_len, password, &[], &BTreeMap::new())?;
     let mut result = crypto::digest("sha3-512", password.as_bytes(), policy)?;
     result.truncate(key_len);
@@ -66,7 +132,7 @@ fn pbkdf_timed(
     msec: u32,
     key_len: usize,
     policy: &Box<dyn CryptoPolicy>,
-) -> Result<(Vec<u8>, BTreeMap<String, usize>), &'static str> {
+) -> Result<(Vec<u8>, BTreeMap<String, usize>)> {
     crypto::derive_key_from_password_timed(
         alg,
         botan_param_order,
@@ -86,7 +152,7 @@ fn pbkdf_manual(
     params_map: BTreeMap<String, usize>,
     key_len: usize,
     policy: &Box<dyn CryptoPolicy>,
-) -> Result<Vec<u8>, &'static str> {
+) -> Result<Vec<u8>> {
     crypto::derive_key_from_password(
         alg,
         botan_param_order,
@@ -118,88 +184,72 @@ pub fn derive_key(
     opts: &etree::PBKDFOptions,
     cache: &mut Option<PBKDFCache>,
     policy: &Box<dyn CryptoPolicy>,
-) -> Result<(Vec<u8>, Option<String>), &'static str> {
+) -> Result<(Vec<u8>, Option<String>)> {
     if opts.alg == "legacy" {
         return Ok((pbkdf_legacy(password, key_len, policy)?, None));
     }
-    let mut salt = opts.salt.clone().unwrap_or_else(|| {
+    let salt = opts.salt.clone().unwrap_or_else(|| {
         rng.as_ref()
             .unwrap()
             .read(opts.saltlen)
-            .map_err(|_| "Failed to read from RNG")
+            .map_err(|_| Error::Crypto("Failed to read from RNG"))
             .unwrap()
     });
     let botan_param_order = BOTAN_PBKDF_PARAM_MAP
         .get::<str>(&opts.alg)
-        .ok_or("Missing PBKDF param mapping")?;
-    if let Some(params) = opts.params.as_ref() {
-        let key;
-        if let Some(entry) = cache.as_ref().unwrap_or(&Vec::new()).iter().find(|e| {
-            e.password == password
-                && e.alg == opts.alg
-                && e.key.len() == key_len
-                && e.msec == 0
-                && e.params == *params
-        }) {
-            key = entry.key.clone();
-        } else {
-            key = pbkdf_manual(
-                &opts.alg,
-                &botan_param_order,
-                password,
-                &salt,
-                opts.params.clone().unwrap(),
-                key_len,
-                policy,
-            )?;
-            if cache.is_some() {
-                cache.as_mut().unwrap().push(PBKDFCacheEntry {
-                    password: password.to_string(),
-                    alg: opts.alg.clone(),
-                    msec: 0,
-                    salt: salt.clone(),
-                    key: key.clone(),
-                    params: params.clone(),
-                });
-            }
-        }
+        .ok_or(Error::Crypto("Missing PBKDF param mapping"))?;
+    let cached = cache
+        .as_mut()
+        .and_then(|cache| cache.find(password, key_len, opts));
+    if let Some(entry) = cached {
+        // possibly derived by another process, under another policy
+        policy.check_pbkdf(&opts.alg, key_len, password, &entry.salt, &entry.params)?;
         return Ok((
-            key,
-            Some(format_phc(&opts.alg, opts.params.as_ref().unwrap(), &salt)),
+            entry.key,
+            Some(format_phc(&opts.alg, &entry.params, &entry.salt)),
         ));
     }
-    let (key, params);
-    if let Some(entry) = cache.as_ref().unwrap_or(&Vec::new()).iter().find(|e| {
-        e.password == password
-            && e.alg == opts.alg
-            && e.key.len() == key_len
-            && e.msec == opts.msec.unwrap()
-    }) {
-        salt = entry.salt.clone();
-        key = entry.key.clone();
-        params = entry.params.clone();
-    } else {
-        let results = pbkdf_timed(
+    if let Some(params) = opts.params.as_ref() {
+        let key = pbkdf_manual(
             &opts.alg,
             &botan_param_order,
             password,
             &salt,
-            opts.msec.ok_or("Missing PBKDF msec")?,
+            params.clone(),
             key_len,
             policy,
         )?;
-        key = results.0;
-        params = results.1;
-        if cache.is_some() {
-            cache.as_mut().unwrap().push(PBKDFCacheEntry {
+        if let Some(cache) = cache.as_mut() {
+            cache.insert(PBKDFCacheEntry {
                 password: password.to_string(),
                 alg: opts.alg.clone(),
-                msec: opts.msec.unwrap(),
+                msec: 0,
                 salt: salt.clone(),
                 key: key.clone(),
                 params: params.clone(),
             });
         }
+        return Ok((key, Some(format_phc(&opts.alg, params, &salt))));
+    }
+    let msec = opts.msec.ok_or(Error::Crypto("Missing PBKDF msec"))?;
+    let (key, params) = pbkdf_timed(
+        &opts.alg,
+        &botan_param_order,
+        password,
+        &salt,
+        msec,
+        key_len,
+        policy,
+    )?;
+    if let Some(cache) = cache.as_mut() {
+        cache.insert(PBKDFCacheEntry {
+            password: password.to_string(),
+            alg: opts.alg.clone(),
+            msec,
+            salt: salt.clone(),
+            key: key.clone(),
+            params: params.clone(),
+        });
     }
     Ok((key, Some(format_phc(&opts.alg, &params, &salt))))
 }
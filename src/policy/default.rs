@@ -23,6 +23,7 @@
 
 use std::collections::BTreeMap;
 
+use error::Result;
 use policy::CryptoPolicy;
 
 pub struct CryptoPolicyDefault {}
@@ -32,11 +33,12 @@ impl CryptoPolicyDefault {
     const DEFAULT_PBKDF_SALT_LEN: usize = 16;
     pub const DEFAULT_PBKDF_MSEC: u32 = 100;
     const DEFAULT_CIPHER_ALG: &'static str = "aes-256-siv";
+    const DEFAULT_PUBKEY_ALG: &'static str = "x25519";
 }
 
 // allow everything
 impl CryptoPolicy for CryptoPolicyDefault {
-    fn check_hash(&self, _alg: &str) -> Result<(), &'static str> {
+    fn check_hash(&self, _alg: &str) -> Result<()> {
         Ok(())
     }
 
@@ -47,17 +49,15 @@ impl CryptoPolicy for CryptoPolicyDefault {
         _password: &str,
         _salt: &[u8],
         _params: &BTreeMap<String, usize>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<()> {
         Ok(())
     }
 
-    fn check_cipher(
-        &self,
-        _alg: &str,
-        _key: &[u8],
-        _iv: &[u8],
-        _ad: &[u8],
-    ) -> Result<(), &'static str> {
+    fn check_cipher(&self, _alg: &str, _key: &[u8], _iv: &[u8], _ad: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn check_pubkey(&self, _alg: &str) -> Result<()> {
         Ok(())
     }
 
@@ -76,4 +76,12 @@ impl CryptoPolicy for CryptoPolicyDefault {
     fn default_cipher_alg(&self) -> String {
         Self::DEFAULT_CIPHER_ALG.to_string()
     }
+
+    fn default_pubkey_alg(&self) -> String {
+        Self::DEFAULT_PUBKEY_ALG.to_string()
+    }
+
+    fn default_bind_ad(&self) -> bool {
+        false
+    }
 }
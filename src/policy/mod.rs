@@ -23,11 +23,14 @@
 
 use std::collections::BTreeMap;
 
+use error::Result;
+
 pub mod default;
 pub mod nist;
+pub mod strict;
 
 pub trait CryptoPolicy {
-    fn check_hash(&self, alg: &str) -> Result<(), &'static str>;
+    fn check_hash(&self, alg: &str) -> Result<()>;
 
     fn check_pbkdf(
         &self,
@@ -36,13 +39,26 @@ pub trait CryptoPolicy {
         password: &str,
         salt: &[u8],
         params: &BTreeMap<String, usize>,
-    ) -> Result<(), &'static str>;
+    ) -> Result<()>;
+
+    fn check_cipher(&self, alg: &str, key: &[u8], iv: &[u8], ad: &[u8]) -> Result<()>;
 
-    fn check_cipher(&self, alg: &str, key: &[u8], iv: &[u8], ad: &[u8])
-        -> Result<(), &'static str>;
+    fn check_pubkey(&self, alg: &str) -> Result<()>;
 
     fn default_pbkdf_alg(&self) -> String;
     fn default_pbkdf_salt_length(&self) -> usize;
     fn default_pbkdf_millis(&self) -> u32;
     fn default_cipher_alg(&self) -> String;
+    fn default_pubkey_alg(&self) -> String;
+    fn default_bind_ad(&self) -> bool;
+
+    // whether --migrate upgrades segments using these algorithms, besides
+    // those using legacy key derivation
+    fn is_weak_pbkdf(&self, _alg: &str) -> bool {
+        false
+    }
+
+    fn is_weak_cipher(&self, _alg: &str) -> bool {
+        false
+    }
 }
@@ -24,6 +24,7 @@
 use phf::phf_set;
 use std::collections::BTreeMap;
 
+use error::{Error, Result};
 use policy::default::CryptoPolicyDefault;
 use policy::CryptoPolicy;
 
@@ -35,6 +36,7 @@ impl CryptoPolicyNIST {
     // no policy per se, so copy the default policy setting
     const DEFAULT_PBKDF_MSEC: u32 = CryptoPolicyDefault::DEFAULT_PBKDF_MSEC;
     const DEFAULT_CIPHER_ALG: &'static str = "aes-256-gcm";
+    const DEFAULT_PUBKEY_ALG: &'static str = "p256";
     const NIST_APPROVED_PBKDFS: phf::Set<&'static str> = phf_set! {
         "pbkdf2-sha256",
         "pbkdf2-sha512",
@@ -46,26 +48,32 @@ impl CryptoPolicyNIST {
         "sha3-256",
         "sha3-512",
     };
+    const NIST_APPROVED_PUBKEYS: phf::Set<&'static str> = phf_set! {
+        "p256",
+    };
     const NIST_PBKDF_MIN_SALT_LEN: usize = 16;
 
-    fn check_alg(&self, kind: &str, alg: &str) -> Result<(), &'static str> {
+    fn check_alg(&self, kind: &str, alg: &str) -> Result<()> {
         let lst = match kind {
             "Cipher" => &Self::NIST_APPROVED_CIPHERS,
             "Hash" => &Self::NIST_APPROVED_HASHES,
             "PBKDF" => &Self::NIST_APPROVED_PBKDFS,
-            _ => return Err("Invalid algorithm kind"),
+            "Public key" => &Self::NIST_APPROVED_PUBKEYS,
+            _ => return Err(Error::Crypto("Invalid algorithm kind")),
         };
         if lst.contains(alg) {
             Ok(())
         } else {
-            eprintln!("{} algorithm is not permitted by policy: {}", kind, alg);
-            Err("Algorithm not permitted by policy")
+            Err(Error::Policy(format!(
+                "{} algorithm is not permitted by policy: {}",
+                kind, alg
+            )))
         }
     }
 }
 
 impl CryptoPolicy for CryptoPolicyNIST {
-    fn check_hash(&self, alg: &str) -> Result<(), &'static str> {
+    fn check_hash(&self, alg: &str) -> Result<()> {
         self.check_alg("Hash", alg)
     }
 
@@ -76,36 +84,36 @@ impl CryptoPolicy for CryptoPolicyNIST {
         _password: &str,
         salt: &[u8],
         params: &BTreeMap<String, usize>,
-    ) -> Result<(), &'static str> {
+    ) -> Result<()> {
         self.check_alg("PBKDF", alg)?;
         if salt.len() < Self::NIST_PBKDF_MIN_SALT_LEN {
-            return Err("Salt length violates policy");
+            return Err(Error::Policy("Salt length violates policy".to_string()));
         }
         if key_len < 14 {
-            return Err("Key length violates policy");
+            return Err(Error::Policy("Key length violates policy".to_string()));
         }
         if let Some(iters) = params.get("i") {
             if *iters < 1000 {
-                return Err("Iteration count violates policy");
+                return Err(Error::Policy("Iteration count violates policy".to_string()));
             }
         }
         Ok(())
     }
 
-    fn check_cipher(
-        &self,
-        alg: &str,
-        _key: &[u8],
-        iv: &[u8],
-        _ad: &[u8],
-    ) -> Result<(), &'static str> {
+    fn check_cipher(&self, alg: &str, _key: &[u8], iv: &[u8], _ad: &[u8]) -> Result<()> {
         self.check_alg("Cipher", alg)?;
         if alg == "aes-256-gcm" && iv.len() != 96 / 8 {
-            return Err("IV length does not match NIST recommendations for this cipher.");
+            return Err(Error::Policy(
+                "IV length does not match NIST recommendations for this cipher.".to_string(),
+            ));
         }
         Ok(())
     }
 
+    fn check_pubkey(&self, alg: &str) -> Result<()> {
+        self.check_alg("Public key", alg)
+    }
+
     fn default_pbkdf_alg(&self) -> String {
         Self::DEFAULT_PBKDF_ALG.to_string()
     }
@@ -121,4 +129,20 @@ impl CryptoPolicy for CryptoPolicyNIST {
     fn default_cipher_alg(&self) -> String {
         Self::DEFAULT_CIPHER_ALG.to_string()
     }
+
+    fn default_pubkey_alg(&self) -> String {
+        Self::DEFAULT_PUBKEY_ALG.to_string()
+    }
+
+    fn default_bind_ad(&self) -> bool {
+        false
+    }
+
+    fn is_weak_pbkdf(&self, alg: &str) -> bool {
+        !Self::NIST_APPROVED_PBKDFS.contains(alg)
+    }
+
+    fn is_weak_cipher(&self, alg: &str) -> bool {
+        !Self::NIST_APPROVED_CIPHERS.contains(alg)
+    }
 }
@@ -0,0 +1,96 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+use std::collections::BTreeMap;
+
+use error::{Error, Result};
+use policy::default::CryptoPolicyDefault;
+use policy::CryptoPolicy;
+
+// the default policy, but ciphertext must be bound to its context and the
+// unsalted legacy key derivation is refused
+pub struct CryptoPolicyStrict {}
+
+impl CryptoPolicyStrict {
+    // what derive_key() checks for the legacy derivation
+    const LEGACY_PBKDF_ALG: &'static str = "sha3-512";
+}
+
+impl CryptoPolicy for CryptoPolicyStrict {
+    fn check_hash(&self, alg: &str) -> Result<()> {
+        CryptoPolicyDefault {}.check_hash(alg)
+    }
+
+    fn check_pbkdf(
+        &self,
+        alg: &str,
+        key_len: usize,
+        password: &str,
+        salt: &[u8],
+        params: &BTreeMap<String, usize>,
+    ) -> Result<()> {
+        if alg == Self::LEGACY_PBKDF_ALG {
+            return Err(Error::Policy(
+                "Legacy key derivation is not permitted by policy".to_string(),
+            ));
+        }
+        CryptoPolicyDefault {}.check_pbkdf(alg, key_len, password, salt, params)
+    }
+
+    fn check_cipher(&self, alg: &str, key: &[u8], iv: &[u8], ad: &[u8]) -> Result<()> {
+        if ad.is_empty() {
+            return Err(Error::Policy(
+                "Associated data is required by policy".to_string(),
+            ));
+        }
+        CryptoPolicyDefault {}.check_cipher(alg, key, iv, ad)
+    }
+
+    fn check_pubkey(&self, alg: &str) -> Result<()> {
+        CryptoPolicyDefault {}.check_pubkey(alg)
+    }
+
+    fn default_pbkdf_alg(&self) -> String {
+        CryptoPolicyDefault {}.default_pbkdf_alg()
+    }
+
+    fn default_pbkdf_salt_length(&self) -> usize {
+        CryptoPolicyDefault {}.default_pbkdf_salt_length()
+    }
+
+    fn default_pbkdf_millis(&self) -> u32 {
+        CryptoPolicyDefault {}.default_pbkdf_millis()
+    }
+
+    fn default_cipher_alg(&self) -> String {
+        CryptoPolicyDefault {}.default_cipher_alg()
+    }
+
+    fn default_pubkey_alg(&self) -> String {
+        CryptoPolicyDefault {}.default_pubkey_alg()
+    }
+
+    fn default_bind_ad(&self) -> bool {
+        true
+    }
+}
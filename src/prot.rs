@@ -24,46 +24,155 @@
 use std::collections::BTreeMap;
 
 use cipher;
+use cipher::SymmetricCipher;
+use crypto;
 use crypto::CryptoPolicy;
+use error::{Error, Result};
 use etree;
 use pbkdf::derive_key;
 use pbkdf::PBKDFCache;
+use pubkey;
+use pubkey::{PrivateKey, PublicKey};
 use utils;
 
+// extended field name prefix for public key recipients, followed by the
+// key fingerprint
+pub const PUBKEY_EXTFIELD_PREFIX: &str = "pk.";
+// extended field name prefix for password recipients, followed by the label
+pub const PASSWORD_EXTFIELD_PREFIX: &str = "pw.";
+// extended field recording the associated data version, and document id
+pub const AD_EXTFIELD: &str = "ad";
+const AD_VERSION: &str = "v1";
+
+// A recipient of an envelope encrypted segment
+
+#[derive(Clone)]
+pub enum Recipient {
+    PublicKey(PublicKey),
+    Password(String), // label
+}
+
+impl Recipient {
+    // extended field name holding the wrapped key for this recipient
+    pub fn name(&self, policy: &Box<dyn CryptoPolicy>) -> Result<String> {
+        Ok(match self {
+            Recipient::PublicKey(pk) => {
+                PUBKEY_EXTFIELD_PREFIX.to_string() + &pk.fingerprint(policy)?
+            }
+            Recipient::Password(label) => PASSWORD_EXTFIELD_PREFIX.to_string() + label,
+        })
+    }
+}
+
 // Get a password
 
-pub fn get_password(name: &str, rep: bool) -> String {
-    let prompt = "Password for ".to_string() + name + ": ";
+fn prompt_password(what: &str, name: &str, rep: bool) -> String {
+    let prompt = what.to_string() + " for " + name + ": ";
     let mut pass = rpassword::prompt_password_stdout(&prompt).unwrap();
     if rep {
-        let prompt = "Repeat password for ".to_string() + name + ": ";
+        let prompt = "Repeat ".to_string() + &what.to_lowercase() + " for " + name + ": ";
         let pass2 = rpassword::prompt_password_stdout(&prompt).unwrap();
         if pass != pass2 {
             eprintln!("Password mismatch. Try again.");
-            pass = get_password(name, rep);
+            pass = prompt_password(what, name, rep);
         }
     }
     pass
 }
 
-// Encrypt
+pub fn get_password(name: &str, rep: bool) -> String {
+    prompt_password("Password", name, rep)
+}
 
-pub fn encrypt(
-    pt: Vec<u8>,
-    password: &str,
+pub fn get_new_password(name: &str) -> String {
+    prompt_password("New password", name, true)
+}
+
+// Split an extended field value of the form ALG$key=value$key=value
+
+fn parse_extfield(value: &str) -> Result<(&str, BTreeMap<&str, String>)> {
+    let mut it = value.split("$");
+    let alg = it.next().ok_or(Error::Format("Invalid extended field"))?;
+    let mut fields = BTreeMap::new();
+    for val in it {
+        let mut it = val.splitn(2, '=');
+        let key = it.next().ok_or(Error::Format("Missing field key"))?;
+        let value = it.collect::<String>();
+        fields.insert(key, value);
+    }
+    Ok((alg, fields))
+}
+
+// Associated data binding the ciphertext to its keyword and extended fields.
+// Recipient entries are left out so they can be added and removed later.
+// Segments without the ad extended field use none, as they always have.
+
+fn associated_data(keyw: &str, extfields: &BTreeMap<String, String>) -> Result<Vec<u8>> {
+    let version = match extfields.get(AD_EXTFIELD) {
+        Some(ad) => parse_extfield(ad)?.0,
+        None => return Ok(Vec::new()),
+    };
+    if version != AD_VERSION {
+        return Err(Error::Format("Unsupported associated data version"));
+    }
+    // keywords and extended fields never contain whitespace
+    let mut ad = format!("enprot-ad-{}\nkeyword:{}\n", version, keyw);
+    for (key, value) in extfields {
+        if key.starts_with(PUBKEY_EXTFIELD_PREFIX) || key.starts_with(PASSWORD_EXTFIELD_PREFIX) {
+            continue;
+        }
+        ad += &format!("{}:{}\n", key, value);
+    }
+    Ok(ad.into_bytes())
+}
+
+// the document id recorded in the associated data must be the expected one
+fn check_doc_id(extfields: &BTreeMap<String, String>, doc_id: &Option<String>) -> Result<()> {
+    if let Some(doc_id) = doc_id {
+        let ad = extfields
+            .get(AD_EXTFIELD)
+            .ok_or(Error::Format("Missing associated data"))?;
+        if parse_extfield(ad)?.1.get("doc") != Some(doc_id) {
+            return Err(Error::Operation("Document identifier mismatch"));
+        }
+    }
+    Ok(())
+}
+
+// the key derivation algorithm of a password encrypted segment
+pub fn pbkdf_alg(extfields: &BTreeMap<String, String>) -> Result<String> {
+    match extfields.get("pbkdf") {
+        Some(pbkdf) => Ok(pbkdf_options_from_phc(pbkdf)?.alg),
+        None => Ok("legacy".to_string()),
+    }
+}
+
+// the cipher algorithm of a segment
+pub fn cipher_alg(extfields: &BTreeMap<String, String>) -> Result<String> {
+    match extfields.get("cipher") {
+        Some(cipher) => Ok(parse_extfield(cipher)?.0.to_string()),
+        None => Ok("aes-256-siv".to_string()),
+    }
+}
+
+// the document id bound to a segment, if any
+pub fn doc_id(extfields: &BTreeMap<String, String>) -> Result<Option<String>> {
+    match extfields.get(AD_EXTFIELD) {
+        Some(ad) => Ok(parse_extfield(ad)?.1.get("doc").cloned()),
+        None => Ok(None),
+    }
+}
+
+fn encrypt_with_key(
+    pt: &[u8],
+    keyw: &str,
+    key: &[u8],
+    enc: &Box<dyn SymmetricCipher>,
     rng: &Option<botan::RandomNumberGenerator>,
-    pbkdfopts: &etree::PBKDFOptions,
     cipheropts: &etree::CipherOptions,
-    cache: &mut Option<PBKDFCache>,
     policy: &Box<dyn CryptoPolicy>,
-) -> Result<(Vec<u8>, BTreeMap<String, String>), &'static str> {
-    let enc = cipher::encryption(&cipheropts.alg)?;
-    let key_len = enc.key_len_max();
-    let (key, pbkdf) = derive_key(password, key_len, rng, pbkdfopts, cache, policy)?;
-    let mut extfields: BTreeMap<String, String> = BTreeMap::new();
-    if pbkdf != None {
-        extfields.insert("pbkdf".to_string(), pbkdf.unwrap());
-    }
+    extfields: &mut BTreeMap<String, String>,
+) -> Result<Vec<u8>> {
     let mut iv: Vec<u8> = Vec::new();
     if cipheropts.alg != "aes-256-siv" {
         // IV required
@@ -72,9 +181,9 @@ pub fn encrypt(
         } else {
             let ivlen = enc.nonce_len();
             rng.as_ref()
-                .ok_or("Missing RNG")?
+                .ok_or(Error::Crypto("Missing RNG"))?
                 .read(ivlen)
-                .map_err(|_| "RNG error")?
+                .map_err(|_| Error::Crypto("RNG error"))?
         };
         extfields.insert(
             "cipher".to_string(),
@@ -82,62 +191,181 @@ pub fn encrypt(
         );
     } else if cipheropts.iv != None {
         // IV not required
-        return Err("IV was supplied but not expected");
+        return Err(Error::Operation("IV was supplied but not expected"));
     }
-    Ok((enc.process(&key, &iv, &[], &pt, policy)?, extfields))
+    if cipheropts.ad {
+        let mut ad = AD_VERSION.to_string();
+        if let Some(doc_id) = &cipheropts.doc_id {
+            ad += &format!("$doc={}", doc_id);
+        }
+        extfields.insert(AD_EXTFIELD.to_string(), ad);
+    }
+    let ad = associated_data(keyw, extfields)?;
+    enc.process(key, &iv, &ad, pt, policy)
 }
 
-// Decrypt
-
-pub fn decrypt(
-    ct: Vec<u8>,
-    password: &str,
-    pbkdf: &Option<&String>,
+// the cipher extfield, or the original default when absent
+fn decryption_from_extfield(
     cipher: &Option<&String>,
-    cache: &mut Option<PBKDFCache>,
-    policy: &Box<dyn CryptoPolicy>,
-) -> Result<Vec<u8>, &'static str> {
+) -> Result<(Box<dyn SymmetricCipher>, Vec<u8>)> {
     let cipher_alg;
     let mut iv = Vec::new();
     if let Some(cipher) = cipher {
-        let mut it = cipher.split("$");
-        cipher_alg = it.next().ok_or("Invalid cipher extfield")?;
-        let mut fields = BTreeMap::new();
-        for val in it {
-            let mut it = val.splitn(2, '=');
-            let key = it.next().ok_or("Missing field key")?;
-            let value = it.collect::<String>();
-            fields.insert(key, value);
-        }
+        let (alg, fields) = parse_extfield(cipher)?;
+        cipher_alg = alg;
         if let Some(myiv) = fields.get("iv") {
             iv = utils::base64_decode(myiv)?;
         }
     } else {
         cipher_alg = "aes-256-siv";
     }
-    let dec = cipher::decryption(&cipher_alg)?;
+    Ok((cipher::decryption(&cipher_alg)?, iv))
+}
+
+// PBKDF options to re-derive a key from its PHC string
+
+pub fn pbkdf_options_from_phc(pbkdf: &str) -> Result<etree::PBKDFOptions> {
+    let phc: phc::raw::RawPHC = pbkdf
+        .parse()
+        .map_err(|_| Error::Format("Failed to parse PHC"))?;
+    let alg = phc.id();
+    let mut params_map: BTreeMap<String, usize> = BTreeMap::new();
+    params_map.extend(
+        phc.params()
+            .iter()
+            .map(|v| (v.0.to_string(), v.1.parse::<usize>().unwrap())),
+    );
+    let salt = match phc.salt().ok_or(Error::Format("Missing salt"))? {
+        phc::Salt::Ascii(s) => utils::base64_decode(s)?,
+        phc::Salt::Binary(b) => utils::base64_decode(std::str::from_utf8(b).unwrap())?,
+    };
+    Ok(etree::PBKDFOptions {
+        alg: alg.to_string(),
+        saltlen: 0,
+        salt: Some(salt),
+        msec: None,
+        params: Some(params_map),
+    })
+}
+
+// Deterministic encryption
+//
+// The salt and IV are derived from the keyword and plaintext under a
+// document key, which is derived from the password with a salt fixed by the
+// document id. Encrypting the same plaintext again then gives the same
+// ciphertext, at the cost of revealing that it is the same.
+
+fn deterministic_options(
+    pt: &[u8],
+    keyw: &str,
+    password: &str,
+    enc: &Box<dyn SymmetricCipher>,
+    pbkdfopts: &etree::PBKDFOptions,
+    cipheropts: &etree::CipherOptions,
+    cache: &mut Option<PBKDFCache>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<(etree::PBKDFOptions, etree::CipherOptions)> {
+    if pbkdfopts.alg != "legacy" && pbkdfopts.params.is_none() {
+        return Err(Error::Operation(
+            "Deterministic encryption needs fixed PBKDF parameters",
+        ));
+    }
+    if cipheropts.iv.is_some() {
+        return Err(Error::Operation("IV was supplied but not expected"));
+    }
+    let doc_id = cipheropts.doc_id.as_ref().map_or("", String::as_str);
+    let doc_salt = crypto::digest(
+        "sha3-512",
+        format!("enprot-doc-key\n{}\n", doc_id).as_bytes(),
+        policy,
+    )?;
+    let derive = |label: &str, len: usize, key: &[u8]| -> Result<Vec<u8>> {
+        let mut data = format!("enprot-{}\n{}\n", label, keyw).into_bytes();
+        data.extend_from_slice(pt);
+        let mut derived = crypto::mac("sha3-512", key, &data, policy)?;
+        if derived.len() < len {
+            return Err(Error::Operation(
+                "Salt too long for deterministic encryption",
+            ));
+        }
+        derived.truncate(len);
+        Ok(derived)
+    };
+
+    let mut pbkdfopts = pbkdfopts.clone();
+    pbkdfopts.salt = Some(doc_salt[..pbkdfopts.saltlen.min(doc_salt.len())].to_vec());
+    let (doc_key, _) = derive_key(
+        password,
+        enc.key_len_max(),
+        &None,
+        &pbkdfopts,
+        cache,
+        policy,
+    )?;
+    pbkdfopts.salt = Some(derive("salt", pbkdfopts.saltlen, &doc_key)?);
+    let mut cipheropts = cipheropts.clone();
+    if cipheropts.alg != "aes-256-siv" {
+        cipheropts.iv = Some(derive("iv", enc.nonce_len(), &doc_key)?);
+    }
+    Ok((pbkdfopts, cipheropts))
+}
+
+// Encrypt
+
+pub fn encrypt(
+    pt: Vec<u8>,
+    keyw: &str,
+    password: &str,
+    rng: &Option<botan::RandomNumberGenerator>,
+    pbkdfopts: &etree::PBKDFOptions,
+    cipheropts: &etree::CipherOptions,
+    cache: &mut Option<PBKDFCache>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<(Vec<u8>, BTreeMap<String, String>)> {
+    let enc = cipher::encryption(&cipheropts.alg)?;
+    let key_len = enc.key_len_max();
+    let (pbkdfopts, cipheropts) = if cipheropts.deterministic {
+        deterministic_options(
+            &pt, keyw, password, &enc, pbkdfopts, cipheropts, cache, policy,
+        )?
+    } else {
+        (pbkdfopts.clone(), cipheropts.clone())
+    };
+    let (key, pbkdf) = derive_key(password, key_len, rng, &pbkdfopts, cache, policy)?;
+    let mut extfields: BTreeMap<String, String> = BTreeMap::new();
+    if pbkdf != None {
+        extfields.insert("pbkdf".to_string(), pbkdf.unwrap());
+    }
+    let ct = encrypt_with_key(
+        &pt,
+        keyw,
+        &key,
+        &enc,
+        rng,
+        &cipheropts,
+        policy,
+        &mut extfields,
+    )?;
+    Ok((ct, extfields))
+}
+
+// Decrypt
+
+pub fn decrypt(
+    ct: Vec<u8>,
+    keyw: &str,
+    password: &str,
+    extfields: &BTreeMap<String, String>,
+    doc_id: &Option<String>,
+    cache: &mut Option<PBKDFCache>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<Vec<u8>> {
+    check_doc_id(extfields, doc_id)?;
+    let (dec, iv) = decryption_from_extfield(&extfields.get("cipher"))?;
     let key_len = dec.key_len_max();
     let key: Vec<u8>;
-    if let Some(pbkdf) = pbkdf {
-        let phc: phc::raw::RawPHC = pbkdf.parse().map_err(|_| "Failed to parse PHC")?;
-        let alg = phc.id();
-        let mut params_map: BTreeMap<String, usize> = BTreeMap::new();
-        params_map.extend(
-            phc.params()
-                .iter()
-                .map(|v| (v.0.to_string(), v.1.parse::<usize>().unwrap())),
-        );
-        let salt = match phc.salt().ok_or("Missing salt")? {
-            phc::Salt::Ascii(s) => utils::base64_decode(s)?,
-            phc::Salt::Binary(b) => utils::base64_decode(std::str::from_utf8(b).unwrap())?,
-        };
-        let pbkdfopts = etree::PBKDFOptions {
-            alg: alg.to_string(),
-            saltlen: 0,
-            salt: Some(salt),
-            msec: None,
-            params: Some(params_map),
-        };
+    if let Some(pbkdf) = extfields.get("pbkdf") {
+        let pbkdfopts = pbkdf_options_from_phc(pbkdf)?;
         let (thekey, _) = derive_key(password, key_len, &None, &pbkdfopts, cache, policy)?;
         key = thekey;
     } else {
@@ -157,5 +385,149 @@ pub fn decrypt(
         )?;
         key = thekey;
     }
-    Ok(dec.process(&key, &iv, &[], &ct, policy)?)
+    let ad = associated_data(keyw, extfields)?;
+    dec.process(&key, &iv, &ad, &ct, policy)
+}
+
+// Envelope encryption
+//
+// The segment is encrypted under a random data key, which is wrapped
+// separately for each recipient. Recipients can then be added or removed
+// without touching the ciphertext.
+
+// true if the segment was encrypted to recipients
+pub fn has_recipients(extfields: &BTreeMap<String, String>) -> bool {
+    extfields.keys().any(|key| {
+        key.starts_with(PUBKEY_EXTFIELD_PREFIX) || key.starts_with(PASSWORD_EXTFIELD_PREFIX)
+    })
+}
+
+pub fn new_data_key(
+    rng: &Option<botan::RandomNumberGenerator>,
+    cipheropts: &etree::CipherOptions,
+) -> Result<Vec<u8>> {
+    let enc = cipher::encryption(&cipheropts.alg)?;
+    rng.as_ref()
+        .ok_or(Error::Crypto("Missing RNG"))?
+        .read(enc.key_len_max())
+        .map_err(|_| Error::Crypto("RNG error"))
+}
+
+pub fn encrypt_with_data_key(
+    pt: Vec<u8>,
+    keyw: &str,
+    dek: &[u8],
+    rng: &Option<botan::RandomNumberGenerator>,
+    cipheropts: &etree::CipherOptions,
+    policy: &Box<dyn CryptoPolicy>,
+    extfields: &mut BTreeMap<String, String>,
+) -> Result<Vec<u8>> {
+    let enc = cipher::encryption(&cipheropts.alg)?;
+    encrypt_with_key(&pt, keyw, dek, &enc, rng, cipheropts, policy, extfields)
+}
+
+pub fn decrypt_with_data_key(
+    ct: Vec<u8>,
+    keyw: &str,
+    dek: &[u8],
+    extfields: &BTreeMap<String, String>,
+    doc_id: &Option<String>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<Vec<u8>> {
+    check_doc_id(extfields, doc_id)?;
+    let (dec, iv) = decryption_from_extfield(&extfields.get("cipher"))?;
+    let ad = associated_data(keyw, extfields)?;
+    dec.process(dek, &iv, &ad, &ct, policy)
+}
+
+// Wrap the data key for a public key, as ALG$epk=..$wk=..
+
+pub fn wrap_for_pubkey(
+    dek: &[u8],
+    recipient: &PublicKey,
+    rng: &Option<botan::RandomNumberGenerator>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<String> {
+    let (epk, wk) = pubkey::wrap_key(dek, recipient, rng, policy)?;
+    Ok(format!(
+        "{}$epk={}$wk={}",
+        recipient.alg,
+        utils::base64_encode(&epk)?,
+        utils::base64_encode(&wk)?
+    ))
+}
+
+// Unwrap the data key with a private key, None if it is not a recipient
+
+pub fn unwrap_with_identity(
+    extfields: &BTreeMap<String, String>,
+    identity: &PrivateKey,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<Option<Vec<u8>>> {
+    let field = PUBKEY_EXTFIELD_PREFIX.to_string() + &identity.public.fingerprint(policy)?;
+    let value = match extfields.get(&field) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    let (alg, fields) = parse_extfield(value)?;
+    if alg != identity.public.alg {
+        return Err(Error::Format("Recipient key algorithm mismatch"));
+    }
+    let epk = utils::base64_decode(
+        fields
+            .get("epk")
+            .ok_or(Error::Format("Missing ephemeral key"))?,
+    )?;
+    let wk = utils::base64_decode(
+        fields
+            .get("wk")
+            .ok_or(Error::Format("Missing wrapped key"))?,
+    )?;
+    Ok(Some(pubkey::unwrap_key(&epk, &wk, identity, policy)?))
+}
+
+// Wrap the data key with a password, as the PHC string of the key
+// derivation followed by $WRAPPEDKEY
+
+pub fn wrap_for_password(
+    dek: &[u8],
+    password: &str,
+    rng: &Option<botan::RandomNumberGenerator>,
+    pbkdfopts: &etree::PBKDFOptions,
+    cache: &mut Option<PBKDFCache>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<String> {
+    let (kek, pbkdf) = derive_key(
+        password,
+        cipher::wrap_key_len()?,
+        rng,
+        pbkdfopts,
+        cache,
+        policy,
+    )?;
+    let pbkdf = pbkdf.ok_or(Error::Operation(
+        "Legacy PBKDF cannot be used for password recipients",
+    ))?;
+    let wk = cipher::wrap_key(&kek, dek, rng, policy)?;
+    Ok(format!("{}${}", pbkdf, utils::base64_encode(&wk)?))
+}
+
+pub fn unwrap_with_password(
+    value: &str,
+    password: &str,
+    cache: &mut Option<PBKDFCache>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<Vec<u8>> {
+    let mut it = value.rsplitn(2, '$');
+    let wk = utils::base64_decode(it.next().ok_or(Error::Format("Missing wrapped key"))?)?;
+    let pbkdfopts = pbkdf_options_from_phc(it.next().ok_or(Error::Format("Missing PBKDF"))?)?;
+    let (kek, _) = derive_key(
+        password,
+        cipher::wrap_key_len()?,
+        &None,
+        &pbkdfopts,
+        cache,
+        policy,
+    )?;
+    cipher::unwrap_key(&kek, &wk, policy)
 }
@@ -0,0 +1,230 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	public key recipients
+
+use phf::phf_map;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use cipher;
+use crypto;
+use crypto::CryptoPolicy;
+use error::{Error, Result};
+use utils;
+
+pub static BOTAN_PUBKEY_ALG_MAP: phf::Map<&'static str, (&'static str, &'static str)> = phf_map! {
+    // alg     botan algorithm, botan params
+    "x25519" => ("Curve25519", ""),
+    "p256" => ("ECDH", "secp256r1"),
+};
+
+// KDF applied to the shared secret
+const AGREEMENT_KDF: &str = "KDF2(SHA-256)";
+// digest bytes making up a key fingerprint
+const FINGERPRINT_LEN: usize = 8;
+
+fn to_botan_pubkey(alg: &str) -> Result<&'static (&'static str, &'static str)> {
+    BOTAN_PUBKEY_ALG_MAP
+        .get::<str>(alg)
+        .ok_or(Error::Crypto("Unrecognized public key algorithm"))
+}
+
+#[derive(Clone)]
+pub struct PublicKey {
+    pub alg: String,  // algorithm name
+    pub key: Vec<u8>, // raw public value used in key agreement
+}
+
+impl PublicKey {
+    pub fn decode(encoded: &str) -> Result<PublicKey> {
+        let mut it = encoded.trim().splitn(2, ':');
+        let alg = it.next().ok_or(Error::Format("Invalid public key"))?;
+        let key = utils::base64_decode(it.next().ok_or(Error::Format("Invalid public key"))?)?;
+        to_botan_pubkey(alg)?;
+        Ok(PublicKey {
+            alg: alg.to_string(),
+            key,
+        })
+    }
+
+    pub fn encode(&self) -> Result<String> {
+        Ok(format!("{}:{}", self.alg, utils::base64_encode(&self.key)?))
+    }
+
+    pub fn fingerprint(&self, policy: &Box<dyn CryptoPolicy>) -> Result<String> {
+        let mut digest = crypto::digest("sha3-256", &self.key, policy)?;
+        digest.truncate(FINGERPRINT_LEN);
+        Ok(hex::encode(digest))
+    }
+}
+
+pub struct PrivateKey {
+    pub public: PublicKey,
+    obj: botan::Privkey,
+}
+
+impl PrivateKey {
+    fn from_botan(obj: botan::Privkey) -> Result<PrivateKey> {
+        let key = obj
+            .key_agreement_key()
+            .map_err(|_| Error::Crypto("Botan error retrieving public key"))?;
+        let name = obj
+            .algo_name()
+            .map_err(|_| Error::Crypto("Botan error retrieving key algorithm"))?;
+        let alg = match name.as_str() {
+            "Curve25519" => "x25519",
+            // uncompressed point on a 256-bit curve
+            "ECDH" if key.len() == 65 => "p256",
+            _ => return Err(Error::Crypto("Unsupported private key algorithm")),
+        };
+        Ok(PrivateKey {
+            public: PublicKey {
+                alg: alg.to_string(),
+                key,
+            },
+            obj,
+        })
+    }
+
+    pub fn generate(
+        alg: &str,
+        rng: &Option<botan::RandomNumberGenerator>,
+        policy: &Box<dyn CryptoPolicy>,
+    ) -> Result<PrivateKey> {
+        policy.check_pubkey(alg)?;
+        let (name, params) = to_botan_pubkey(alg)?;
+        let obj = botan::Privkey::create(
+            name,
+            params,
+            rng.as_ref().ok_or(Error::Crypto("Missing RNG"))?,
+        )
+        .map_err(|_| Error::Crypto("Botan error generating key"))?;
+        PrivateKey::from_botan(obj)
+    }
+
+    pub fn decode(pem: &str) -> Result<PrivateKey> {
+        let obj = botan::Privkey::load_pem(pem)
+            .map_err(|_| Error::Format("Failed to load private key"))?;
+        PrivateKey::from_botan(obj)
+    }
+
+    pub fn encode(&self) -> Result<String> {
+        self.obj
+            .pem_encode()
+            .map_err(|_| Error::Crypto("Botan error encoding private key"))
+    }
+}
+
+fn agree(privkey: &botan::Privkey, peer: &[u8], salt: &[u8], key_len: usize) -> Result<Vec<u8>> {
+    let op = botan::KeyAgreement::new(privkey, AGREEMENT_KDF)
+        .map_err(|_| Error::Crypto("Botan error creating key agreement"))?;
+    op.agree(key_len, peer, salt)
+        .map_err(|_| Error::Crypto("Botan error in key agreement"))
+}
+
+// Wrap a data key for a recipient, returns the ephemeral public key and
+// the wrapped key
+
+pub fn wrap_key(
+    dek: &[u8],
+    recipient: &PublicKey,
+    rng: &Option<botan::RandomNumberGenerator>,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<(Vec<u8>, Vec<u8>)> {
+    let ephemeral = PrivateKey::generate(&recipient.alg, rng, policy)?;
+    let salt = [&ephemeral.public.key[..], &recipient.key[..]].concat();
+    let kek = agree(
+        &ephemeral.obj,
+        &recipient.key,
+        &salt,
+        cipher::wrap_key_len()?,
+    )?;
+    let wk = cipher::wrap_key(&kek, dek, rng, policy)?;
+    Ok((ephemeral.public.key.clone(), wk))
+}
+
+// Unwrap a data key with our private key
+
+pub fn unwrap_key(
+    epk: &[u8],
+    wk: &[u8],
+    identity: &PrivateKey,
+    policy: &Box<dyn CryptoPolicy>,
+) -> Result<Vec<u8>> {
+    policy.check_pubkey(&identity.public.alg)?;
+    let salt = [epk, &identity.public.key[..]].concat();
+    let kek = agree(&identity.obj, epk, &salt, cipher::wrap_key_len()?)?;
+    cipher::unwrap_key(&kek, wk, policy)
+}
+
+// Key files
+
+fn read_key_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| Error::Io {
+        path: path.to_path_buf(),
+        source: e,
+    })
+}
+
+fn write_key_file(path: &Path, contents: &str, private: bool) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    if private {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+    }
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })
+}
+
+// a private key file is also accepted in place of a public key
+pub fn load_public_key(path: &Path) -> Result<PublicKey> {
+    let contents = read_key_file(path)?;
+    if contents.starts_with("-----BEGIN") {
+        return Ok(PrivateKey::decode(&contents)?.public);
+    }
+    PublicKey::decode(&contents)
+}
+
+pub fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    PrivateKey::decode(&read_key_file(path)?)
+}
+
+// writes the private key to path and the public key to path.pub
+pub fn save_keypair(path: &Path, key: &PrivateKey) -> Result<()> {
+    let mut pubpath = path.as_os_str().to_owned();
+    pubpath.push(".pub");
+    write_key_file(path, &key.encode()?, true)?;
+    write_key_file(Path::new(&pubpath), &(key.public.encode()? + "\n"), false)
+}
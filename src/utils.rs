@@ -21,10 +21,12 @@
 // (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
 // OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
-pub fn base64_encode(data: &[u8]) -> Result<String, &'static str> {
-    botan::base64_encode(data).map_err(|_| "Botan error")
+use error::{Error, Result};
+
+pub fn base64_encode(data: &[u8]) -> Result<String> {
+    botan::base64_encode(data).map_err(|_| Error::Crypto("Botan error encoding base64"))
 }
 
-pub fn base64_decode(data: &str) -> Result<Vec<u8>, &'static str> {
-    botan::base64_decode(data).map_err(|_| "Botan error")
+pub fn base64_decode(data: &str) -> Result<Vec<u8>> {
+    botan::base64_decode(data).map_err(|_| Error::Format("Invalid base64"))
 }
@@ -0,0 +1,289 @@
+// Copyright (c) 2020 [Ribose Inc](https://www.ribose.com).
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions
+// are met:
+// 1. Redistributions of source code must retain the above copyright
+//    notice, this list of conditions and the following disclaimer.
+// 2. Redistributions in binary form must reproduce the above copyright
+//    notice, this list of conditions and the following disclaimer in the
+//    documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS
+// ``AS IS'' AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT
+// LIMITED TO, THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR
+// A PARTICULAR PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT
+// OWNER OR CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL,
+// SPECIAL, EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT
+// LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE,
+// DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY
+// THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT
+// (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+// OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//	the files of a directory tree, for -R
+
+use glob::{MatchOptions, Pattern, PatternError};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use error::{Error, Result};
+
+// files listing patterns of paths to leave alone, in each directory
+pub const IGNORE_FILES: [&str; 2] = [".gitignore", ".enprotignore"];
+
+// how much of a file is looked at to tell whether it is binary
+const BINARY_CHECK_LEN: u64 = 8000;
+
+// a glob relative to base; globs without a '/' match the file name only
+pub struct Glob {
+    base: PathBuf,
+    pattern: Pattern,
+    anchored: bool,
+}
+
+impl Glob {
+    pub fn new(base: &Path, glob: &str) -> std::result::Result<Glob, PatternError> {
+        let pattern = Pattern::new(glob.trim_start_matches('/'))?;
+        Ok(Glob {
+            base: base.to_path_buf(),
+            pattern,
+            anchored: glob.contains('/'),
+        })
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        let opts = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        if self.anchored {
+            path.strip_prefix(&self.base)
+                .map_or(false, |rel| self.pattern.matches_path_with(rel, opts))
+        } else {
+            path.file_name().map_or(false, |name| {
+                self.pattern.matches_with(&name.to_string_lossy(), opts)
+            })
+        }
+    }
+}
+
+// a line of an ignore file
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+}
+
+// the rules of the ignore file at path, relative to dir, like git: '#'
+// starts a comment, '!' includes again what an earlier rule excluded, and a
+// trailing '/' matches directories only
+fn read_rules_from(path: &Path, dir: &Path, rules: &mut Vec<Rule>) -> Result<()> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    let contents = fs::read_to_string(path).map_err(|e| Error::Io {
+        path: path.to_path_buf(),
+        source: e,
+    })?;
+    for line in contents.lines() {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let negated = line.starts_with('!');
+        let line = line.trim_start_matches('!');
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        // skip what git would not match either
+        if let Ok(glob) = Glob::new(dir, line) {
+            rules.push(Rule {
+                glob,
+                negated,
+                dir_only,
+            });
+        }
+    }
+    Ok(())
+}
+
+// the rules of the ignore files in dir
+fn read_rules(dir: &Path) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for name in &IGNORE_FILES {
+        read_rules_from(&dir.join(name), dir, &mut rules)?;
+    }
+    Ok(rules)
+}
+
+// the rules that apply to dir from above it: those of the repository's
+// .git/info/exclude and of the ignore files in each directory from the
+// repository root down to the parent of dir. Outside a repository there
+// are none.
+fn inherited_rules(dir: &Path) -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    let repo = match dir.ancestors().find(|dir| dir.join(".git").exists()) {
+        Some(repo) => repo,
+        None => return Ok(rules),
+    };
+    read_rules_from(&repo.join(".git/info/exclude"), repo, &mut rules)?;
+    let mut parents: Vec<&Path> = dir
+        .ancestors()
+        .skip(1)
+        .take_while(|parent| parent.starts_with(repo))
+        .collect();
+    parents.reverse();
+    for parent in parents {
+        rules.extend(read_rules(parent)?);
+    }
+    Ok(rules)
+}
+
+// whether the last rule matching path excludes it
+fn ignored(rules: &[Rule], path: &Path, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| (is_dir || !rule.dir_only) && rule.glob.matches(path))
+        .map_or(false, |rule| !rule.negated)
+}
+
+// whether the beginning of the file has a NUL byte, as git decides it
+pub fn is_binary(path: &Path) -> Result<bool> {
+    let mut head = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(BINARY_CHECK_LEN).read_to_end(&mut head))
+        .map_err(|e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+    Ok(head.contains(&0))
+}
+
+// a file name of the content addressed storage
+fn is_cas_object(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map_or(false, |name| {
+            name.len() == 64 && name.bytes().all(|c| c.is_ascii_hexdigit())
+        })
+}
+
+pub struct Walk {
+    pub include: Vec<Glob>, // files to take, all if empty
+    pub exclude: Vec<Glob>, // files and directories to leave alone
+    pub casdir: Option<PathBuf>,
+}
+
+impl Walk {
+    // the text files below root that are included and not ignored or
+    // excluded, in order. The CAS directory is skipped, or only its objects
+    // when it is root itself.
+    pub fn files(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        let io_error = |path: &Path, e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let root_abs = fs::canonicalize(root).map_err(|e| io_error(root, e))?;
+        let mut files = Vec::new();
+        let mut rules = inherited_rules(&root_abs)?;
+        self.walk(root, &root_abs, &mut rules, &mut files)?;
+        Ok(files)
+    }
+
+    fn walk(
+        &self,
+        dir: &Path,
+        dir_abs: &Path,
+        rules: &mut Vec<Rule>,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let io_error = |e| Error::Io {
+            path: dir.to_path_buf(),
+            source: e,
+        };
+        let is_casdir = self.casdir.as_deref() == Some(dir_abs);
+        let parent_rules = rules.len();
+        rules.extend(read_rules(dir_abs)?);
+        let mut entries = fs::read_dir(dir)
+            .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+            .map_err(io_error)?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            let path_abs = dir_abs.join(entry.file_name());
+            let file_type = entry.file_type().map_err(io_error)?;
+            let is_dir = file_type.is_dir();
+            if entry.file_name() == ".git"
+                || ignored(rules, &path_abs, is_dir)
+                || self.exclude.iter().any(|glob| glob.matches(&path_abs))
+            {
+                continue;
+            }
+            if is_dir {
+                if self.casdir.as_ref() != Some(&path_abs) {
+                    self.walk(&path, &path_abs, rules, files)?;
+                }
+            } else if file_type.is_file()
+                && !(is_casdir && is_cas_object(&path))
+                && (self.include.is_empty()
+                    || self.include.iter().any(|glob| glob.matches(&path_abs)))
+                && !IGNORE_FILES.iter().any(|name| entry.file_name() == *name)
+                && !is_binary(&path)?
+            {
+                files.push(path);
+            }
+        }
+        rules.truncate(parent_rules);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(line: &str) -> Rule {
+        Rule {
+            glob: Glob::new(Path::new("/repo"), line.trim_end_matches('/')).unwrap(),
+            negated: false,
+            dir_only: line.ends_with('/'),
+        }
+    }
+
+    #[test]
+    fn globs() {
+        let name = Glob::new(Path::new("/repo"), "*.ept").unwrap();
+        assert!(name.matches(Path::new("/repo/a.ept")));
+        assert!(name.matches(Path::new("/repo/sub/b.ept")));
+        assert!(!name.matches(Path::new("/repo/a.txt")));
+
+        let anchored = Glob::new(Path::new("/repo"), "/sub/*.ept").unwrap();
+        assert!(anchored.matches(Path::new("/repo/sub/b.ept")));
+        assert!(!anchored.matches(Path::new("/repo/b.ept")));
+        assert!(!anchored.matches(Path::new("/repo/sub/deeper/b.ept")));
+        assert!(!anchored.matches(Path::new("/other/sub/b.ept")));
+
+        let deep = Glob::new(Path::new("/repo"), "docs/**/*.ept").unwrap();
+        assert!(deep.matches(Path::new("/repo/docs/a.ept")));
+        assert!(deep.matches(Path::new("/repo/docs/x/y/a.ept")));
+    }
+
+    #[test]
+    fn ignore_rules() {
+        let mut rules = vec![rule("*.log"), rule("build/")];
+        assert!(ignored(&rules, Path::new("/repo/x/a.log"), false));
+        assert!(ignored(&rules, Path::new("/repo/build"), true));
+        assert!(!ignored(&rules, Path::new("/repo/build"), false));
+        assert!(!ignored(&rules, Path::new("/repo/a.ept"), false));
+
+        // the last matching rule decides
+        rules.push(Rule {
+            negated: true,
+            ..rule("keep.log")
+        });
+        assert!(!ignored(&rules, Path::new("/repo/keep.log"), false));
+        assert!(ignored(&rules, Path::new("/repo/other.log"), false));
+    }
+}
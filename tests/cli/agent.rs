@@ -0,0 +1,264 @@
+use assert_cmd::assert::Assert;
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::{tempdir, TempDir};
+
+// an agent in a private directory, stopped when dropped
+struct Agent {
+    dir: TempDir,
+    socket: PathBuf,
+}
+
+impl Agent {
+    fn start(timeout: &str) -> Agent {
+        let dir = tempdir().unwrap();
+        #[cfg(unix)]
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700)).unwrap();
+        let socket = dir.path().join("agent.sock");
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("agent")
+            .arg("start")
+            .arg("--socket")
+            .arg(&socket)
+            .arg("--timeout")
+            .arg(timeout)
+            .assert()
+            .success()
+            .stdout(format!(
+                "ENPROT_AGENT_SOCK={}; export ENPROT_AGENT_SOCK;\n",
+                socket.display()
+            ));
+        Agent { dir, socket }
+    }
+
+    fn enprot(&self) -> Command {
+        let mut cmd = Command::cargo_bin("enprot").unwrap();
+        cmd.env("ENPROT_AGENT_SOCK", &self.socket)
+            .env_remove("ENPROT_KEY_Agent_007");
+        cmd
+    }
+
+    fn status(&self, expected: &str) {
+        self.enprot()
+            .arg("agent")
+            .arg("status")
+            .assert()
+            .success()
+            .stdout(format!("{}: {}", self.socket.display(), expected));
+    }
+}
+
+impl Drop for Agent {
+    fn drop(&mut self) {
+        let _ = self.enprot().arg("agent").arg("stop").output();
+    }
+}
+
+fn smudge(agent: &Agent, file: &Path) -> Assert {
+    agent
+        .enprot()
+        .arg("git-filter")
+        .arg("smudge")
+        .arg("test.ept")
+        .with_stdin()
+        .buffer(fs::read_to_string(file).unwrap())
+        .assert()
+}
+
+#[test]
+fn passwords_and_keys() {
+    let agent = Agent::start("0");
+    let file = agent.dir.path().join("test.ept");
+    fs::copy("sample/test.ept", &file).unwrap();
+    agent.status("unlocked, 0 key(s)\n");
+
+    // the key derived for encryption is kept, but not the password given
+    agent
+        .enprot()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&file)
+        .assert()
+        .success();
+    agent.status("unlocked, 1 key(s)\n");
+    smudge(&agent, &file)
+        .success()
+        .stdout(fs::read_to_string(&file).unwrap());
+
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("add")
+        .arg("Agent_007")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success();
+    agent.status("unlocked, 1 key(s)\n  Agent_007\n");
+    smudge(&agent, &file)
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+    agent.status("unlocked, 2 key(s)\n  Agent_007\n");
+
+    // a prompted password is given to the agent
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("forget")
+        .arg("Agent_007")
+        .assert()
+        .success();
+    agent.status("unlocked, 0 key(s)\n");
+    agent
+        .enprot()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&file)
+        .arg("-o")
+        .arg("-")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success();
+    agent.status("unlocked, 1 key(s)\n  Agent_007\n");
+
+    agent.enprot().arg("agent").arg("forget").assert().success();
+    agent.status("unlocked, 0 key(s)\n");
+}
+
+#[test]
+fn lock_unlock() {
+    let agent = Agent::start("0");
+    let file = agent.dir.path().join("test.ept");
+    fs::copy("test-data/test-encrypt-agent007.ept", &file).unwrap();
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("add")
+        .arg("Agent_007")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success();
+
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("lock")
+        .with_stdin()
+        .buffer("secret\nsecret\n")
+        .assert()
+        .success();
+    agent.status("locked, 0 key(s)\n  Agent_007\n");
+    smudge(&agent, &file)
+        .success()
+        .stdout(fs::read_to_string(&file).unwrap());
+
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("unlock")
+        .with_stdin()
+        .buffer("wrong\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("agent: wrong passphrase"));
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("unlock")
+        .with_stdin()
+        .buffer("secret\n")
+        .assert()
+        .success();
+    smudge(&agent, &file)
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[test]
+fn timeout() {
+    let agent = Agent::start("1");
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("add")
+        .arg("Agent_007")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success();
+    agent.status("unlocked, 0 key(s)\n  Agent_007\n");
+    thread::sleep(Duration::from_millis(2500));
+    agent.status("unlocked, 0 key(s)\n");
+}
+
+#[test]
+fn stop() {
+    let agent = Agent::start("0");
+    agent.enprot().arg("agent").arg("stop").assert().success();
+    assert!(!agent.socket.exists());
+    agent
+        .enprot()
+        .arg("agent")
+        .arg("status")
+        .assert()
+        .failure()
+        .stderr(predicate::str::starts_with(format!(
+            "agent: {}: ",
+            agent.socket.display()
+        )));
+}
+
+#[test]
+fn no_agent() {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("agent")
+        .arg("status")
+        .env_remove("ENPROT_AGENT_SOCK")
+        .assert()
+        .failure()
+        .stderr("agent: ENPROT_AGENT_SOCK is not set, aborting.\n");
+
+    // without the agent, nothing changes
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg("-o")
+        .arg("-")
+        .env("ENPROT_AGENT_SOCK", "/nonexistent/agent.sock")
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn shared_directory() {
+    let dir = tempdir().unwrap();
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("agent")
+        .arg("serve")
+        .arg("--socket")
+        .arg(dir.path().join("agent.sock"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is accessible by other users"));
+}
@@ -0,0 +1,121 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+
+use Fixture;
+
+fn encrypt(ept: &Fixture, args: &[&str]) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--pbkdf")
+        .arg("argon2")
+        .arg("--pbkdf-params")
+        .arg("t=1,p=1,m=16")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .args(args)
+        .arg(&ept.path)
+        .assert()
+        .success();
+}
+
+#[test]
+fn bind_ad_encrypt_decrypt() {
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt(&ept, &["--bind-ad"]);
+    assert!(fs::read_to_string(&ept.path).unwrap().contains(" ad:v1 "));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn bind_ad_moved_keyword() {
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt(&ept, &["--bind-ad"]);
+    let moved = fs::read_to_string(&ept.path)
+        .unwrap()
+        .replace("Agent_007", "Agent_008");
+    fs::write(&ept.path, moved).unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_008")
+        .arg("-k")
+        .arg("Agent_008=password")
+        .arg(&ept.path)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn doc_id_mismatch() {
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt(&ept, &["--doc-id", "simple-1"]);
+    assert!(fs::read_to_string(&ept.path)
+        .unwrap()
+        .contains(" ad:v1$doc=simple-1 "));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--doc-id")
+        .arg("simple-2")
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Document identifier mismatch"));
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--doc-id")
+        .arg("simple-1")
+        .arg(&ept.path)
+        .assert()
+        .success();
+}
+
+#[test]
+fn strict_policy_requires_ad() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007-gcm.ept");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--policy")
+        .arg("strict")
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Associated data is required by policy",
+        ));
+
+    // binds associated data by default
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt(&ept, &["--policy", "strict"]);
+    assert!(fs::read_to_string(&ept.path).unwrap().contains(" ad:v1 "));
+}
@@ -0,0 +1,83 @@
+use assert_cmd::prelude::*;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+fn store(dir: &Path, args: &[&str]) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir)
+        .arg("-c")
+        .arg("cas")
+        .arg("-s")
+        .arg("Agent_007")
+        .args(args)
+        .assert()
+        .success();
+}
+
+fn names(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[cfg(unix)]
+#[test]
+fn permissions_kept() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("simple.ept");
+    fs::copy("sample/simple.ept", &path).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    fs::create_dir(dir.path().join("cas")).unwrap();
+
+    store(dir.path(), &["simple.ept"]);
+    assert!(fs::read_to_string(&path)
+        .unwrap()
+        .contains("// <( STORED Agent_007 "));
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o640);
+    // no temporary files are left behind, and there is no backup
+    assert_eq!(names(dir.path()), ["cas", "simple.ept"]);
+    let objects = names(&dir.path().join("cas"));
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].len(), 64);
+}
+
+#[test]
+fn backups() {
+    let dir = tempdir().unwrap();
+    let simple = fs::read_to_string("sample/simple.ept").unwrap();
+    fs::write(dir.path().join("a.ept"), &simple).unwrap();
+    fs::write(dir.path().join("b.ept"), &simple).unwrap();
+    fs::create_dir(dir.path().join("cas")).unwrap();
+
+    store(dir.path(), &["--backup", "a.ept"]);
+    store(dir.path(), &["--backup=.orig", "b.ept"]);
+    assert_eq!(
+        names(dir.path()),
+        ["a.ept", "a.ept~", "b.ept", "b.ept.orig", "cas"]
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("a.ept~")).unwrap(),
+        simple
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path().join("b.ept.orig")).unwrap(),
+        simple
+    );
+    assert_ne!(
+        fs::read_to_string(dir.path().join("a.ept")).unwrap(),
+        simple
+    );
+
+    // nothing is backed up for a new file
+    store(dir.path(), &["--backup", "a.ept~", "-o", "new.ept"]);
+    assert!(!dir.path().join("new.ept~").exists());
+}
@@ -0,0 +1,89 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+#[test]
+fn unchanged() {
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--check")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn changes_as_diff() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--check")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("GEHEIM")
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stdout(predicate::str::starts_with(format!(
+            "--- {}\n+++ {}\n@@ -1,11 +1,5 @@\n hello, this is a test file\n\
+             -// <( BEGIN GEHEIM )>\n",
+            ept.path.display(),
+            ept.path.display()
+        )))
+        .stdout(predicate::str::contains(
+            "+// <( STORED GEHEIM cea67c3ef34ff899793b557e9178c1b97bbcfe9722df2f6d35d2d0c91d2c1fe4 )>\n",
+        ));
+    // nothing is written
+    assert_eq!(
+        &fs::read_to_string(&ept.source).unwrap(),
+        &fs::read_to_string(&ept.path).unwrap()
+    );
+    assert_eq!(fs::read_dir(casdir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn protected_keywords() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "protect = [\"@secrets\"]\n[groups]\nsecrets = [\"GEHEIM\", \"Agent_008\"]\n",
+    )
+    .unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("sample/test.ept", &file).unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--check")
+        .arg(&file)
+        .assert()
+        .failure()
+        .stdout(format!("{}: GEHEIM appears in plaintext\n", file.display()));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(dir.path())
+        .arg("-s")
+        .arg("GEHEIM")
+        .arg(&file)
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--check")
+        .arg(&file)
+        .assert()
+        .success()
+        .stdout("");
+}
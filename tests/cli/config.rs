@@ -0,0 +1,155 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+const SIMPLE: &str = "Regular text\n\
+                      // <( BEGIN Agent_007 )>\n\
+                      Secret text\n\
+                      // <( END Agent_007 )>\n\
+                      More regular text\n";
+
+// a project with the configuration at its root and a file in a subdirectory
+fn project(config: &str) -> tempfile::TempDir {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join(".enprot.toml"), config).unwrap();
+    fs::create_dir_all(dir.path().join("src/secret")).unwrap();
+    fs::write(dir.path().join("src/secret/simple.ept"), SIMPLE).unwrap();
+    dir
+}
+
+// run enprot from outside the project
+fn enprot(file: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.current_dir(std::env::temp_dir()).arg(file);
+    cmd
+}
+
+#[test]
+fn settings_from_parent_directory() {
+    let dir = project("cipher = \"aes-256-gcm\"\npbkdf = \"pbkdf2-sha256\"\npbkdf-msec = 10\n");
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&file)
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .assert()
+        .success();
+    let encrypted = fs::read_to_string(&file).unwrap();
+    assert!(encrypted.contains("cipher:aes-256-gcm$"));
+    assert!(encrypted.contains("pbkdf:$pbkdf2-sha256$"));
+}
+
+#[test]
+fn flags_override_config() {
+    let dir = project("cipher = \"aes-256-gcm\"\npbkdf = \"pbkdf2-sha256\"\npbkdf-msec = 10\n");
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&file)
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--cipher")
+        .arg("aes-256-siv")
+        .assert()
+        .success();
+    let encrypted = fs::read_to_string(&file).unwrap();
+    assert!(!encrypted.contains("cipher:aes-256-gcm"));
+    assert!(encrypted.contains("pbkdf:$pbkdf2-sha256$"));
+}
+
+#[test]
+fn casdir_relative_to_config() {
+    let dir = project("casdir = \"objects\"\n");
+    fs::create_dir(dir.path().join("objects")).unwrap();
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&file).arg("-s").arg("Agent_007").assert().success();
+    assert!(fs::read_to_string(&file)
+        .unwrap()
+        .contains("// <( STORED Agent_007 "));
+    assert_eq!(fs::read_dir(dir.path().join("objects")).unwrap().count(), 1);
+
+    enprot(&file).arg("-f").arg("Agent_007").assert().success();
+    assert_eq!(fs::read_to_string(&file).unwrap(), SIMPLE);
+}
+
+#[test]
+fn casdir_created() {
+    // the CAS directory need not exist in a fresh checkout
+    let dir = project("casdir = \"objects\"\n");
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&file).arg("-s").arg("Agent_007").assert().success();
+    assert_eq!(fs::read_dir(dir.path().join("objects")).unwrap().count(), 1);
+}
+
+#[test]
+fn keyword_groups() {
+    let dir = project("[groups]\nagents = [\"Agent_007\", \"Agent_008\"]\n");
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&file)
+        .arg("-c")
+        .arg(dir.path())
+        .arg("-s")
+        .arg("@agents")
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&file)
+        .unwrap()
+        .contains("// <( STORED Agent_007 "));
+
+    enprot(&file)
+        .arg("-c")
+        .arg(dir.path())
+        .arg("-f")
+        .arg("@spies")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown keyword group 'spies'"));
+}
+
+#[test]
+fn separators_by_glob() {
+    let dir = project("[[separators]]\nglob = \"docs/*.txt\"\nleft = \"<<\"\nright = \">>\"\n");
+    fs::create_dir(dir.path().join("docs")).unwrap();
+    let doc = dir.path().join("docs/notes.txt");
+    fs::write(
+        &doc,
+        "<< BEGIN Agent_007 >>\nJames Bond\n<< END Agent_007 >>\n",
+    )
+    .unwrap();
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&doc)
+        .arg(&file)
+        .arg("-c")
+        .arg(dir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&doc)
+        .unwrap()
+        .starts_with("<< STORED Agent_007 "));
+    assert!(fs::read_to_string(&file)
+        .unwrap()
+        .contains("// <( STORED Agent_007 "));
+}
+
+#[test]
+fn invalid_setting() {
+    let dir = project("policy = \"lax\"\n");
+    let file = dir.path().join("src/secret/simple.ept");
+
+    enprot(&file)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid policy 'lax'"));
+}
@@ -0,0 +1,175 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+fn encrypt(path: &Path, words: &str, extra: &[&str]) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg(words)
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .args(extra)
+        .arg(path)
+        .assert()
+        .success();
+}
+
+fn edit(path: &Path, word: &str, editor: &str, runtime: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.arg("edit")
+        .arg("-d")
+        .arg(word)
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(path)
+        .env_remove("VISUAL")
+        .env("EDITOR", editor)
+        .env("XDG_RUNTIME_DIR", runtime);
+    cmd
+}
+
+#[test]
+fn edit_segment() {
+    let runtime = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+    encrypt(
+        &ept.path,
+        "GEHEIM",
+        &[
+            "--pbkdf",
+            "argon2",
+            "--pbkdf-params",
+            "t=1,p=1,m=16",
+            "--cipher",
+            "aes-256-gcm-siv",
+        ],
+    );
+
+    edit(
+        &ept.path,
+        "GEHEIM",
+        "perl -pi -e 's/Secret line 1/Secret line one/'",
+        runtime.path(),
+    )
+    .assert()
+    .success();
+    // encrypted again, the same way
+    let edited = fs::read_to_string(&ept.path).unwrap();
+    assert!(edited.contains("// <( ENCRYPTED GEHEIM "));
+    assert!(edited.contains("pbkdf:$argon2$m=16,p=1,t=1$"));
+    assert!(edited.contains("cipher:aes-256-gcm-siv$iv="));
+    assert!(!edited.contains("Secret line"));
+    // and the temporary file is gone
+    assert_eq!(fs::read_dir(runtime.path()).unwrap().count(), 0);
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("GEHEIM")
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&ept.path).unwrap(),
+        fs::read_to_string("sample/test.ept")
+            .unwrap()
+            .replace("Secret line 1", "Secret line one")
+    );
+}
+
+#[test]
+fn unchanged_segments() {
+    let runtime = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+    encrypt(&ept.path, "Agent_007", &[]);
+    let before = fs::read_to_string(&ept.path).unwrap();
+
+    edit(
+        &ept.path,
+        "Agent_007",
+        "perl -pi -e 's/Super secret/Top secret/'",
+        runtime.path(),
+    )
+    .assert()
+    .success();
+    // only the last segment has new ciphertext
+    let after = fs::read_to_string(&ept.path).unwrap();
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    assert_eq!(before[..8], after[..8]);
+    assert_ne!(before[8..], after[8..]);
+
+    // no changes at all
+    let before = fs::read_to_string(&ept.path).unwrap();
+    edit(&ept.path, "Agent_007", "true", runtime.path())
+        .assert()
+        .success();
+    assert_eq!(before, fs::read_to_string(&ept.path).unwrap());
+}
+
+#[test]
+fn parse_error() {
+    let runtime = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+    encrypt(&ept.path, "GEHEIM", &[]);
+    let before = fs::read_to_string(&ept.path).unwrap();
+
+    edit(
+        &ept.path,
+        "GEHEIM",
+        "perl -ni -e 'print unless /END GEHEIM/'",
+        runtime.path(),
+    )
+    .with_stdin()
+    .buffer("n\n")
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("BEGIN GEHEIM without END"))
+    .stderr(predicate::str::contains("Edit again? [Y/n]"))
+    .stderr(predicate::str::contains("Not saved, the file is unchanged"));
+    assert_eq!(before, fs::read_to_string(&ept.path).unwrap());
+    assert_eq!(fs::read_dir(runtime.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn editor_fails() {
+    let runtime = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+    encrypt(&ept.path, "GEHEIM", &[]);
+    let before = fs::read_to_string(&ept.path).unwrap();
+
+    edit(&ept.path, "GEHEIM", "false", runtime.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("false: editor exit status: 1"));
+    assert_eq!(before, fs::read_to_string(&ept.path).unwrap());
+    assert_eq!(fs::read_dir(runtime.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn nothing_to_edit() {
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("edit")
+        .arg("-d")
+        .arg("GEHEIM")
+        .arg(&ept.path)
+        .env("EDITOR", "true")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No encrypted segments to edit"));
+}
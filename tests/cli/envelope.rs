@@ -0,0 +1,165 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+const PBKDF_ARGS: &[&str] = &["--pbkdf", "argon2", "--pbkdf-params", "t=1,p=1,m=16"];
+
+fn data_lines(path: &std::path::Path) -> Vec<String> {
+    fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .filter(|line| line.contains("DATA "))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn encrypt_to_alice(ept: &Fixture) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--password-recipient")
+        .arg("Agent_007=alice")
+        .arg("-k")
+        .arg("Agent_007/alice=alicepass")
+        .args(PBKDF_ARGS)
+        .arg(&ept.path)
+        .assert()
+        .success();
+}
+
+#[test]
+fn encrypt_decrypt_password_recipient() {
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt_to_alice(&ept);
+    let encrypted = fs::read_to_string(&ept.path).unwrap();
+    assert!(encrypted.contains("pw.alice:$argon2$"));
+    assert!(!encrypted.contains("pbkdf:"));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007/alice=alicepass")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn decrypt_wrong_password_recipient() {
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt_to_alice(&ept);
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007/alice=wrong")
+        .arg(&ept.path)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn grant_revoke_keeps_ciphertext() {
+    let keydir = tempdir().unwrap();
+    let key = keydir.path().join("bob");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--gen-keypair")
+        .arg(&key)
+        .assert()
+        .success();
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt_to_alice(&ept);
+    let data = data_lines(&ept.path);
+
+    // add bob and carol, unlocking with alice's password
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--grant")
+        .arg("Agent_007")
+        .arg("--recipient")
+        .arg(format!("Agent_007={}.pub", key.display()))
+        .arg("--password-recipient")
+        .arg("Agent_007=carol")
+        .arg("-k")
+        .arg("Agent_007/alice=alicepass,Agent_007/carol=carolpass")
+        .args(PBKDF_ARGS)
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let granted = fs::read_to_string(&ept.path).unwrap();
+    assert!(granted.contains("pw.alice:"));
+    assert!(granted.contains("pw.carol:"));
+    assert!(granted.contains("pk."));
+    assert_eq!(data_lines(&ept.path), data);
+
+    // bob can decrypt
+    let out = Fixture::blank("out.ept");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-i")
+        .arg(&key)
+        .arg(&ept.path)
+        .arg("-o")
+        .arg(&out.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&out.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+
+    // drop alice, carol still works
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--revoke")
+        .arg("Agent_007=pw.alice")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert!(!fs::read_to_string(&ept.path).unwrap().contains("pw.alice:"));
+    assert_eq!(data_lines(&ept.path), data);
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007/carol=carolpass")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn revoke_last_recipient() {
+    let ept = Fixture::copy("sample/simple.ept");
+    encrypt_to_alice(&ept);
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--revoke")
+        .arg("Agent_007=pw.alice")
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("last recipient"));
+}
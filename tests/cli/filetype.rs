@@ -0,0 +1,130 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+const MARKDOWN: &str = "# Notes\n\
+                        <!-- <( BEGIN Agent_007 )> -->\n\
+                        James Bond\n\
+                        <!-- <( END Agent_007 )> -->\n";
+
+const SCRIPT: &str = "#!/bin/sh\n\
+                      # <( BEGIN Agent_007 )>\n\
+                      echo James Bond\n\
+                      # <( END Agent_007 )>\n";
+
+fn store(dir: &std::path::Path, file: &str) -> Command {
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.current_dir(dir)
+        .arg("-c")
+        .arg(".")
+        .arg("-s")
+        .arg("Agent_007")
+        .arg(file);
+    cmd
+}
+
+#[test]
+fn separators_from_extension() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("notes.md"), MARKDOWN).unwrap();
+
+    store(dir.path(), "notes.md").assert().success();
+    let stored = fs::read_to_string(dir.path().join("notes.md")).unwrap();
+    assert!(stored.contains("<!-- <( STORED Agent_007 "));
+    assert!(!stored.contains("James Bond"));
+}
+
+#[test]
+fn separators_from_shebang() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("run"), SCRIPT).unwrap();
+
+    store(dir.path(), "run").assert().success();
+    let stored = fs::read_to_string(dir.path().join("run")).unwrap();
+    assert!(stored.starts_with("#!/bin/sh\n# <( STORED Agent_007 "));
+}
+
+#[test]
+fn separators_flags_override() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("notes.md"), MARKDOWN).unwrap();
+
+    // with the default separators, the markup is just text
+    store(dir.path(), "notes.md")
+        .arg("-l")
+        .arg("// <(")
+        .arg("-r")
+        .arg(")>")
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(dir.path().join("notes.md")).unwrap(),
+        MARKDOWN
+    );
+}
+
+#[test]
+fn separators_from_config() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[filetypes.txt]\nleft = \"<<\"\nright = \">>\"\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("notes.txt"),
+        "<< BEGIN Agent_007 >>\nJames Bond\n<< END Agent_007 >>\n",
+    )
+    .unwrap();
+
+    store(dir.path(), "notes.txt").assert().success();
+    let stored = fs::read_to_string(dir.path().join("notes.txt")).unwrap();
+    assert!(stored.starts_with("<< STORED Agent_007 "));
+}
+
+#[test]
+fn invalid_config() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[filetypes.txt]\nleft = 1\n",
+    )
+    .unwrap();
+    fs::write(dir.path().join("notes.txt"), "text\n").unwrap();
+
+    store(dir.path(), "notes.txt").assert().failure();
+}
+
+#[test]
+fn default_separators_kept() {
+    // files protected before their type was detected keep working
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join("notes.md"),
+        "# Notes\n// <( BEGIN Agent_007 )>\nJames Bond\n// <( END Agent_007 )>\n",
+    )
+    .unwrap();
+
+    store(dir.path(), "notes.md").assert().success();
+    let stored = fs::read_to_string(dir.path().join("notes.md")).unwrap();
+    assert!(stored.starts_with("# Notes\n// <( STORED Agent_007 "));
+}
+
+#[test]
+fn unmatched_keyword_warning() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("notes.md"), MARKDOWN).unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .arg("-e")
+        .arg("Agent_007,Agent_008")
+        .arg("-k")
+        .arg("Agent_007=password,Agent_008=password")
+        .arg("notes.md")
+        .assert()
+        .success()
+        .stderr("Warning: no Agent_008 segments in notes.md\n");
+}
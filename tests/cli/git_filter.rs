@@ -0,0 +1,252 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+// a pkt-line of git's filter protocol
+fn packet(data: &str) -> String {
+    format!("{:04x}{}", data.len() + 4, data)
+}
+
+fn git(dir: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("HOME", dir);
+    cmd
+}
+
+#[test]
+fn clean_smudge() {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("git-filter")
+        .arg("clean")
+        .arg("sample/test.ept")
+        .arg("--pbkdf")
+        .arg("legacy")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .with_stdin()
+        .buffer(fs::read_to_string("sample/test.ept").unwrap())
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap());
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("git-filter")
+        .arg("smudge")
+        .arg("sample/test.ept")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .with_stdin()
+        .buffer(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap())
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[test]
+fn smudge_without_key() {
+    // left encrypted, without asking for a password
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("git-filter")
+        .arg("smudge")
+        .arg("sample/test.ept")
+        .env_remove("ENPROT_KEY_Agent_007")
+        .with_stdin()
+        .buffer(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap())
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap());
+}
+
+#[test]
+fn clean_protected() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join(".enprot.toml"), "protect = [\"GEHEIM\"]\n").unwrap();
+    let content = fs::read_to_string("sample/test.ept").unwrap();
+
+    // without a password for GEHEIM, it would be committed in plaintext
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .arg("git-filter")
+        .arg("clean")
+        .arg("test.ept")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .env_remove("ENPROT_KEY_GEHEIM")
+        .env_remove("ENPROT_AGENT_SOCK")
+        .with_stdin()
+        .buffer(content.clone())
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "Error cleaning GEHEIM: protected segment left in plaintext",
+        ));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .arg("git-filter")
+        .arg("clean")
+        .arg("test.ept")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .env("ENPROT_KEY_GEHEIM", "password")
+        .with_stdin()
+        .buffer(content)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("// <( ENCRYPTED GEHEIM "));
+}
+
+#[test]
+fn filter_process() {
+    let content = fs::read_to_string("sample/test.ept").unwrap();
+    let input = packet("git-filter-client\n")
+        + &packet("version=2\n")
+        + "0000"
+        + &packet("capability=clean\n")
+        + &packet("capability=smudge\n")
+        + &packet("capability=delay\n")
+        + "0000"
+        + &packet("command=clean\n")
+        + &packet("pathname=sample/test.ept\n")
+        + "0000"
+        + &packet(&content)
+        + "0000";
+    let expected = fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap();
+    let output = packet("git-filter-server\n")
+        + &packet("version=2\n")
+        + "0000"
+        + &packet("capability=clean\n")
+        + &packet("capability=smudge\n")
+        + "0000"
+        + &packet("status=success\n")
+        + "0000"
+        + &packet(&expected)
+        + "0000"
+        + "0000";
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("git-filter")
+        .arg("process")
+        .arg("--pbkdf")
+        .arg("legacy")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .with_stdin()
+        .buffer(input)
+        .assert()
+        .success()
+        .stdout(output);
+}
+
+#[test]
+fn filter_process_error() {
+    let input = packet("git-filter-client\n")
+        + &packet("version=2\n")
+        + "0000"
+        + &packet("capability=clean\n")
+        + "0000"
+        + &packet("command=clean\n")
+        + &packet("pathname=broken.ept\n")
+        + "0000"
+        + &packet("// <( BEGIN Agent_007 )>\n")
+        + "0000";
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("git-filter")
+        .arg("process")
+        .with_stdin()
+        .buffer(input)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(packet("status=error\n") + "0000"))
+        .stderr(predicate::str::contains("broken.ept"));
+}
+
+#[test]
+fn git_setup() {
+    let dir = tempdir().unwrap();
+    git(dir.path()).arg("init").assert().success();
+    fs::write(dir.path().join(".gitattributes"), "*.png binary").unwrap();
+
+    for _ in 0..2 {
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .current_dir(dir.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("HOME", dir.path())
+            .arg("git-setup")
+            .assert()
+            .success();
+    }
+    assert_eq!(
+        fs::read_to_string(dir.path().join(".gitattributes")).unwrap(),
+        "*.png binary\n*.ept filter=enprot diff=enprot merge=enprot\n"
+    );
+    git(dir.path())
+        .arg("config")
+        .arg("filter.enprot.process")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("enprot' git-filter process\n"));
+
+    // plaintext in the working copy, encrypted in the repository
+    fs::copy("sample/test.ept", dir.path().join("test.ept")).unwrap();
+    git(dir.path())
+        .arg("add")
+        .arg("test.ept")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .assert()
+        .success();
+    git(dir.path())
+        .arg("show")
+        .arg(":test.ept")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("// <( ENCRYPTED Agent_007 "))
+        .stdout(predicate::str::contains("James Bond").not());
+    assert_eq!(
+        fs::read_to_string(dir.path().join("test.ept")).unwrap(),
+        fs::read_to_string("sample/test.ept").unwrap()
+    );
+
+    // adding it again keeps the ciphertext in the index
+    let blob = |dir: &Path| {
+        git(dir)
+            .arg("rev-parse")
+            .arg(":test.ept")
+            .output()
+            .unwrap()
+            .stdout
+    };
+    let before = blob(dir.path());
+    git(dir.path())
+        .arg("add")
+        .arg("--renormalize")
+        .arg("test.ept")
+        .env("ENPROT_KEY_Agent_007", "password")
+        .assert()
+        .success();
+    assert_eq!(before, blob(dir.path()));
+}
+
+#[test]
+fn git_setup_outside_repository() {
+    let dir = tempdir().unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .env("GIT_CEILING_DIRECTORIES", dir.path())
+        .arg("git-setup")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("git: "));
+}
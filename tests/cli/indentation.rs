@@ -0,0 +1,86 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+#[test]
+fn store_fetch_keeps_indentation() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/indented.py");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-l")
+        .arg("# <(")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let stored = fs::read_to_string(&ept.path).unwrap();
+    assert!(stored
+        .lines()
+        .any(|line| line.starts_with("    # <( STORED Agent_007 ")));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-l")
+        .arg("# <(")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-f")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn encrypt_decrypt_keeps_indentation() {
+    let ept = Fixture::copy("sample/indented.py");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-l")
+        .arg("# <(")
+        .arg("-e")
+        .arg("GEHEIM,Agent_007")
+        .arg("-k")
+        .arg("GEHEIM=password,Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let encrypted = fs::read_to_string(&ept.path).unwrap();
+    assert!(encrypted
+        .lines()
+        .any(|line| line.starts_with("    # <( ENCRYPTED Agent_007 ")));
+    assert!(encrypted
+        .lines()
+        .filter(|line| line.contains("DATA "))
+        .all(|line| line.starts_with("    # <( DATA ")));
+    assert!(encrypted.contains("\n    # <( END Agent_007 )>\n"));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-l")
+        .arg("# <(")
+        .arg("-d")
+        .arg("Agent_007,GEHEIM")
+        .arg("-k")
+        .arg("GEHEIM=password,Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
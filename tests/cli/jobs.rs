@@ -0,0 +1,137 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+#[test]
+fn output_in_order() {
+    let dir = tempdir().unwrap();
+    let mut expected = String::new();
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.arg("list").arg("--jobs").arg("4");
+    for i in 0..8 {
+        let path = dir.path().join(format!("{}.ept", i));
+        fs::copy("sample/simple.ept", &path).unwrap();
+        expected += &format!("{}:\n  BEGIN Agent_007\n", path.display());
+        cmd.arg(&path);
+    }
+    cmd.assert().success().stdout(expected);
+}
+
+#[test]
+fn encrypt_decrypt_tree() {
+    let dir = tempdir().unwrap();
+    let casdir = tempdir().unwrap();
+    let mut summary = String::new();
+    for i in 0..6 {
+        let path = dir.path().join(format!("sub{}/{}.ept", i % 2, i));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::copy("sample/simple.ept", &path).unwrap();
+    }
+    for i in &[0, 2, 4, 1, 3, 5] {
+        summary += &format!("{}/sub{}/{}.ept\n", dir.path().display(), i % 2, i);
+    }
+    summary += "6 of 6 file(s) changed\n";
+
+    // the password is asked for only once
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .env_remove("ENPROT_AGENT_SOCK")
+        .arg("-R")
+        .arg(dir.path())
+        .arg("--jobs")
+        .arg("3")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-e")
+        .arg("Agent_007")
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(summary.clone()));
+    for i in 0..6 {
+        let path = dir.path().join(format!("sub{}/{}.ept", i % 2, i));
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .contains("// <( ENCRYPTED Agent_007 "));
+    }
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .env_remove("ENPROT_AGENT_SOCK")
+        .arg("-R")
+        .arg(dir.path())
+        .arg("--jobs")
+        .arg("3")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-d")
+        .arg("Agent_007")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(summary));
+    let simple = fs::read_to_string("sample/simple.ept").unwrap();
+    for i in 0..6 {
+        let path = dir.path().join(format!("sub{}/{}.ept", i % 2, i));
+        assert_eq!(fs::read_to_string(&path).unwrap(), simple);
+    }
+}
+
+#[test]
+fn error_after_output() {
+    let dir = tempdir().unwrap();
+    let first = dir.path().join("first.ept");
+    let missing = dir.path().join("missing.ept");
+    fs::copy("sample/simple.ept", &first).unwrap();
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.arg("list")
+        .arg("--jobs")
+        .arg("4")
+        .arg(&first)
+        .arg(&missing);
+    for i in 0..4 {
+        let path = dir.path().join(format!("{}.ept", i));
+        fs::copy("sample/simple.ept", &path).unwrap();
+        cmd.arg(&path);
+    }
+
+    // the files before the one that failed are shown, those after it not
+    cmd.assert()
+        .failure()
+        .stdout(format!("{}:\n  BEGIN Agent_007\n", first.display()))
+        .stderr(format!(
+            "Failed to open {} for reading: No such file or directory (os error 2)\n",
+            missing.display()
+        ));
+}
+
+#[test]
+fn messages_in_order() {
+    let dir = tempdir().unwrap();
+    let encrypted = fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap();
+    let unknown = encrypted.replacen("ENCRYPTED Agent_007 ", "ENCRYPTED Agent_007 foo:bar ", 1);
+    let mut expected = String::new();
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.arg("list").arg("-v").arg("--jobs").arg("4");
+    for i in 0..6 {
+        let path = dir.path().join(format!("{}.ept", i));
+        expected += &format!(
+            "Reading {}\nLEFT_SEP='// <(' RIGHT_SEP=')>' casdir = './'\n",
+            path.display()
+        );
+        if i % 2 == 0 {
+            fs::write(&path, &unknown).unwrap();
+            expected += "Warning: Unrecognized extended field(s) present\n";
+        } else {
+            fs::write(&path, &encrypted).unwrap();
+        }
+        cmd.arg(&path);
+    }
+
+    // the messages of each file come with it
+    cmd.assert().success().stderr(expected);
+}
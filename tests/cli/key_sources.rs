@@ -0,0 +1,342 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+fn decrypt() -> Command {
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.arg("-d")
+        .arg("Agent_007")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg("-o")
+        .arg("-")
+        .env_remove("ENPROT_KEY_Agent_007");
+    cmd
+}
+
+#[test]
+fn key_file() {
+    let dir = tempdir().unwrap();
+    let file = dir.path().join("password");
+    fs::write(&file, "password\nnot the password\n").unwrap();
+
+    decrypt()
+        .arg("--key-file")
+        .arg(format!("Agent_007={}", file.display()))
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[test]
+fn key_env() {
+    decrypt()
+        .arg("--key-env")
+        .arg("Agent_007=AGENT_PASSWORD")
+        .env("AGENT_PASSWORD", "password")
+        .env("ENPROT_KEY_Agent_007", "wrong")
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+
+    decrypt()
+        .arg("--key-env")
+        .arg("Agent_007=AGENT_PASSWORD")
+        .env_remove("AGENT_PASSWORD")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Cannot get the password for Agent_007: environment variable AGENT_PASSWORD is not set",
+        ));
+}
+
+#[cfg(unix)]
+#[test]
+fn key_fd() {
+    decrypt()
+        .arg("--key-fd")
+        .arg("Agent_007=0")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn key_cmd() {
+    decrypt()
+        .arg("--key-cmd")
+        .arg("Agent_007=printf 'pass%s\\n' word")
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+
+    decrypt()
+        .arg("--key-cmd")
+        .arg("Agent_007=exit 3")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Cannot get the password for Agent_007: exit 3: exit status: 3",
+        ));
+}
+
+#[test]
+fn key_source_arguments() {
+    decrypt()
+        .arg("--key-fd")
+        .arg("Agent_007=stdin")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid fd 'stdin'"));
+    decrypt()
+        .arg("--key-cmd")
+        .arg("pass show agent")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Must be of the form WORD=COMMAND"));
+}
+
+#[test]
+fn key_config() {
+    let dir = tempdir().unwrap();
+    fs::create_dir(dir.path().join("keys")).unwrap();
+    fs::write(dir.path().join("keys/agent"), "password\n").unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[keys]\nAgent_007 = { file = \"keys/agent\" }\nGEHEIM = { cmd = \"exit 1\" }\n",
+    )
+    .unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("test-data/test-encrypt-agent007.ept", &file).unwrap();
+
+    // the configuration in the home directory is the user's own, so it may
+    // have commands
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&file)
+        .env("HOME", dir.path())
+        .env_remove("ENPROT_KEY_Agent_007")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        fs::read_to_string("sample/test.ept").unwrap()
+    );
+
+    // the command line takes precedence
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--key-env")
+        .arg("Agent_007=AGENT_PASSWORD")
+        .arg(&file)
+        .env("HOME", dir.path())
+        .env("AGENT_PASSWORD", "other")
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&file)
+        .env("HOME", dir.path())
+        .env_remove("ENPROT_KEY_Agent_007")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Decryption failed"));
+}
+
+#[test]
+fn key_config_untrusted() {
+    let dir = tempdir().unwrap();
+    let home = tempdir().unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("test-data/test-encrypt-agent007.ept", &file).unwrap();
+
+    // a project configuration cannot run commands or read files outside it
+    for source in &[
+        "cmd = \"printf password\"",
+        "fd = 0",
+        "file = \"/etc/hostname\"",
+        "file = \"../outside\"",
+        "env = \"AWS_SECRET_ACCESS_KEY\"",
+    ] {
+        fs::write(
+            dir.path().join(".enprot.toml"),
+            format!("[keys]\nAgent_007 = {{ {} }}\n", source),
+        )
+        .unwrap();
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-d")
+            .arg("Agent_007")
+            .arg(&file)
+            .env("HOME", home.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("ENPROT_KEY_Agent_007")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                ".enprot.toml: the password source of Agent_007 needs a trusted configuration",
+            ));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn key_config_symlink() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tempdir().unwrap();
+    let home = tempdir().unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("test-data/test-encrypt-agent007.ept", &file).unwrap();
+    fs::write(home.path().join("secret"), "password\n").unwrap();
+    symlink(home.path().join("secret"), dir.path().join("innocent")).unwrap();
+    symlink(home.path(), dir.path().join("keys")).unwrap();
+
+    // links committed to the project do not lead out of it
+    for path in &["innocent", "keys/secret", "keys/missing"] {
+        fs::write(
+            dir.path().join(".enprot.toml"),
+            format!("[keys]\nAgent_007 = {{ file = \"{}\" }}\n", path),
+        )
+        .unwrap();
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-d")
+            .arg("Agent_007")
+            .arg(&file)
+            .env("HOME", home.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("ENPROT_KEY_Agent_007")
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                ".enprot.toml: the password source of Agent_007 needs a trusted configuration",
+            ));
+    }
+}
+
+#[test]
+fn key_config_env() {
+    let dir = tempdir().unwrap();
+    let home = tempdir().unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("test-data/test-encrypt-agent007.ept", &file).unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[keys]\nAgent_007 = { env = \"ENPROT_AGENT\" }\n",
+    )
+    .unwrap();
+
+    // variables of our own are fine without trust
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&file)
+        .env("HOME", home.path())
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("ENPROT_AGENT", "password")
+        .env_remove("ENPROT_KEY_Agent_007")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        fs::read_to_string("sample/test.ept").unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn key_config_trusted() {
+    let dir = tempdir().unwrap();
+    let home = tempdir().unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("test-data/test-encrypt-agent007.ept", &file).unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[keys]\nAgent_007 = { cmd = \"printf password\" }\n",
+    )
+    .unwrap();
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .current_dir(dir.path())
+            .env("HOME", home.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .args(args)
+            .assert()
+            .success();
+    };
+    git(&["init", "-q"]);
+    git(&["config", "enprot.trustConfig", "true"]);
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&file)
+        .env("HOME", home.path())
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env_remove("ENPROT_KEY_Agent_007")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        fs::read_to_string("sample/test.ept").unwrap()
+    );
+}
+
+#[test]
+fn key_config_git_filter() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("agent"), "password\n").unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[keys]\nAgent_007 = { file = \"agent\" }\n",
+    )
+    .unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .arg("git-filter")
+        .arg("smudge")
+        .arg("test.ept")
+        .env_remove("ENPROT_KEY_Agent_007")
+        .with_stdin()
+        .buffer(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap())
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[test]
+fn key_config_invalid() {
+    let dir = tempdir().unwrap();
+    fs::write(
+        dir.path().join(".enprot.toml"),
+        "[keys]\nAgent_007 = { password = \"password\" }\n",
+    )
+    .unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("sample/test.ept", &file).unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("list")
+        .arg(&file)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            ".enprot.toml: unknown variant `password`",
+        ));
+}
@@ -0,0 +1,165 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+// enprot with the keystore file in dir
+fn enprot(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.env("XDG_DATA_HOME", dir)
+        .env_remove("ENPROT_KEYSTORE_PASSWORD")
+        .env_remove("ENPROT_AGENT_SOCK")
+        .env_remove("ENPROT_KEY_Agent_007");
+    cmd
+}
+
+fn decrypt(dir: &Path) -> Command {
+    let mut cmd = enprot(dir);
+    cmd.arg("--keyring")
+        .arg("file")
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg("-o")
+        .arg("-");
+    cmd
+}
+
+#[test]
+fn keystore_file() {
+    let dir = tempdir().unwrap();
+    let keystore = dir.path().join("enprot/keystore");
+
+    // the password is kept once it decrypted the segment
+    decrypt(dir.path())
+        .with_stdin()
+        .buffer("password\nmaster\nmaster\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            fs::read_to_string("sample/test.ept").unwrap(),
+        ));
+    let sealed = fs::read_to_string(&keystore).unwrap();
+    assert!(sealed.starts_with("ENPROT-KEYSTORE pbkdf:$argon2$"));
+    assert!(!sealed.contains("password"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(&keystore).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // and only the passphrase of the keystore is asked for
+    decrypt(dir.path())
+        .with_stdin()
+        .buffer("master\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            fs::read_to_string("sample/test.ept").unwrap(),
+        ));
+    decrypt(dir.path())
+        .with_stdin()
+        .buffer("wrong\n")
+        .assert()
+        .failure()
+        .stderr(format!(
+            "{}: Decryption failed, wrong key or corrupted data, aborting.\n",
+            keystore.display()
+        ));
+}
+
+#[test]
+fn wrong_password_not_kept() {
+    let dir = tempdir().unwrap();
+
+    decrypt(dir.path())
+        .with_stdin()
+        .buffer("wrong\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Decryption failed"));
+    assert!(!dir.path().join("enprot").exists());
+}
+
+#[test]
+fn encryption_password_kept() {
+    let dir = tempdir().unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("sample/test.ept", &file).unwrap();
+
+    // repeated, so it is kept right away
+    enprot(dir.path())
+        .arg("--keyring")
+        .arg("file")
+        .arg("-e")
+        .arg("Agent_007")
+        .arg(&file)
+        .env("ENPROT_KEYSTORE_PASSWORD", "master")
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    enprot(dir.path())
+        .arg("--keyring")
+        .arg("file")
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&file)
+        .env("ENPROT_KEYSTORE_PASSWORD", "master")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        fs::read_to_string("sample/test.ept").unwrap()
+    );
+}
+
+#[test]
+fn keyring_config_git_filter() {
+    let dir = tempdir().unwrap();
+    decrypt(dir.path())
+        .env("ENPROT_KEYSTORE_PASSWORD", "master")
+        .with_stdin()
+        .buffer("password\n")
+        .assert()
+        .success();
+    let project = dir.path().join("project");
+    fs::create_dir(&project).unwrap();
+    fs::write(project.join(".enprot.toml"), "keyring = \"file\"\n").unwrap();
+
+    // the filter cannot ask for the passphrase
+    let smudge = |passphrase: Option<&str>| {
+        let mut cmd = enprot(dir.path());
+        cmd.current_dir(&project)
+            .arg("git-filter")
+            .arg("smudge")
+            .arg("test.ept");
+        if let Some(passphrase) = passphrase {
+            cmd.env("ENPROT_KEYSTORE_PASSWORD", passphrase);
+        }
+        cmd.with_stdin()
+            .buffer(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap())
+            .assert()
+            .success()
+    };
+    smudge(None).stdout(fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap());
+    smudge(Some("master")).stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[test]
+fn keyring_config_invalid() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join(".enprot.toml"), "keyring = \"kwallet\"\n").unwrap();
+    let file = dir.path().join("test.ept");
+    fs::copy("sample/test.ept", &file).unwrap();
+
+    enprot(dir.path())
+        .arg("list")
+        .arg(&file)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid keyring 'kwallet'"));
+}
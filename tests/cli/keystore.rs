@@ -0,0 +1,295 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+// enprot with the passphrase of the keystore in the environment
+fn enprot(passphrase: &str) -> Command {
+    let mut cmd = Command::cargo_bin("enprot").unwrap();
+    cmd.env("ENPROT_KEYSTORE_PASSWORD", passphrase)
+        .env_remove("ENPROT_AGENT_SOCK")
+        .env_remove("ENPROT_KEY_Agent_007");
+    cmd
+}
+
+fn keystore(keystore: &Path, args: &[&str]) -> Command {
+    let mut cmd = enprot("master");
+    cmd.arg("keystore")
+        .arg("--keystore")
+        .arg(keystore)
+        .args(args);
+    cmd
+}
+
+#[test]
+fn add_list_remove() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+
+    keystore(&path, &["add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    keystore(&path, &["add", "GEHEIM"])
+        .with_stdin()
+        .buffer("secret\nsecret\n")
+        .assert()
+        .success();
+    let sealed = fs::read_to_string(&path).unwrap();
+    assert!(sealed.starts_with("ENPROT-KEYSTORE pbkdf:$argon2$"));
+    assert!(!sealed.contains("secret"));
+    keystore(&path, &["list"])
+        .assert()
+        .success()
+        .stdout("Agent_007: password\nGEHEIM: password\n");
+    keystore(&path, &["export", "GEHEIM"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("GEHEIM = \"secret\"\n"))
+        .stdout(predicate::str::contains("Agent_007").not());
+
+    keystore(&path, &["remove", "GEHEIM"]).assert().success();
+    keystore(&path, &["list"])
+        .assert()
+        .success()
+        .stdout("Agent_007: password\n");
+    keystore(&path, &["remove", "GEHEIM"])
+        .assert()
+        .failure()
+        .stderr(format!(
+            "{}: No such keyword in the keystore, aborting.\n",
+            path.display()
+        ));
+}
+
+#[cfg(unix)]
+#[test]
+fn private_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+    keystore(&path, &["add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
+
+#[test]
+fn concurrent_adds() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+    keystore(&path, &["add", "first"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+
+    // none of the entries added at the same time are lost
+    let words = ["a", "b", "c", "d"];
+    let children: Vec<_> = words
+        .iter()
+        .map(|word| {
+            let mut cmd = keystore(&path, &["add", word]);
+            cmd.stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::null());
+            let mut child = cmd.spawn().unwrap();
+            let stdin = child.stdin.as_mut().unwrap();
+            std::io::Write::write_all(stdin, b"password\npassword\n").unwrap();
+            child
+        })
+        .collect();
+    for child in children {
+        assert!(child.wait_with_output().unwrap().status.success());
+    }
+    keystore(&path, &["list"])
+        .assert()
+        .success()
+        .stdout("a: password\nb: password\nc: password\nd: password\nfirst: password\n");
+}
+
+#[test]
+fn wrong_passphrase() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+
+    keystore(&path, &["list"]).assert().failure();
+    keystore(&path, &["add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    enprot("wrong")
+        .arg("keystore")
+        .arg("--keystore")
+        .arg(&path)
+        .arg("list")
+        .assert()
+        .failure()
+        .stderr(format!(
+            "{}: Decryption failed, wrong key or corrupted data, aborting.\n",
+            path.display()
+        ));
+}
+
+#[test]
+fn decrypt_with_password() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+
+    keystore(&path, &["add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    enprot("master")
+        .arg("--keystore")
+        .arg(&path)
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(
+            fs::read_to_string("sample/test.ept").unwrap(),
+        ));
+}
+
+#[test]
+fn decrypt_with_jobs() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+    keystore(&path, &["add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    let files: Vec<_> = (0..6)
+        .map(|i| dir.path().join(format!("{}.ept", i)))
+        .collect();
+    let decrypt = |passphrase: &str| {
+        for file in &files {
+            fs::copy("test-data/test-encrypt-agent007.ept", file).unwrap();
+        }
+        let mut cmd = enprot(passphrase);
+        cmd.arg("--keystore")
+            .arg(&path)
+            .arg("--jobs")
+            .arg("3")
+            .arg("-d")
+            .arg("Agent_007")
+            .args(&files);
+        cmd
+    };
+
+    // the keystore is opened once, for all workers
+    decrypt("master").assert().success();
+    for file in &files {
+        assert_eq!(
+            fs::read_to_string(file).unwrap(),
+            fs::read_to_string("sample/test.ept").unwrap()
+        );
+    }
+    decrypt("wrong").assert().failure().stderr(format!(
+        "{}: Decryption failed, wrong key or corrupted data, aborting.\n",
+        path.display()
+    ));
+}
+
+#[test]
+fn policy() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+
+    // sealed under the policy given
+    keystore(&path, &["--fips", "add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    let sealed = fs::read_to_string(&path).unwrap();
+    assert!(sealed.contains(" pbkdf:$pbkdf2-sha512$"));
+    keystore(&path, &["list", "--policy", "nist"])
+        .assert()
+        .success()
+        .stdout("Agent_007: password\n");
+    keystore(&path, &["--fips", "--policy", "default", "list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Policy setting of 'default' conflicts with --fips",
+        ));
+
+    // and opened under it, before any file is looked at
+    fs::remove_file(&path).unwrap();
+    keystore(&path, &["add", "Agent_007"])
+        .with_stdin()
+        .buffer("password\npassword\n")
+        .assert()
+        .success();
+    enprot("master")
+        .arg("--keystore")
+        .arg(&path)
+        .arg("--policy")
+        .arg("nist")
+        .arg("sample/simple.ept")
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("argon2"));
+}
+
+#[test]
+fn decrypt_with_private_key() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("keystore");
+    let key = dir.path().join("alice");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--gen-keypair")
+        .arg(&key)
+        .assert()
+        .success();
+    let ept = Fixture::copy("sample/simple.ept");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--recipient")
+        .arg(format!("Agent_007={}.pub", key.display()))
+        .arg(&ept.path)
+        .assert()
+        .success();
+
+    keystore(&path, &["add", "alice", "--private-key"])
+        .arg(&key)
+        .assert()
+        .success();
+    keystore(&path, &["list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match("^alice: private key [0-9a-f]+\n$").unwrap());
+    enprot("master")
+        .arg("--keystore")
+        .arg(&path)
+        .arg("-d")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
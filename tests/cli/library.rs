@@ -0,0 +1,99 @@
+use enprot::{Credentials, Document, Error, Operations};
+use std::fs;
+use std::io::Cursor;
+use tempfile::tempdir;
+
+use Fixture;
+
+fn to_string(doc: &Document, ops: &mut Operations) -> String {
+    let mut out = Vec::new();
+    doc.write(&mut out, ops).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn library_encrypt_decrypt() {
+    let ept = Fixture::copy("sample/simple.ept");
+    let source = fs::read_to_string(&ept.source).unwrap();
+    let creds = || Credentials::new().password("Agent_007", "password");
+
+    let mut ops = Operations::default()
+        .encrypt("Agent_007")
+        .credentials(creds());
+    let doc = Document::read(&ept.path, &mut ops).unwrap();
+    let encrypted = doc.transform(&mut ops).unwrap();
+    let text = to_string(&encrypted, &mut ops);
+    assert!(text.contains("ENCRYPTED Agent_007"));
+    assert!(!text.contains("Secret text"));
+
+    let mut ops = Operations::default()
+        .decrypt("Agent_007")
+        .credentials(creds());
+    let decrypted = encrypted.transform(&mut ops).unwrap();
+    assert_eq!(to_string(&decrypted, &mut ops), source);
+}
+
+#[test]
+fn library_store_fetch() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/simple.ept");
+    let source = fs::read_to_string(&ept.source).unwrap();
+
+    let mut ops = Operations::default()
+        .store("Agent_007")
+        .casdir(casdir.path());
+    let doc = Document::read(&ept.path, &mut ops).unwrap();
+    let stored = doc.transform(&mut ops).unwrap();
+    assert!(to_string(&stored, &mut ops).contains("STORED Agent_007"));
+
+    let mut ops = Operations::default()
+        .fetch("Agent_007")
+        .casdir(casdir.path());
+    let fetched = stored.transform(&mut ops).unwrap();
+    assert_eq!(to_string(&fetched, &mut ops), source);
+}
+
+#[test]
+fn library_warnings() {
+    let encrypted = fs::read_to_string("test-data/test-encrypt-agent007.ept").unwrap();
+    let unknown = encrypted.replacen("ENCRYPTED Agent_007 ", "ENCRYPTED Agent_007 foo:bar ", 1);
+    let mut ops = Operations::default();
+
+    Document::parse("unknown.ept", Cursor::new(unknown), &mut ops).unwrap();
+    assert_eq!(
+        ops.take_warnings(),
+        vec!["Warning: Unrecognized extended field(s) present".to_string()]
+    );
+    assert!(ops.take_warnings().is_empty());
+}
+
+#[test]
+fn library_keywords() {
+    let mut ops = Operations::default();
+    let doc = Document::read("sample/test.ept", &mut ops).unwrap();
+    let keywords = doc.keywords().into_iter().collect::<Vec<String>>();
+    assert_eq!(keywords, vec!["Agent_007", "GEHEIM"]);
+}
+
+#[test]
+fn library_missing_password() {
+    let mut ops = Operations::default().encrypt("Agent_007");
+    let doc = Document::read("sample/simple.ept", &mut ops).unwrap();
+    match doc.transform(&mut ops) {
+        Err(Error::MissingPassword(name)) => assert_eq!(name, "Agent_007"),
+        _ => panic!("expected a missing password error"),
+    }
+}
+
+#[test]
+fn library_parse_error() {
+    let mut ops = Operations::default();
+    let ept = "// <( BEGIN Agent_007 )>\nSecret text\n";
+    match Document::parse("broken.ept", Cursor::new(ept), &mut ops) {
+        Err(Error::Parse { file, line, .. }) => {
+            assert_eq!(file, "broken.ept");
+            assert_eq!(line, 2);
+        }
+        _ => panic!("expected a parse error"),
+    }
+}
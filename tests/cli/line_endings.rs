@@ -0,0 +1,140 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+const CRLF_EPT: &str = "Regular text\r\n\
+                        // <( BEGIN Agent_007 )>\r\n\
+                        Secret text\r\n\
+                        // <( END Agent_007 )>\r\n\
+                        More regular text\r\n";
+
+// spacing and a missing final newline, as a hand written file might have
+const UNUSUAL_EPT: &str = "Regular text\n\
+                           // <(BEGIN Agent_007)>\n\
+                           Secret text\n\
+                           // <(   END   Agent_007 )>  \n\
+                           More regular text";
+
+#[test]
+fn noop_is_byte_identical() {
+    for contents in &[CRLF_EPT, UNUSUAL_EPT] {
+        let ept = Fixture::blank("file.ept");
+        fs::write(&ept.path, contents).unwrap();
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-s")
+            .arg("noexist")
+            .arg(&ept.path)
+            .assert()
+            .success();
+        assert_eq!(&fs::read_to_string(&ept.path).unwrap(), contents);
+    }
+}
+
+#[test]
+fn store_fetch_keeps_crlf() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::blank("crlf.ept");
+    fs::write(&ept.path, CRLF_EPT).unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let stored = fs::read_to_string(&ept.path).unwrap();
+    assert!(stored.contains("// <( STORED Agent_007 "));
+    assert_eq!(stored.matches("\r\n").count(), stored.matches('\n').count());
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-f")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(&fs::read_to_string(&ept.path).unwrap(), CRLF_EPT);
+}
+
+#[test]
+fn encrypt_decrypt_keeps_crlf() {
+    let ept = Fixture::blank("crlf.ept");
+    fs::write(&ept.path, CRLF_EPT).unwrap();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let encrypted = fs::read_to_string(&ept.path).unwrap();
+    assert!(encrypted.contains("// <( DATA "));
+    assert_eq!(
+        encrypted.matches("\r\n").count(),
+        encrypted.matches('\n').count()
+    );
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(&fs::read_to_string(&ept.path).unwrap(), CRLF_EPT);
+}
+
+#[test]
+fn eol_not_inherited() {
+    let casdir = tempdir().unwrap();
+    let crlf = Fixture::blank("crlf.ept");
+    fs::write(&crlf.path, CRLF_EPT).unwrap();
+    let single = Fixture::blank("single.ept");
+    fs::write(
+        &single.path,
+        "// <( BEGIN Agent_007 )>\nSecret text\n// <( END Agent_007 )>\n",
+    )
+    .unwrap();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .arg(&single.path)
+        .assert()
+        .success();
+    // a single line without a terminator
+    let stored = fs::read_to_string(&single.path).unwrap();
+    fs::write(&single.path, stored.trim_end()).unwrap();
+
+    // fetching it after a CRLF file still uses newlines
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-f")
+        .arg("Agent_007")
+        .arg(&crlf.path)
+        .arg(&single.path)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&single.path).unwrap(),
+        "// <( BEGIN Agent_007 )>\nSecret text\n// <( END Agent_007 )>\n"
+    );
+}
@@ -0,0 +1,227 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+// sample/test.ept with its lines replaced, GEHEIM encrypted as path
+fn version(path: &Path, replace: &[(&str, &str)]) {
+    let mut content = fs::read_to_string("sample/test.ept").unwrap();
+    for (from, to) in replace {
+        content = content.replace(from, to);
+    }
+    fs::write(path, content).unwrap();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("GEHEIM")
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg(path)
+        .assert()
+        .success();
+}
+
+fn decrypted(path: &Path) -> String {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("GEHEIM")
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg(path)
+        .arg("-o")
+        .arg("-")
+        .output()
+        .unwrap()
+        .stdout
+        .into_iter()
+        .map(char::from)
+        .collect()
+}
+
+fn git(dir: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("HOME", dir)
+        .env("GIT_AUTHOR_NAME", "enprot")
+        .env("GIT_AUTHOR_EMAIL", "enprot@example.com")
+        .env("GIT_COMMITTER_NAME", "enprot")
+        .env("GIT_COMMITTER_EMAIL", "enprot@example.com")
+        .env("ENPROT_KEY_GEHEIM", "secret");
+    cmd
+}
+
+#[test]
+fn merge() {
+    let dir = tempdir().unwrap();
+    let (base, ours, theirs) = (
+        dir.path().join("base.ept"),
+        dir.path().join("ours.ept"),
+        dir.path().join("theirs.ept"),
+    );
+    version(&base, &[]);
+    version(&ours, &[("Secret line 1", "Secret line one")]);
+    version(&theirs, &[("James Bond", "Bond, James Bond")]);
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("merge-driver")
+        .arg(&base)
+        .arg(&ours)
+        .arg(&theirs)
+        .env("ENPROT_KEY_GEHEIM", "secret")
+        .assert()
+        .success();
+    let merged = fs::read_to_string(&ours).unwrap();
+    assert!(merged.contains("// <( ENCRYPTED GEHEIM "));
+    assert!(!merged.contains("Secret line"));
+    // replaced atomically, without a temporary file left behind
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    assert_eq!(
+        decrypted(&ours),
+        fs::read_to_string("sample/test.ept")
+            .unwrap()
+            .replace("Secret line 1", "Secret line one")
+            .replace("James Bond", "Bond, James Bond")
+    );
+}
+
+#[test]
+fn conflict() {
+    let dir = tempdir().unwrap();
+    let (base, ours, theirs) = (
+        dir.path().join("base.ept"),
+        dir.path().join("ours.ept"),
+        dir.path().join("theirs.ept"),
+    );
+    version(&base, &[]);
+    version(&ours, &[("Secret line 1", "Secret line one")]);
+    version(&theirs, &[("Secret line 1", "Secret line uno")]);
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("merge-driver")
+        .arg(&base)
+        .arg(&ours)
+        .arg(&theirs)
+        .arg("test.ept")
+        .env("ENPROT_KEY_GEHEIM", "secret")
+        .assert()
+        .failure()
+        .stderr("test.ept: 1 conflict(s)\n");
+    // the markers are in the plaintext only
+    assert!(!fs::read_to_string(&ours).unwrap().contains("<<<<<<<"));
+    assert!(decrypted(&ours).contains(
+        "// <( BEGIN GEHEIM )>\n<<<<<<< ours\nSecret line one\n=======\nSecret line uno\n\
+         >>>>>>> theirs\nSecret line 2\n"
+    ));
+}
+
+#[test]
+fn without_key() {
+    let dir = tempdir().unwrap();
+    let (base, ours, theirs) = (
+        dir.path().join("base.ept"),
+        dir.path().join("ours.ept"),
+        dir.path().join("theirs.ept"),
+    );
+    version(&base, &[]);
+    version(&ours, &[("Secret line 1", "Secret line one")]);
+    let base_content = fs::read_to_string(&base).unwrap();
+    fs::write(&theirs, base_content.replace("hello", "goodbye")).unwrap();
+    let ours_content = fs::read_to_string(&ours).unwrap();
+
+    // the changed segment is taken as a whole
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("merge-driver")
+        .arg(&base)
+        .arg(&ours)
+        .arg(&theirs)
+        .env_remove("ENPROT_KEY_GEHEIM")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&ours).unwrap(),
+        ours_content.replace("hello", "goodbye")
+    );
+}
+
+#[test]
+fn git_merge() {
+    let dir = tempdir().unwrap();
+    git(dir.path()).arg("init").arg("-q").assert().success();
+    git(dir.path())
+        .arg("checkout")
+        .arg("-q")
+        .arg("-b")
+        .arg("main")
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("HOME", dir.path())
+        .arg("git-setup")
+        .assert()
+        .success();
+    let file = dir.path().join("test.ept");
+    let commit = |message: &str| {
+        git(dir.path())
+            .arg("commit")
+            .arg("-q")
+            .arg("-a")
+            .arg("-m")
+            .arg(message)
+            .assert()
+            .success();
+    };
+
+    fs::copy("sample/test.ept", &file).unwrap();
+    git(dir.path()).arg("add").arg(".").assert().success();
+    commit("base");
+    git(dir.path())
+        .arg("checkout")
+        .arg("-q")
+        .arg("-b")
+        .arg("other")
+        .assert()
+        .success();
+    let content = fs::read_to_string(&file).unwrap();
+    fs::write(&file, content.replace("James Bond", "Bond, James Bond")).unwrap();
+    commit("other");
+    git(dir.path())
+        .arg("checkout")
+        .arg("-q")
+        .arg("main")
+        .assert()
+        .success();
+    let content = fs::read_to_string(&file).unwrap();
+    fs::write(&file, content.replace("Secret line 1", "Secret line one")).unwrap();
+    commit("ours");
+
+    git(dir.path())
+        .arg("merge")
+        .arg("-q")
+        .arg("--no-edit")
+        .arg("other")
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&file).unwrap(),
+        fs::read_to_string("sample/test.ept")
+            .unwrap()
+            .replace("Secret line 1", "Secret line one")
+            .replace("James Bond", "Bond, James Bond")
+    );
+    git(dir.path())
+        .arg("show")
+        .arg("HEAD:test.ept")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Secret line").not());
+}
@@ -0,0 +1,136 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+
+use Fixture;
+
+fn decrypt_matches(ept: &Fixture, source: &str) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(source).unwrap()
+    );
+}
+
+#[test]
+fn migrate_legacy_pbkdf() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-v")
+        .arg("--migrate")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--pbkdf-params")
+        .arg("t=1,p=1,m=16")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Migrated Agent_007 in "))
+        .stderr(predicate::str::contains("pbkdf legacy -> argon2"));
+    assert!(fs::read_to_string(&ept.path)
+        .unwrap()
+        .contains("pbkdf:$argon2$"));
+    decrypt_matches(&ept, "sample/simple.ept");
+}
+
+#[test]
+fn migrate_nist_defaults() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-v")
+        .arg("--defaults")
+        .arg("nist")
+        .arg("--migrate")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("pbkdf legacy -> pbkdf2-sha512"))
+        .stderr(predicate::str::contains(
+            "cipher aes-256-siv -> aes-256-gcm",
+        ));
+    let migrated = fs::read_to_string(&ept.path).unwrap();
+    assert!(migrated.contains("cipher:aes-256-gcm$iv="));
+    assert!(migrated.contains("pbkdf:$pbkdf2-sha512$"));
+    decrypt_matches(&ept, "sample/simple.ept");
+}
+
+#[test]
+fn migrate_up_to_date() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007-gcm.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--migrate")
+        .arg("Agent_007")
+        .arg("--cipher")
+        .arg("aes-256-gcm")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Migrated").not());
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn migrate_no_downgrade() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007-gcm.ept");
+
+    // neither the cipher nor the key derivation is weak
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-v")
+        .arg("--migrate")
+        .arg("Agent_007")
+        .arg("--cipher")
+        .arg("aes-256-siv")
+        .arg("--pbkdf")
+        .arg("pbkdf2-sha256")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Migrated").not());
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn strict_policy_refuses_legacy() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--policy")
+        .arg("strict")
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Legacy key derivation is not permitted by policy",
+        ));
+}
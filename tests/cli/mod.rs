@@ -1,9 +1,35 @@
+mod agent;
+mod associated_data;
+mod backup;
+mod check;
 mod cipher;
+mod config;
+// the editors of these tests are shell commands
+#[cfg(unix)]
+mod edit;
 mod encrypt_decrypt;
 mod encrypt_store;
+mod envelope;
+mod filetype;
+mod git_filter;
+mod indentation;
 mod issue_15;
+mod jobs;
+mod key_sources;
+mod keyring;
+mod keystore;
+mod library;
+mod line_endings;
+mod merge_driver;
+mod migrate;
 mod misc;
 mod pbkdf;
 mod pipe;
 mod policy;
+mod pubkey;
+mod recursive;
+mod rekey;
+mod reuse;
 mod store_fetch;
+mod subcommands;
+mod textconv;
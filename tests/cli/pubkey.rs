@@ -0,0 +1,178 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+fn gen_keypair(path: &std::path::Path, args: &[&str]) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--gen-keypair")
+        .arg(path)
+        .args(args)
+        .assert()
+        .success();
+}
+
+#[test]
+fn encrypt_decrypt_recipient() {
+    let keydir = tempdir().unwrap();
+    let key = keydir.path().join("alice");
+    gen_keypair(&key, &[]);
+    let ept = Fixture::copy("sample/simple.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--recipient")
+        .arg(format!("Agent_007={}.pub", key.display()))
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let encrypted = fs::read_to_string(&ept.path).unwrap();
+    assert!(encrypted.contains("pk."));
+    assert!(encrypted.contains("x25519$epk="));
+    assert!(!encrypted.contains("pbkdf:"));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-i")
+        .arg(&key)
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn encrypt_multiple_recipients() {
+    let keydir = tempdir().unwrap();
+    let alice = keydir.path().join("alice");
+    let bob = keydir.path().join("bob");
+    gen_keypair(&alice, &[]);
+    gen_keypair(&bob, &["--pubkey-alg", "p256"]);
+    let ept = Fixture::copy("sample/simple.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--recipient")
+        .arg(format!(
+            "Agent_007={}.pub,Agent_007={}.pub",
+            alice.display(),
+            bob.display()
+        ))
+        .arg(&ept.path)
+        .assert()
+        .success();
+
+    // either private key can decrypt
+    for key in &[&alice, &bob] {
+        let out = Fixture::blank("out.ept");
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-d")
+            .arg("Agent_007")
+            .arg("-i")
+            .arg(key)
+            .arg(&ept.path)
+            .arg("-o")
+            .arg(&out.path)
+            .assert()
+            .success();
+        assert_eq!(
+            &fs::read_to_string(&out.path).unwrap(),
+            &fs::read_to_string(&ept.source).unwrap()
+        );
+    }
+}
+
+#[test]
+fn decrypt_wrong_identity() {
+    let keydir = tempdir().unwrap();
+    let alice = keydir.path().join("alice");
+    let mallory = keydir.path().join("mallory");
+    gen_keypair(&alice, &[]);
+    gen_keypair(&mallory, &[]);
+    let ept = Fixture::copy("sample/simple.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--recipient")
+        .arg(format!("Agent_007={}.pub", alice.display()))
+        .arg(&ept.path)
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-i")
+        .arg(&mallory)
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "No identity or password matches the recipients",
+        ));
+}
+
+#[test]
+fn nist_pubkey_alg() {
+    let keydir = tempdir().unwrap();
+    let key = keydir.path().join("alice");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--policy")
+        .arg("nist")
+        .arg("--gen-keypair")
+        .arg(&key)
+        .arg("--pubkey-alg")
+        .arg("x25519")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Public key algorithm is not permitted by policy",
+        ));
+
+    // the policy default is allowed
+    gen_keypair(&key, &["--policy", "nist"]);
+    let ept = Fixture::copy("sample/simple.ept");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--policy")
+        .arg("nist")
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--recipient")
+        .arg(format!("Agent_007={}.pub", key.display()))
+        .arg(&ept.path)
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--policy")
+        .arg("nist")
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-i")
+        .arg(&key)
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
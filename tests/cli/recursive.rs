@@ -0,0 +1,253 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+// a tree of copies of sample/simple.ept, and of other files
+fn tree(dir: &Path, files: &[(&str, &[u8])]) {
+    for (name, contents) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        if contents.is_empty() {
+            fs::copy("sample/simple.ept", &path).unwrap();
+        } else {
+            fs::write(&path, contents).unwrap();
+        }
+    }
+}
+
+fn stored(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .unwrap()
+        .contains("// <( STORED Agent_007 ")
+}
+
+#[test]
+fn ignore_files() {
+    let dir = tempdir().unwrap();
+    let casdir = tempdir().unwrap();
+    tree(
+        dir.path(),
+        &[
+            ("a.ept", b""),
+            ("sub/b.ept", b""),
+            ("sub/kept.log", b""),
+            ("plain.txt", b"nothing to see here\n"),
+            (
+                "binary.dat",
+                b"// <( BEGIN Agent_007 )>\n\0\n// <( END Agent_007 )>\n",
+            ),
+            ("x.log", b""),
+            ("ignored/c.ept", b""),
+            ("secret/d.ept", b""),
+            (".gitignore", b"# build output\n*.log\nignored/\n"),
+            ("sub/.enprotignore", b"!kept.log\n"),
+            (".enprotignore", b"/secret\n"),
+        ],
+    );
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-R")
+        .arg(dir.path())
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .assert()
+        .success()
+        .stdout(format!(
+            "{0}/a.ept\n{0}/sub/b.ept\n{0}/sub/kept.log\n3 of 4 file(s) changed\n",
+            dir.path().display()
+        ));
+    assert!(stored(&dir.path().join("a.ept")));
+    assert!(stored(&dir.path().join("sub/b.ept")));
+    assert!(stored(&dir.path().join("sub/kept.log")));
+    assert!(!stored(&dir.path().join("x.log")));
+    assert!(!stored(&dir.path().join("ignored/c.ept")));
+    assert!(!stored(&dir.path().join("secret/d.ept")));
+    assert!(fs::read(dir.path().join("binary.dat"))
+        .unwrap()
+        .contains(&0));
+}
+
+#[test]
+fn ancestor_ignore_files() {
+    let dir = tempdir().unwrap();
+    let casdir = tempdir().unwrap();
+    tree(
+        dir.path(),
+        &[
+            ("repo/docs/a.ept", b""),
+            ("repo/docs/b.tmp.ept", b""),
+            ("repo/docs/draft.ept", b""),
+            ("repo/docs/x.log", b""),
+            ("repo/docs/private/c.ept", b""),
+            ("repo/docs/sub/draft.ept", b""),
+            ("repo/.git/info/exclude", b"*.tmp.ept\n"),
+            ("repo/.gitignore", b"*.log\n/docs/private/\n"),
+            ("repo/docs/.enprotignore", b"/draft.ept\n"),
+            // above the repository, so it does not count
+            (".gitignore", b"*.ept\n"),
+        ],
+    );
+    let docs = dir.path().join("repo/docs");
+
+    // the rules of the repository apply below its root too
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-R")
+        .arg(&docs)
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .assert()
+        .success()
+        .stdout(format!(
+            "{0}/a.ept\n{0}/sub/draft.ept\n2 of 2 file(s) changed\n",
+            docs.display()
+        ));
+    assert!(!stored(&docs.join("b.tmp.ept")));
+    assert!(!stored(&docs.join("draft.ept")));
+    assert!(!stored(&docs.join("private/c.ept")));
+}
+
+#[test]
+fn include_exclude() {
+    let dir = tempdir().unwrap();
+    let casdir = tempdir().unwrap();
+    tree(
+        dir.path(),
+        &[
+            ("a.ept", b""),
+            ("a.txt", b""),
+            ("sub/b.ept", b""),
+            ("other/c.ept", b""),
+        ],
+    );
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("store")
+        .arg("Agent_007")
+        .arg("-R")
+        .arg(dir.path())
+        .arg("--include")
+        .arg("*.ept")
+        .arg("--exclude")
+        .arg("/other")
+        .arg("-c")
+        .arg(casdir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("2 of 2 file(s) changed\n"));
+    assert!(stored(&dir.path().join("a.ept")));
+    assert!(stored(&dir.path().join("sub/b.ept")));
+    assert!(!stored(&dir.path().join("a.txt")));
+    assert!(!stored(&dir.path().join("other/c.ept")));
+}
+
+#[test]
+fn configured_globs() {
+    let dir = tempdir().unwrap();
+    tree(
+        dir.path(),
+        &[
+            ("a.ept", b""),
+            ("a.txt", b""),
+            ("sub/b.ept", b""),
+            (
+                ".enprot.toml",
+                b"include = [\"*.ept\"]\nexclude = [\"sub/*\"]\n",
+            ),
+        ],
+    );
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("list")
+        .arg("-R")
+        .arg(dir.path())
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}/a.ept:\n  BEGIN Agent_007\n",
+            dir.path().display()
+        ));
+}
+
+#[test]
+fn skips_casdir() {
+    // a CAS directory inside the tree is skipped
+    let dir = tempdir().unwrap();
+    tree(dir.path(), &[("a.ept", b""), ("cas/README", b"objects\n")]);
+    for &changed in &["1 of 1", "0 of 1"] {
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-R")
+            .arg(dir.path())
+            .arg("-c")
+            .arg(dir.path().join("cas"))
+            .arg("-s")
+            .arg("Agent_007")
+            .assert()
+            .success()
+            .stdout(predicate::str::ends_with(format!(
+                "{} file(s) changed\n",
+                changed
+            )));
+    }
+
+    // and so are the objects of the tree as CAS directory
+    let dir = tempdir().unwrap();
+    tree(dir.path(), &[("a.ept", b""), ("cas/README", b"objects\n")]);
+    for &changed in &["1 of 2", "0 of 2"] {
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-R")
+            .arg(dir.path())
+            .arg("-c")
+            .arg(dir.path())
+            .arg("-s")
+            .arg("Agent_007")
+            .assert()
+            .success()
+            .stdout(predicate::str::ends_with(format!(
+                "{} file(s) changed\n",
+                changed
+            )));
+    }
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+}
+
+#[test]
+fn with_input_files() {
+    let dir = tempdir().unwrap();
+    let casdir = tempdir().unwrap();
+    tree(dir.path(), &[("a.ept", b"")]);
+    let ept = Fixture::copy("sample/simple.ept");
+
+    // the input files are processed as usual, but not counted
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-R")
+        .arg(dir.path())
+        .arg(&ept.path)
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("Agent_007")
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}/a.ept\n1 of 1 file(s) changed\n",
+            dir.path().display()
+        ));
+    assert!(stored(&ept.path));
+    assert!(stored(&dir.path().join("a.ept")));
+}
@@ -0,0 +1,171 @@
+use assert_cmd::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+#[test]
+fn rekey_password() {
+    let ept = Fixture::copy("test-data/simple-encrypt-agent007-gcm.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--rekey")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--new-key")
+        .arg("Agent_007=newpassword")
+        .arg("--pbkdf")
+        .arg("argon2")
+        .arg("--pbkdf-params")
+        .arg("t=1,p=1,m=16")
+        .arg("--cipher")
+        .arg("aes-256-gcm-siv")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let rekeyed = fs::read_to_string(&ept.path).unwrap();
+    assert!(rekeyed.contains("cipher:aes-256-gcm-siv$iv="));
+
+    // the old password no longer works
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .arg("-o")
+        .arg("-")
+        .assert()
+        .failure();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=newpassword")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string("sample/simple.ept").unwrap()
+    );
+}
+
+#[test]
+fn rekey_stored() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("--encrypt-store")
+        .arg("Agent_007")
+        .arg("--pbkdf")
+        .arg("legacy")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("--rekey")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--new-key")
+        .arg("Agent_007=newpassword")
+        .arg("--pbkdf")
+        .arg("argon2")
+        .arg("--pbkdf-params")
+        .arg("t=1,p=1,m=16")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let rekeyed = fs::read_to_string(&ept.path).unwrap();
+    assert!(rekeyed.contains("pbkdf:$argon2$"));
+    assert!(!rekeyed.contains("DATA"));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=newpassword")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
+
+#[test]
+fn rekey_password_recipient() {
+    let ept = Fixture::copy("sample/simple.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("--password-recipient")
+        .arg("Agent_007=alice")
+        .arg("-k")
+        .arg("Agent_007/alice=password")
+        .arg("--pbkdf")
+        .arg("argon2")
+        .arg("--pbkdf-params")
+        .arg("t=1,p=1,m=16")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    let data = fs::read_to_string(&ept.path)
+        .unwrap()
+        .lines()
+        .filter(|line| line.contains("DATA "))
+        .collect::<Vec<&str>>()
+        .join("\n");
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("--rekey")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007/alice=password")
+        .arg("--new-key")
+        .arg("Agent_007/alice=newpassword")
+        .arg("--pbkdf")
+        .arg("argon2")
+        .arg("--pbkdf-params")
+        .arg("t=1,p=1,m=16")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    // only the wrapped key changes
+    assert!(fs::read_to_string(&ept.path).unwrap().contains(&data));
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007/alice=newpassword")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.path).unwrap(),
+        &fs::read_to_string(&ept.source).unwrap()
+    );
+}
@@ -0,0 +1,206 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use Fixture;
+
+fn encrypt(path: &Path, extra: &[&str]) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("GEHEIM,Agent_007")
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .args(extra)
+        .arg(path)
+        .assert()
+        .success();
+}
+
+fn decrypt(path: &Path) {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("GEHEIM,Agent_007")
+        .arg("-k")
+        .arg("GEHEIM=secret")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg(path)
+        .assert()
+        .success();
+}
+
+#[test]
+fn unchanged() {
+    let ept = Fixture::copy("sample/test.ept");
+    let previous = Fixture::blank("previous.ept");
+
+    encrypt(&ept.path, &[]);
+    fs::copy(&ept.path, &previous.path).unwrap();
+    decrypt(&ept.path);
+    encrypt(
+        &ept.path,
+        &["--reuse-from", previous.path.to_str().unwrap()],
+    );
+    assert_eq!(
+        fs::read_to_string(&previous.path).unwrap(),
+        fs::read_to_string(&ept.path).unwrap()
+    );
+}
+
+#[test]
+fn changed() {
+    let ept = Fixture::copy("sample/test.ept");
+    let previous = Fixture::blank("previous.ept");
+
+    encrypt(&ept.path, &[]);
+    fs::copy(&ept.path, &previous.path).unwrap();
+    decrypt(&ept.path);
+    let content = fs::read_to_string(&ept.path).unwrap();
+    fs::write(&ept.path, content.replace("line 3", "line 4")).unwrap();
+    encrypt(
+        &ept.path,
+        &["--reuse-from", previous.path.to_str().unwrap()],
+    );
+
+    // GEHEIM, with Agent_007 inside, is kept but the last segment is not
+    let before = fs::read_to_string(&previous.path).unwrap();
+    let after = fs::read_to_string(&ept.path).unwrap();
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    assert_eq!(before.len(), after.len());
+    assert_eq!(before[..4], after[..4]);
+    assert_ne!(before[4..], after[4..]);
+}
+
+#[test]
+fn other_password() {
+    let ept = Fixture::copy("sample/test.ept");
+    let previous = Fixture::blank("previous.ept");
+
+    encrypt(&ept.path, &[]);
+    fs::copy(&ept.path, &previous.path).unwrap();
+    decrypt(&ept.path);
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("GEHEIM,Agent_007")
+        .arg("-k")
+        .arg("GEHEIM=other")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--reuse-from")
+        .arg(&previous.path)
+        .arg(&ept.path)
+        .assert()
+        .success();
+    decrypt(&previous.path);
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-d")
+        .arg("GEHEIM")
+        .arg("-k")
+        .arg("GEHEIM=other")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&ept.path)
+        .unwrap()
+        .contains("Secret line 1"));
+}
+
+#[test]
+fn other_params() {
+    let ept = Fixture::copy("sample/test.ept");
+    let previous = Fixture::blank("previous.ept");
+    let reuse = ["--reuse-from", previous.path.to_str().unwrap()];
+
+    encrypt(&ept.path, &["--pbkdf-params", "m=8192,p=1,t=1"]);
+    fs::copy(&ept.path, &previous.path).unwrap();
+
+    // the same explicit params keep the ciphertext
+    decrypt(&ept.path);
+    encrypt(
+        &ept.path,
+        &[&reuse[..], &["--pbkdf-params", "m=8192,p=1,t=1"]].concat(),
+    );
+    assert_eq!(
+        fs::read_to_string(&previous.path).unwrap(),
+        fs::read_to_string(&ept.path).unwrap()
+    );
+
+    // other ones encrypt again
+    decrypt(&ept.path);
+    encrypt(
+        &ept.path,
+        &[&reuse[..], &["--pbkdf-params", "m=8192,p=1,t=2"]].concat(),
+    );
+    let after = fs::read_to_string(&ept.path).unwrap();
+    assert!(after.contains("t=2"));
+    assert!(!after.contains("t=1"));
+}
+
+#[test]
+fn deterministic() {
+    let first = Fixture::copy("sample/test.ept");
+    let second = Fixture::copy("sample/test.ept");
+    let params = ["--deterministic", "--pbkdf-params", "m=8192,p=1,t=1"];
+
+    encrypt(&first.path, &params);
+    encrypt(&second.path, &params);
+    assert_eq!(
+        fs::read_to_string(&first.path).unwrap(),
+        fs::read_to_string(&second.path).unwrap()
+    );
+    decrypt(&first.path);
+    assert_eq!(
+        fs::read_to_string(&first.source).unwrap(),
+        fs::read_to_string(&first.path).unwrap()
+    );
+}
+
+#[test]
+fn deterministic_without_params() {
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--deterministic")
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "Deterministic encryption needs fixed PBKDF parameters",
+        ));
+}
+
+#[test]
+fn invalid_params() {
+    let ept = Fixture::copy("sample/test.ept");
+
+    for params in &["m", "t=x", "m=8192,", "=1"] {
+        Command::cargo_bin("enprot")
+            .unwrap()
+            .arg("-e")
+            .arg("Agent_007")
+            .arg("-k")
+            .arg("Agent_007=password")
+            .arg("--pbkdf-params")
+            .arg(params)
+            .arg(&ept.path)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(
+                "Must be of the form NAME=NUMBER[,NAME=NUMBER]",
+            ));
+    }
+}
@@ -0,0 +1,202 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+const GEHEIM_HASH: &str = "cea67c3ef34ff899793b557e9178c1b97bbcfe9722df2f6d35d2d0c91d2c1fe4";
+
+#[test]
+fn list() {
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("list")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}:\n  BEGIN GEHEIM\n    BEGIN Agent_007\n  BEGIN Agent_007\n",
+            ept.path.display()
+        ));
+    // nothing is written
+    assert_eq!(
+        &fs::read_to_string(&ept.source).unwrap(),
+        &fs::read_to_string(&ept.path).unwrap()
+    );
+}
+
+#[test]
+fn list_encrypted() {
+    let ept = Fixture::copy("test-data/test-encrypt-agent007.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("list")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "  BEGIN GEHEIM\n    ENCRYPTED Agent_007",
+        ));
+}
+
+#[test]
+fn status() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("status")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}:\n  Agent_007: 2 plaintext\n  GEHEIM: 1 plaintext\n",
+            ept.path.display()
+        ));
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("store")
+        .arg("GEHEIM")
+        .arg(&ept.path)
+        .arg("-c")
+        .arg(casdir.path())
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("status")
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout(format!(
+            "{}:\n  Agent_007: 1 plaintext\n  GEHEIM: 1 stored\n",
+            ept.path.display()
+        ));
+}
+
+#[test]
+fn verify() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("GEHEIM")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("verify")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with(": OK\n"));
+
+    // tampered with
+    fs::write(casdir.path().join(GEHEIM_HASH), "Bond, James Bond\n").unwrap();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("verify")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "Error verifying GEHEIM: CAS content hash mismatch",
+        ));
+
+    // gone
+    fs::remove_file(casdir.path().join(GEHEIM_HASH)).unwrap();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("verify")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg(&ept.path)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(format!(
+            "Error verifying GEHEIM: CAS object {} not found",
+            GEHEIM_HASH
+        )));
+}
+
+#[test]
+fn encrypt_decrypt() {
+    let ept = Fixture::copy("sample/simple.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("encrypt")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .arg("-k")
+        .arg("Agent_007=password")
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&ept.path)
+        .unwrap()
+        .contains("// <( ENCRYPTED Agent_007 "));
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("decrypt")
+        .arg("Agent_007")
+        .arg(&ept.path)
+        .arg("-k")
+        .arg("Agent_007=password")
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.source).unwrap(),
+        &fs::read_to_string(&ept.path).unwrap()
+    );
+}
+
+#[test]
+fn store_fetch() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+    let out = Fixture::blank("out.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("store")
+        .arg("Agent_007,GEHEIM")
+        .arg(&ept.path)
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-o")
+        .arg(&out.path)
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&out.path).unwrap(),
+        &fs::read_to_string("test-data/test-store-both.ept").unwrap()
+    );
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("fetch")
+        .arg("Agent_007,GEHEIM")
+        .arg(&out.path)
+        .arg("-c")
+        .arg(casdir.path())
+        .assert()
+        .success();
+    assert_eq!(
+        &fs::read_to_string(&ept.source).unwrap(),
+        &fs::read_to_string(&out.path).unwrap()
+    );
+}
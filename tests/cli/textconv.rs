@@ -0,0 +1,159 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+use std::process::Command;
+use tempfile::tempdir;
+
+use Fixture;
+
+#[test]
+fn placeholders() {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("textconv")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .env_remove("ENPROT_KEY_Agent_007")
+        .assert()
+        .success()
+        .stdout(
+            "hello, this is a test file\n\
+             // <( BEGIN GEHEIM )>\n\
+             Secret line 1\n\
+             Secret line 2\n\
+             [ENCRYPTED Agent_007, 27 bytes, legacy/aes-256-siv]\n\
+             // <( END GEHEIM )>\n\
+             [ENCRYPTED Agent_007, 36 bytes, legacy/aes-256-siv]\n",
+        );
+}
+
+#[test]
+fn decrypted() {
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("textconv")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+}
+
+#[test]
+fn fetched() {
+    let casdir = tempdir().unwrap();
+    let ept = Fixture::copy("sample/test.ept");
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-c")
+        .arg(casdir.path())
+        .arg("-s")
+        .arg("GEHEIM")
+        .arg(&ept.path)
+        .assert()
+        .success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("textconv")
+        .arg("-c")
+        .arg(casdir.path())
+        .arg(&ept.path)
+        .assert()
+        .success()
+        .stdout(fs::read_to_string("sample/test.ept").unwrap());
+    // the file itself is left alone
+    assert!(fs::read_to_string(&ept.path)
+        .unwrap()
+        .contains("// <( STORED GEHEIM "));
+}
+
+#[test]
+fn diff() {
+    let new = Fixture::blank("new.ept");
+    let content = fs::read_to_string("sample/test.ept").unwrap();
+    fs::write(
+        &new.path,
+        content.replace("Secret line 2", "Secret line two"),
+    )
+    .unwrap();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("-e")
+        .arg("Agent_007")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .arg("--pbkdf")
+        .arg("legacy")
+        .arg(&new.path)
+        .assert()
+        .success();
+
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("diff")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg(&new.path)
+        .arg("-k")
+        .arg("Agent_007=password")
+        .assert()
+        .failure()
+        .stdout(format!(
+            "--- test-data/test-encrypt-agent007.ept\n+++ {}\n@@ -1,7 +1,7 @@\n \
+             hello, this is a test file\n \
+             // <( BEGIN GEHEIM )>\n \
+             Secret line 1\n\
+             -Secret line 2\n\
+             +Secret line two\n \
+             // <( BEGIN Agent_007 )>\n \
+             James Bond\n \
+             // <( END Agent_007 )>\n",
+            new.path.display()
+        ));
+
+    // the same plaintext, but different ciphertext
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .arg("diff")
+        .arg("test-data/test-encrypt-agent007.ept")
+        .arg("test-data/test-encrypt-agent007-pbkdf2.ept")
+        .arg("-k")
+        .arg("Agent_007=password")
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn git_diff() {
+    let dir = tempdir().unwrap();
+    let git = || {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(dir.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("HOME", dir.path())
+            .env("ENPROT_KEY_Agent_007", "password");
+        cmd
+    };
+    git().arg("init").arg("-q").assert().success();
+    Command::cargo_bin("enprot")
+        .unwrap()
+        .current_dir(dir.path())
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("HOME", dir.path())
+        .arg("git-setup")
+        .assert()
+        .success();
+    let file = dir.path().join("test.ept");
+    fs::copy("sample/test.ept", &file).unwrap();
+    git().arg("add").arg("test.ept").assert().success();
+    let content = fs::read_to_string(&file).unwrap();
+    fs::write(&file, content.replace("James Bond", "Bond, James Bond")).unwrap();
+
+    git()
+        .arg("diff")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-James Bond\n+Bond, James Bond\n"))
+        .stdout(predicate::str::contains("DATA").not());
+}
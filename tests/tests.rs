@@ -2,6 +2,7 @@
 
 extern crate assert_cmd;
 extern crate cpu_time;
+extern crate enprot;
 extern crate predicates;
 extern crate tempfile;
 
@@ -41,13 +42,13 @@ impl Fixture {
     }
 }
 
-pub fn digest(alg: &str, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+pub fn digest(alg: &str, data: &[u8]) -> Result<Vec<u8>, enprot::Error> {
     let policy: Box<dyn enprot::crypto::CryptoPolicy> =
         Box::new(enprot::crypto::CryptoPolicyDefault {});
     enprot::crypto::digest(alg, data, &policy)
 }
 
-pub fn hexdigest(alg: &str, data: &[u8]) -> Result<String, &'static str> {
+pub fn hexdigest(alg: &str, data: &[u8]) -> Result<String, enprot::Error> {
     let policy: Box<dyn enprot::crypto::CryptoPolicy> =
         Box::new(enprot::crypto::CryptoPolicyDefault {});
     enprot::crypto::hexdigest(alg, data, &policy)